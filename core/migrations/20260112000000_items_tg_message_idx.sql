@@ -0,0 +1,2 @@
+-- Lookup by Telegram message coordinates (GET /api/v1/items/by-message)
+CREATE INDEX IF NOT EXISTS idx_items_tg_message ON items (tg_chat_id, tg_message_id);
@@ -0,0 +1,2 @@
+-- 按用户名（大小写不敏感）查找实体
+CREATE INDEX IF NOT EXISTS idx_entities_username_lower ON entities (lower(username));
@@ -0,0 +1,2 @@
+-- 视频预览拼图（多帧平铺成一张图），帧数与间隔记录在 meta.storyboard
+ALTER TABLE items ADD COLUMN IF NOT EXISTS storyboard_key TEXT;
@@ -0,0 +1,3 @@
+-- 收藏/置顶标记
+ALTER TABLE items ADD COLUMN IF NOT EXISTS is_pinned BOOLEAN NOT NULL DEFAULT FALSE;
+CREATE INDEX IF NOT EXISTS idx_items_pinned ON items (id DESC) WHERE is_pinned;
@@ -0,0 +1,2 @@
+-- 机器可读的失败分类（download / storage / vlm / clip / embedding / db / too_large / unsupported / internal）
+ALTER TABLE tasks ADD COLUMN IF NOT EXISTS error_code TEXT;
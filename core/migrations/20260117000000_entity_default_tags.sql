@@ -0,0 +1,7 @@
+-- 来源实体的默认标签：从该实体收录的 item 自动打上这些标签
+CREATE TABLE IF NOT EXISTS entity_default_tags (
+    entity_id BIGINT NOT NULL REFERENCES entities(id) ON DELETE CASCADE,
+    tag_id INTEGER NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ DEFAULT NOW(),
+    PRIMARY KEY (entity_id, tag_id)
+);
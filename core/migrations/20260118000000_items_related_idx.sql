@@ -0,0 +1,3 @@
+-- GET /api/v1/entities/:id/related：按来源实体取最近 item，再按相册关联
+CREATE INDEX IF NOT EXISTS idx_items_source_entity ON items ((COALESCE(tg_user_id, tg_chat_id)), created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_items_tg_group ON items (tg_group_id) WHERE tg_group_id IS NOT NULL;
@@ -0,0 +1,4 @@
+-- 公开/私有：开启 RESTRICT_PUBLIC_READS 后，未带 token 的读接口只返回 public
+ALTER TABLE items ADD COLUMN IF NOT EXISTS visibility VARCHAR(10) NOT NULL DEFAULT 'private'
+    CHECK (visibility IN ('private', 'public'));
+CREATE INDEX IF NOT EXISTS idx_items_public ON items (id DESC) WHERE visibility = 'public';
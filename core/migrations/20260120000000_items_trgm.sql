@@ -0,0 +1,3 @@
+-- 模糊匹配召回（拼写错误、部分词）：pg_trgm + GIN 三元组索引
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+CREATE INDEX IF NOT EXISTS idx_items_searchable_trgm ON items USING gin (searchable_text gin_trgm_ops);
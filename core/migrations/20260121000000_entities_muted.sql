@@ -0,0 +1,2 @@
+-- 静音的实体：内容照常收录，但不出现在默认时间线中
+ALTER TABLE entities ADD COLUMN IF NOT EXISTS muted BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,2 @@
+-- 图片 / 视频封面的 64 位感知哈希（dHash，按位存为 BIGINT），用汉明距离找近似重复
+ALTER TABLE items ADD COLUMN IF NOT EXISTS phash BIGINT;
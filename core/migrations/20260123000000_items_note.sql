@@ -0,0 +1,2 @@
+-- item 的个人备注（PATCH /api/v1/items/:id/note）
+ALTER TABLE items ADD COLUMN IF NOT EXISTS note TEXT;
@@ -0,0 +1,9 @@
+-- 加权全文索引：用户的说明文字（content_text）权重 A，其余可搜索文本（OCR、回复上下文、备注，即 searchable_text）权重 B。
+-- content_text 同时包含在 searchable_text 中，说明文字命中的词同时带 A、B 两种权重，排名高于只在 OCR 中出现的词
+ALTER TABLE items ADD COLUMN IF NOT EXISTS search_tsv tsvector GENERATED ALWAYS AS (
+    setweight(to_tsvector('simple', COALESCE(content_text, '')), 'A') ||
+    setweight(to_tsvector('simple', COALESCE(searchable_text, '')), 'B')
+) STORED;
+
+CREATE INDEX IF NOT EXISTS idx_items_search_tsv ON items USING gin (search_tsv);
+DROP INDEX IF EXISTS idx_items_search;
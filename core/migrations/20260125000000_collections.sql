@@ -0,0 +1,21 @@
+-- 手动合集：有序、命名、可指定封面的 item 分组（与 Telegram 组图、标签都无关）
+CREATE TABLE IF NOT EXISTS collections (
+    id BIGSERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    description TEXT,
+    -- 封面必须是合集成员；未设置时取排在最前的成员
+    cover_item_id BIGINT REFERENCES items(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ DEFAULT NOW(),
+    updated_at TIMESTAMPTZ DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS collection_items (
+    collection_id BIGINT NOT NULL REFERENCES collections(id) ON DELETE CASCADE,
+    item_id BIGINT NOT NULL REFERENCES items(id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    added_at TIMESTAMPTZ DEFAULT NOW(),
+    PRIMARY KEY (collection_id, item_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_collection_items_item ON collection_items (item_id);
+CREATE INDEX IF NOT EXISTS idx_collection_items_position ON collection_items (collection_id, position);
@@ -0,0 +1,6 @@
+-- 检测到的语言（ISO 639-3，与 meta.lang.code 相同）单独成列，按语言筛选时可以走索引
+ALTER TABLE items ADD COLUMN IF NOT EXISTS lang TEXT;
+
+UPDATE items SET lang = meta->'lang'->>'code' WHERE lang IS NULL AND meta ? 'lang';
+
+CREATE INDEX IF NOT EXISTS idx_items_lang ON items (lang) WHERE lang IS NOT NULL;
@@ -0,0 +1,4 @@
+-- meta.near_duplicate_of 与其它 id 一样以字符串返回，避免 JS 丢失 int64 精度
+UPDATE items
+SET meta = jsonb_set(meta, '{near_duplicate_of}', to_jsonb(meta->>'near_duplicate_of'))
+WHERE jsonb_typeof(meta->'near_duplicate_of') = 'number';
@@ -1,9 +1,15 @@
 use crate::state::AppState;
-use crate::db::{search_text_vec, search_visual_vec, search_fts, rrf_merge, fetch_items_by_ids};
-use s3::{Bucket, creds::Credentials, region::Region};
+use crate::media;
+use crate::http;
+use crate::db::{search_text_vec, search_visual_vec, search_fts, search_trgm, rrf_merge, rrf_merge_weighted, rrf_scores, rrf_scores_weighted, fetch_items_by_ids, find_near_duplicates, tag_items, untag_items, task_queue_depth, task_queue_full, FtsMatch, SearchFilter, SearchHit};
+use crate::storage;
+use crate::purge;
+use crate::embedder::normalized_centroid;
+use crate::search_cache::normalize_query;
+use s3::Bucket;
 use axum::{
     extract::{Path, Query, State},
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     response::IntoResponse,
     routing::get,
     Json, Router,
@@ -24,13 +30,43 @@ struct ListEntitiesParams {
 
 pub async fn run_server(state: AppState) {
     let app = Router::new()
-        .route("/api/v1/items", get(list_items))
+        .route("/api/v1/items", get(list_items).post(create_item_from_upload))
+        .route("/api/v1/uploads/presign", axum::routing::post(presign_upload))
+        .route("/api/v1/items/by-message", get(get_item_by_message))
+        .route("/api/v1/items/batch", axum::routing::post(batch_get_items))
+        .route("/api/v1/items/random", get(get_random_item))
+        .route("/api/v1/item-types", get(list_item_types))
         .route("/api/v1/items/:id", get(get_item).delete(delete_item))
         .route("/api/v1/items/:id/raw", get(get_raw_item))
+        .route("/api/v1/items/:id/duplicates", get(get_item_duplicates))
+        .route("/api/v1/items/:id/tags", axum::routing::put(set_item_tags))
+        .route("/api/v1/items/:id/visibility", axum::routing::patch(set_item_visibility))
+        .route("/api/v1/items/:id/note", axum::routing::patch(set_item_note))
+        .route("/api/v1/items/:id/pin", axum::routing::post(pin_item))
+        .route("/api/v1/items/:id/unpin", axum::routing::post(unpin_item))
         .route("/api/v1/search", get(search_items))
+        .route("/api/v1/search/explain", get(explain_search))
         .route("/api/v1/entities", get(list_entities))
+        .route("/api/v1/entities/by-username/:username", get(get_entity_by_username))
+        .route("/api/v1/entities/:id", axum::routing::patch(update_entity))
+        .route("/api/v1/entities/:id/avatar", axum::routing::post(upload_entity_avatar))
+        .route("/api/v1/entities/:id/related", get(get_related_entities))
+        .route("/api/v1/entities/:id/tags", get(get_entity_tag_stats))
+        .route("/api/v1/entities/:id/timeline", get(get_entity_timeline))
+        .route("/api/v1/entities/:id/default-tags", get(get_entity_default_tags).post(set_entity_default_tags))
         .route("/api/v1/tags", get(list_tags).post(create_tag))
         .route("/api/v1/tags/:id", axum::routing::patch(update_tag).delete(delete_tag))
+        .route("/api/v1/tags/:id/apply", axum::routing::post(apply_tag_to_items))
+        .route("/api/v1/tags/:id/remove", axum::routing::post(remove_tag_from_items))
+        .route("/api/v1/collections", get(list_collections).post(create_collection))
+        .route("/api/v1/collections/:id", axum::routing::patch(update_collection).delete(delete_collection))
+        .route("/api/v1/collections/:id/items", get(list_collection_items).post(add_collection_items))
+        .route("/api/v1/collections/:id/items/order", axum::routing::put(reorder_collection_items))
+        .route("/api/v1/collections/:id/items/:item_id", axum::routing::delete(remove_collection_item))
+        .route("/api/v1/pending", get(list_pending_tasks))
+        .route("/api/v1/maintenance/reindex", axum::routing::post(reindex_items))
+        .route("/api/v1/admin/gc", axum::routing::post(gc_orphaned_objects))
+        .route("/api/v1/admin/incomplete", get(list_incomplete_items))
         .with_state(state);
 
     let addr = std::net::SocketAddr::from(([0, 0, 0, 0], 8080));
@@ -39,13 +75,33 @@ pub async fn run_server(state: AppState) {
     axum::serve(listener, app).await.unwrap();
 }
 
+/// 实体列表的默认每页条数（头像墙一屏放不下太多）
+const ENTITIES_PAGE_SIZE: i64 = 10;
+
+/// 统一的分页大小处理：未指定时取接口默认值，并限制在 [1, max]
+fn clamp_limit(requested: Option<i64>, default: i64, max: i64) -> i64 {
+    requested.unwrap_or(default).clamp(1, max.max(1))
+}
+
+#[derive(Deserialize)]
+struct ListTagsParams {
+    limit: Option<i64>, // 不传时返回全部标签（标签选择器需要完整列表）
+    with_counts: Option<bool>, // 附带每个标签的 item 数（默认 true，需要扫描 items 聚合）
+}
+
 #[derive(Deserialize)]
 struct ListParams {
-    cursor: Option<i64>,  // 游标：上一页最后一条的 id
+    cursor: Option<String>,  // 游标：timeline 为上一页最后一条的 id；随机模式为 "<seed>:<key>"
+    seed: Option<String>,    // 随机模式的洗牌种子，带上后可按游标翻页且不重复
     limit: Option<i64>,
     mode: Option<String>, // "timeline" (默认) 或 "random"
-    entity_id: Option<i64>,
+    // entity_id 可以重复出现（多个来源合并成一条时间线），serde_urlencoded 不支持重复键，
+    // 由 list_items 从原始参数列表中单独解析
     tag_id: Option<i32>,
+    lang: Option<String>, // 语言过滤（items.lang，ISO 639-3，例如 "eng"、"jpn"）
+    pinned: Option<bool>, // 只看收藏（true）或排除收藏（false）
+    collection_id: Option<i64>, // 只看某个手动合集的成员
+    source_kind: Option<String>, // 来源类别（meta.source_kind）：forward_channel、forward_user、forward_hidden、self
 }
 
 #[derive(Deserialize)]
@@ -55,9 +111,128 @@ struct CreateTagRequest {
     label: Option<String>,
 }
 
+#[derive(Deserialize)]
+struct CreateCollectionRequest {
+    name: String,
+    description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct UpdateCollectionRequest {
+    name: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_present")]
+    description: Option<Option<String>>,
+    // 显式传 null 恢复默认封面（排在最前的成员）
+    #[serde(default, deserialize_with = "deserialize_present_id")]
+    cover_item_id: Option<Option<i64>>,
+}
+
+#[derive(Deserialize)]
+struct AddCollectionItemsRequest {
+    #[serde(deserialize_with = "deserialize_ids")]
+    item_ids: Vec<i64>,
+}
+
+#[derive(Deserialize)]
+struct ReorderCollectionRequest {
+    #[serde(deserialize_with = "deserialize_ids")]
+    item_ids: Vec<i64>, // 排到最前面的成员（按此顺序），未列出的成员保持原有相对顺序排在后面
+}
+
+#[derive(Deserialize)]
+struct CollectionItemsParams {
+    cursor: Option<i32>, // 上一页最后一条的 position
+    limit: Option<i64>,
+}
+
 #[derive(Deserialize)]
 struct UpdateTagRequest {
-    label: Option<String>,
+    // 区分「未提供」与「显式置空」：只改图标时不应清掉 label
+    #[serde(default, deserialize_with = "deserialize_present")]
+    label: Option<Option<String>>,
+    icon_type: Option<String>,  // "emoji" | "tmoji"
+    icon_value: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BulkTagRequest {
+    #[serde(deserialize_with = "deserialize_ids")]
+    item_ids: Vec<i64>,
+}
+
+/// 字段出现即为 Some（值可以是 null），缺省为 None（配合 `#[serde(default)]`）
+fn deserialize_present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// 请求体里的 i64 id：响应中以字符串返回（超出 JS 安全整数范围），客户端原样回传时也要能解析
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum IdRepr {
+    Num(i64),
+    Str(String),
+}
+
+impl IdRepr {
+    fn into_id<E: serde::de::Error>(self) -> Result<i64, E> {
+        match self {
+            IdRepr::Num(id) => Ok(id),
+            IdRepr::Str(s) => s.trim().parse().map_err(|_| E::custom(format!("invalid id: {}", s))),
+        }
+    }
+}
+
+/// id 列表，元素可以是数字或数字字符串
+fn deserialize_ids<'de, D>(deserializer: D) -> Result<Vec<i64>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Vec::<IdRepr>::deserialize(deserializer)?.into_iter().map(IdRepr::into_id).collect()
+}
+
+/// 同 deserialize_present，值为数字或数字字符串形式的 id
+fn deserialize_present_id<'de, D>(deserializer: D) -> Result<Option<Option<i64>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Option::<IdRepr>::deserialize(deserializer)?.map(IdRepr::into_id).transpose().map(Some)
+}
+
+#[derive(Deserialize)]
+struct ByMessageParams {
+    chat_id: i64,
+    message_id: i64,
+}
+
+#[derive(Deserialize)]
+struct SetItemTagsRequest {
+    tags: Vec<i32>,
+}
+
+/// 校验管理/诊断接口的 API token；未配置 API_TOKEN 时这些接口不对外开放
+fn require_api_token(state: &AppState, headers: &HeaderMap) -> Result<(), StatusCode> {
+    let Some(expected) = state.config.api_token.as_deref() else {
+        return Err(StatusCode::FORBIDDEN);
+    };
+    let provided = headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|v| v.trim());
+    if provided == Some(expected) {
+        Ok(())
+    } else {
+        Err(StatusCode::UNAUTHORIZED)
+    }
+}
+
+/// 开启 RESTRICT_PUBLIC_READS 时，没有带有效 token 的读请求只能看到 public item
+fn public_only(state: &AppState, headers: &HeaderMap) -> bool {
+    state.config.restrict_public_reads && require_api_token(state, headers).is_err()
 }
 
 fn resolve_proxy_url(state: &AppState, raw: Option<String>) -> impl std::future::Future<Output = Option<String>> + '_ {
@@ -65,6 +240,12 @@ fn resolve_proxy_url(state: &AppState, raw: Option<String>) -> impl std::future:
         let Some(url) = raw else { return None; };
         if url.starts_with("PROXY:") {
             let key = &url[6..];
+            if let Some(cdn) = state.config.cdn_base_url.as_deref() {
+                // 公开前缀的对象上传时已设为 public-read，可以不带签名经 CDN 访问；item 内容仍然预签名
+                if state.config.is_public_key(key) {
+                    return Some(format!("{}/{}", cdn, key));
+                }
+            }
             state.s3_signing_client.presign_get(key, 3600, None).await.ok()
         } else {
             Some(url)
@@ -115,137 +296,681 @@ async fn fetch_tags_map(state: &AppState, tag_ids: &[i32]) -> HashMap<i32, serde
     map
 }
 
-async fn list_entities(
+/// 批量获取实体的精简信息（id/name/username/avatar_url），头像按需预签名
+async fn fetch_entities_map(state: &AppState, entity_ids: &[i64]) -> HashMap<i64, serde_json::Value> {
+    if entity_ids.is_empty() {
+        return HashMap::new();
+    }
+
+    let rows = sqlx::query(
+        r#"
+        SELECT id, name, username, avatar_url
+        FROM entities
+        WHERE id = ANY($1)
+        "#,
+    )
+    .bind(entity_ids)
+    .fetch_all(&state.db)
+    .await
+    .unwrap_or_default();
+
+    let mut map = HashMap::new();
+    for row in rows {
+        let id: i64 = row.get("id");
+        let name: String = row.get("name");
+        let username: Option<String> = row.try_get("username").ok();
+        let avatar_url_raw: Option<String> = row.try_get("avatar_url").ok();
+
+        let avatar_url = resolve_proxy_url(state, avatar_url_raw).await;
+        map.insert(
+            id,
+            json!({
+                "id": id.to_string(),
+                "name": name,
+                "username": username,
+                "avatar_url": avatar_url,
+            }),
+        );
+    }
+
+    map
+}
+
+/// 按 @username 查找实体（大小写不敏感），便于前端把 `@channelname` 转换成 entity_id 过滤
+async fn get_entity_by_username(
     State(state): State<AppState>,
-    Query(params): Query<ListEntitiesParams>,
+    Path(username): Path<String>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    let limit = params.limit.unwrap_or(10).clamp(1, 100);
+    let username = username.trim().trim_start_matches('@');
+    if username.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
 
-    let (cursor_ts, cursor_id): (Option<chrono::DateTime<chrono::Utc>>, Option<i64>) =
-        match params.cursor.as_deref() {
-            None => (None, None),
-            Some(raw) => {
-                let mut parts = raw.splitn(2, '|');
-                let ts_str = parts.next();
-                let id_str = parts.next();
-                match (ts_str, id_str) {
-                    (Some(ts), Some(id)) => {
-                        let parsed_ts = chrono::DateTime::parse_from_rfc3339(ts)
-                            .map(|dt| dt.with_timezone(&chrono::Utc))
-                            .ok();
-                        let parsed_id = id.parse::<i64>().ok();
-                        (parsed_ts, parsed_id)
-                    }
-                    _ => (None, None),
-                }
-            }
-        };
+    // 用户名可能被转手，同名时取最近更新的那个
+    let row = sqlx::query(
+        r#"
+        SELECT id, name, username, type, avatar_url, updated_at, muted
+        FROM entities
+        WHERE lower(username) = lower($1)
+        ORDER BY updated_at DESC NULLS LAST
+        LIMIT 1
+        "#,
+    )
+    .bind(username)
+    .fetch_optional(&state.db)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to fetch entity by username {}: {}", username, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?
+    .ok_or(StatusCode::NOT_FOUND)?;
 
-    let total: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM entities")
-        .fetch_one(&state.db)
+    let id: i64 = row.get("id");
+    let entity_type: String = row.get("type");
+    let updated_at: Option<chrono::DateTime<chrono::Utc>> = row.try_get("updated_at").ok();
+    let avatar_url = resolve_proxy_url(&state, row.try_get("avatar_url").ok().flatten()).await;
+
+    Ok(Json(json!({
+        "id": id.to_string(),
+        "name": row.get::<String, _>("name"),
+        "username": row.get::<Option<String>, _>("username"),
+        "type": entity_type,
+        "avatar_url": avatar_url,
+        "updated_at": updated_at,
+        "muted": row.try_get::<bool, _>("muted").unwrap_or(false),
+    })))
+}
+
+#[derive(Deserialize)]
+struct UpdateEntityRequest {
+    muted: Option<bool>,
+}
+
+/// 修改实体设置（目前只有 muted：静音的实体不出现在默认时间线，按 entity_id 过滤时仍可见）
+async fn update_entity(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+    Json(req): Json<UpdateEntityRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    require_api_token(&state, &headers)?;
+    let Some(muted) = req.muted else {
+        return Err(StatusCode::BAD_REQUEST);
+    };
+
+    let result = sqlx::query("UPDATE entities SET muted = $1 WHERE id = $2")
+        .bind(muted)
+        .bind(id)
+        .execute(&state.db)
         .await
         .map_err(|e| {
-            tracing::error!("Failed to count entities: {}", e);
+            tracing::error!("Failed to set muted={} for entity {}: {}", muted, id, e);
             StatusCode::INTERNAL_SERVER_ERROR
         })?;
 
-    let rows = if let (Some(ts), Some(id)) = (cursor_ts, cursor_id) {
-        sqlx::query(
-            r#"
-            SELECT id, name, username, type, avatar_url, updated_at
-            FROM entities
-            WHERE updated_at < $1 OR (updated_at = $1 AND id < $2)
-            ORDER BY updated_at DESC, id DESC
-            LIMIT $3
-            "#,
-        )
-        .bind(ts)
+    if result.rows_affected() == 0 {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    Ok(Json(json!({ "id": id.to_string(), "muted": muted })))
+}
+
+/// 与 axum 默认的请求体上限一致
+const AVATAR_MAX_BYTES: usize = 2 * 1024 * 1024;
+
+/// 手动设置实体头像（bot 取不到头像的隐藏用户、私聊等）：请求体为图片原始字节，
+/// 存到 avatars/ 下并把 avatar_url 设为 PROXY: key。bot 只在 avatar_url 为空时抓取，不会覆盖手动头像
+async fn upload_entity_avatar(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+    body: axum::body::Bytes,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    require_api_token(&state, &headers)?;
+    if body.len() > AVATAR_MAX_BYTES {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    // 按文件头判断格式，不信任 Content-Type
+    let Some((mime, ext)) = media::sniff_image(&body) else {
+        return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    };
+
+    let old_avatar_url: Option<String> = sqlx::query_scalar::<_, Option<String>>("SELECT avatar_url FROM entities WHERE id = $1")
         .bind(id)
-        .bind(limit)
-        .fetch_all(&state.db)
+        .fetch_optional(&state.db)
         .await
-    } else {
-        sqlx::query(
-            r#"
-            SELECT id, name, username, type, avatar_url, updated_at
-            FROM entities
-            ORDER BY updated_at DESC, id DESC
-            LIMIT $1
-            "#,
-        )
-        .bind(limit)
-        .fetch_all(&state.db)
+        .map_err(|e| {
+            tracing::error!("Failed to load entity {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    // 每次换新 key，避免浏览器 / CDN 缓存住旧头像
+    let key = format!("avatars/{}-{}.{}", id, uuid::Uuid::new_v4(), ext);
+    let bucket = storage::with_object_acl(&internal_bucket(&state), &state.config, &key);
+    bucket.put_object_with_content_type(&key, &body, mime).await.map_err(|e| {
+        tracing::error!("Failed to upload avatar for entity {}: {}", id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let avatar_url = format!("PROXY:{}", key);
+    sqlx::query("UPDATE entities SET avatar_url = $1, updated_at = NOW() WHERE id = $2")
+        .bind(&avatar_url)
+        .bind(id)
+        .execute(&state.db)
         .await
+        .map_err(|e| {
+            tracing::error!("Failed to set avatar for entity {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    // 之前手动上传的头像不再被引用，删掉；bot 抓取的 avatars/{id}.{ext} 保留（同 key 会被 bot 复用）
+    if let Some(old_key) = old_avatar_url
+        .as_deref()
+        .and_then(|url| url.strip_prefix("PROXY:"))
+        .filter(|k| k.starts_with(&format!("avatars/{}-", id)))
+    {
+        // ACL 头只用于写入，删除走不带该头的 bucket
+        let _ = internal_bucket(&state).delete_object(old_key).await
+            .map_err(|e| tracing::warn!("Failed to delete old avatar {}: {}", old_key, e));
     }
+
+    let avatar_url = resolve_proxy_url(&state, Some(avatar_url)).await;
+    Ok(Json(json!({ "id": id.to_string(), "avatar_url": avatar_url })))
+}
+
+const RELATED_ENTITIES_DEFAULT_LIMIT: i64 = 10;
+/// 只取该实体最近的这么多条 item 参与共现统计，避免大实体拖慢查询
+const RELATED_SOURCE_ITEMS: i64 = 500;
+const RELATED_DEFAULT_WINDOW_SECS: i64 = 300;
+const RELATED_MAX_WINDOW_SECS: i64 = 86400;
+
+#[derive(Deserialize)]
+struct RelatedEntitiesParams {
+    limit: Option<i64>,
+    window_secs: Option<i64>, // 收藏时间相差在该秒数内视为「一起收藏」
+}
+
+/// 与该实体经常一起收藏的其他实体：同一相册（tg_group_id）或收藏时间相近的 item 计为一次共现，
+/// 按共现的源 item 数降序返回
+async fn get_related_entities(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+    Query(params): Query<RelatedEntitiesParams>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let limit = clamp_limit(params.limit, RELATED_ENTITIES_DEFAULT_LIMIT, state.config.max_page_size);
+    let window_secs = params
+        .window_secs
+        .unwrap_or(RELATED_DEFAULT_WINDOW_SECS)
+        .clamp(0, RELATED_MAX_WINDOW_SECS);
+
+    // 来源实体与 item_source_entity_id 保持一致：COALESCE(tg_user_id, tg_chat_id)。
+    // 相册与时间窗口分两路 JOIN 再合并，各自能走 tg_group_id / created_at 索引。
+    // public_only 时两端 item 都必须公开，否则只出现在隐藏 item 上的实体会被暴露
+    let rows = sqlx::query(
+        r#"
+        WITH src AS (
+            SELECT id, tg_group_id, created_at
+            FROM items
+            WHERE COALESCE(tg_user_id, tg_chat_id) = $1
+              AND (NOT $5 OR visibility = 'public')
+            ORDER BY created_at DESC
+            LIMIT $4
+        ),
+        pairs AS (
+            SELECT s.id AS src_id, COALESCE(o.tg_user_id, o.tg_chat_id) AS entity_id
+            FROM src s
+            JOIN items o ON o.tg_group_id = s.tg_group_id AND o.id <> s.id
+            WHERE s.tg_group_id IS NOT NULL
+              AND (NOT $5 OR o.visibility = 'public')
+            UNION ALL
+            SELECT s.id AS src_id, COALESCE(o.tg_user_id, o.tg_chat_id) AS entity_id
+            FROM src s
+            JOIN items o
+              ON o.created_at BETWEEN s.created_at - make_interval(secs => $2)
+                                  AND s.created_at + make_interval(secs => $2)
+             AND o.id <> s.id
+            WHERE NOT $5 OR o.visibility = 'public'
+        )
+        SELECT p.entity_id, COUNT(DISTINCT p.src_id) AS co_count
+        FROM pairs p
+        JOIN entities e ON e.id = p.entity_id
+        WHERE p.entity_id <> $1
+        GROUP BY p.entity_id
+        ORDER BY co_count DESC, p.entity_id DESC
+        LIMIT $3
+        "#,
+    )
+    .bind(id)
+    .bind(window_secs as f64)
+    .bind(limit)
+    .bind(RELATED_SOURCE_ITEMS)
+    .bind(public_only(&state, &headers))
+    .fetch_all(&state.db)
+    .await
     .map_err(|e| {
-        tracing::error!("Failed to fetch entities: {}", e);
+        tracing::error!("Failed to fetch related entities for {}: {}", id, e);
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
 
-    let mut entities = Vec::new();
-    let mut next_cursor: Option<String> = None;
-
-    for row in rows.iter() {
-        let id: i64 = row.get("id");
-        let name: String = row.get("name");
-        let username: Option<String> = row.get("username");
-        let entity_type: String = row.get("type");
-        let avatar_url: Option<String> = row.get("avatar_url");
-        let updated_at: Option<chrono::DateTime<chrono::Utc>> = row.try_get("updated_at").ok();
+    let counts: Vec<(i64, i64)> = rows
+        .iter()
+        .map(|row| (row.get("entity_id"), row.get("co_count")))
+        .collect();
+    let entity_ids: Vec<i64> = counts.iter().map(|(eid, _)| *eid).collect();
+    let entities_map = fetch_entities_map(&state, &entity_ids).await;
 
-        let avatar_final_url = if let Some(url) = avatar_url {
-            if url.starts_with("PROXY:") {
-                let key = &url[6..];
-                state
-                    .s3_signing_client
-                    .presign_get(key, 3600, None)
-                    .await
-                    .ok()
-            } else {
-                Some(url)
-            }
-        } else {
-            None
-        };
+    let related: Vec<serde_json::Value> = counts
+        .into_iter()
+        .filter_map(|(eid, count)| {
+            entities_map.get(&eid).map(|entity| json!({ "entity": entity, "count": count }))
+        })
+        .collect();
 
-        entities.push(json!({
-            "id": id.to_string(),
-            "name": name,
-            "username": username,
-            "type": entity_type,
-            "avatar_url": avatar_final_url,
-            "updated_at": updated_at,
-        }));
-    }
+    Ok(Json(json!({
+        "entity_id": id.to_string(),
+        "related": related,
+    })))
+}
 
-    if entities.len() == limit as usize {
-        if let Some(last) = rows.last() {
-            let last_id: i64 = last.get("id");
-            let last_ts: Option<chrono::DateTime<chrono::Utc>> = last.try_get("updated_at").ok();
-            if let Some(ts) = last_ts {
-                next_cursor = Some(format!("{}|{}", ts.to_rfc3339(), last_id));
-            }
-        }
-    }
+/// 该实体的 items 上各标签的使用次数，按次数降序（与 list_items 的 entity_id 过滤口径一致）
+async fn get_entity_tag_stats(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let rows = sqlx::query(
+        r#"
+        SELECT t.tag_id, COUNT(*) AS item_count
+        FROM items i, unnest(i.tags) AS t(tag_id)
+        WHERE (i.tg_chat_id = $1 OR i.tg_user_id = $1)
+          AND (NOT $2 OR i.visibility = 'public')
+        GROUP BY t.tag_id
+        ORDER BY item_count DESC, t.tag_id
+        "#,
+    )
+    .bind(id)
+    .bind(public_only(&state, &headers))
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to fetch tag stats for entity {}: {}", id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
 
+    let counts: Vec<(i32, i64)> = rows
+        .iter()
+        .map(|row| (row.get("tag_id"), row.get("item_count")))
+        .collect();
+    let tag_ids: Vec<i32> = counts.iter().map(|(tid, _)| *tid).collect();
+    let tags_map = fetch_tags_map(&state, &tag_ids).await;
+
+    // 已删除的标签可能还残留在 items.tags 中，跳过
+    let tags: Vec<serde_json::Value> = counts
+        .iter()
+        .filter_map(|(tid, count)| {
+            tags_map.get(tid).map(|tag| json!({ "tag": tag, "count": count }))
+        })
+        .collect();
     Ok(Json(json!({
-        "entities": entities,
-        "next_cursor": next_cursor,
-        "total": total
+        "entity_id": id.to_string(),
+        "tags": tags,
     })))
 }
 
-async fn list_items(
-    State(state): State<AppState>,
-    Query(params): Query<ListParams>,
-) -> Json<serde_json::Value> {
-    let limit = params.limit.unwrap_or(20).min(100);
-    let mode = params.mode.as_deref().unwrap_or("timeline");
-    let entity_id = params.entity_id;
-    let tag_id = params.tag_id;
+const TIMELINE_DEFAULT_DAYS: i64 = 30;
+const TIMELINE_MAX_DAYS: i64 = 366;
+
+#[derive(Deserialize)]
+struct TimelineParams {
+    from: Option<chrono::NaiveDate>, // YYYY-MM-DD，默认 to 之前 30 天
+    to: Option<chrono::NaiveDate>,   // YYYY-MM-DD（含），默认今天
+}
+
+/// 该实体每天收录的 item 数（按 UTC 日期分桶，区间内没有 item 的日期补 0），用于实体活跃度图表
+async fn get_entity_timeline(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+    Query(params): Query<TimelineParams>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let to = params.to.unwrap_or_else(|| chrono::Utc::now().date_naive());
+    let from = params.from.unwrap_or(to - chrono::Duration::days(TIMELINE_DEFAULT_DAYS - 1));
+    let span = (to - from).num_days();
+    if !(0..TIMELINE_MAX_DAYS).contains(&span) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let rows = sqlx::query(
+        r#"
+        SELECT d::date AS day, COALESCE(c.item_count, 0) AS item_count
+        FROM generate_series($1::date, $2::date, interval '1 day') AS d
+        LEFT JOIN (
+            SELECT (created_at AT TIME ZONE 'UTC')::date AS day, COUNT(*) AS item_count
+            FROM items
+            WHERE (tg_chat_id = $3 OR tg_user_id = $3)
+              AND (NOT $4 OR visibility = 'public')
+              AND created_at >= $1::date::timestamp AT TIME ZONE 'UTC'
+              AND created_at < ($2::date + 1)::timestamp AT TIME ZONE 'UTC'
+            GROUP BY 1
+        ) c ON c.day = d::date
+        ORDER BY d
+        "#,
+    )
+    .bind(from)
+    .bind(to)
+    .bind(id)
+    .bind(public_only(&state, &headers))
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to fetch timeline for entity {}: {}", id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let days: Vec<serde_json::Value> = rows
+        .iter()
+        .map(|row| {
+            json!({
+                "date": row.get::<chrono::NaiveDate, _>("day"),
+                "count": row.get::<i64, _>("item_count"),
+            })
+        })
+        .collect();
+    let total: i64 = rows.iter().map(|row| row.get::<i64, _>("item_count")).sum();
+
+    Ok(Json(json!({
+        "entity_id": id.to_string(),
+        "from": from,
+        "to": to,
+        "total": total,
+        "days": days,
+    })))
+}
+
+#[derive(Deserialize)]
+struct SetDefaultTagsRequest {
+    tag_ids: Vec<i32>,
+}
+
+/// 查看实体的默认标签
+async fn get_entity_default_tags(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let tag_ids: Vec<i32> = sqlx::query_scalar(
+        "SELECT tag_id FROM entity_default_tags WHERE entity_id = $1 ORDER BY tag_id",
+    )
+    .bind(id)
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to fetch default tags for entity {}: {}", id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let tags_map = fetch_tags_map(&state, &tag_ids).await;
+    let tag_objects: Vec<serde_json::Value> = tag_ids.iter().filter_map(|t| tags_map.get(t).cloned()).collect();
+    Ok(Json(json!({
+        "entity_id": id.to_string(),
+        "tag_ids": tag_ids,
+        "tag_objects": tag_objects,
+    })))
+}
+
+/// 整体替换实体的默认标签：之后从该实体收录的 item 会自动打上这些标签（不影响已有 item）
+async fn set_entity_default_tags(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+    Json(req): Json<SetDefaultTagsRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    if state.config.restrict_public_reads {
+        require_api_token(&state, &headers).map_err(|status| (status, Json(json!({ "error": "unauthorized" }))))?;
+    }
+    let internal = |e: sqlx::Error| {
+        tracing::error!("Failed to set default tags for entity {}: {}", id, e);
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": "internal error" })))
+    };
+
+    let mut seen: HashSet<i32> = HashSet::new();
+    let tag_ids: Vec<i32> = req.tag_ids.into_iter().filter(|t| seen.insert(*t)).collect();
+
+    let entity_exists: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM entities WHERE id = $1)")
+        .bind(id)
+        .fetch_one(&state.db)
+        .await
+        .map_err(internal)?;
+    if !entity_exists {
+        return Err((StatusCode::NOT_FOUND, Json(json!({ "error": "entity not found" }))));
+    }
+
+    let existing: Vec<i32> = sqlx::query_scalar("SELECT id FROM tags WHERE id = ANY($1)")
+        .bind(&tag_ids)
+        .fetch_all(&state.db)
+        .await
+        .map_err(internal)?;
+    let existing: HashSet<i32> = existing.into_iter().collect();
+    let unknown: Vec<i32> = tag_ids.iter().copied().filter(|t| !existing.contains(t)).collect();
+    if !unknown.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": "unknown tag ids", "unknown_tags": unknown })),
+        ));
+    }
+
+    let mut tx = state.db.begin().await.map_err(internal)?;
+    sqlx::query("DELETE FROM entity_default_tags WHERE entity_id = $1")
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map_err(internal)?;
+    sqlx::query(
+        "INSERT INTO entity_default_tags (entity_id, tag_id) SELECT $1, t FROM unnest($2::int[]) AS t",
+    )
+    .bind(id)
+    .bind(&tag_ids)
+    .execute(&mut *tx)
+    .await
+    .map_err(internal)?;
+    tx.commit().await.map_err(internal)?;
+
+    Ok(Json(json!({ "entity_id": id.to_string(), "tag_ids": tag_ids })))
+}
+
+/// item 的来源实体：转发自用户（含 Hidden User 的 0）时取 tg_user_id，否则取 tg_chat_id
+fn item_source_entity_id(row: &PgRow) -> Option<i64> {
+    let tg_user_id: Option<i64> = row.try_get("tg_user_id").ok().flatten();
+    let tg_chat_id: Option<i64> = row.try_get("tg_chat_id").ok().flatten();
+    tg_user_id.or(tg_chat_id)
+}
+
+async fn list_entities(
+    State(state): State<AppState>,
+    Query(params): Query<ListEntitiesParams>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let limit = clamp_limit(params.limit, ENTITIES_PAGE_SIZE, state.config.max_page_size);
+
+    let (cursor_ts, cursor_id): (Option<chrono::DateTime<chrono::Utc>>, Option<i64>) =
+        match params.cursor.as_deref() {
+            None => (None, None),
+            Some(raw) => {
+                let mut parts = raw.splitn(2, '|');
+                let ts_str = parts.next();
+                let id_str = parts.next();
+                match (ts_str, id_str) {
+                    (Some(ts), Some(id)) => {
+                        let parsed_ts = chrono::DateTime::parse_from_rfc3339(ts)
+                            .map(|dt| dt.with_timezone(&chrono::Utc))
+                            .ok();
+                        let parsed_id = id.parse::<i64>().ok();
+                        (parsed_ts, parsed_id)
+                    }
+                    _ => (None, None),
+                }
+            }
+        };
+
+    let total: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM entities")
+        .fetch_one(&state.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to count entities: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let rows = if let (Some(ts), Some(id)) = (cursor_ts, cursor_id) {
+        sqlx::query(
+            r#"
+            SELECT id, name, username, type, avatar_url, updated_at, muted
+            FROM entities
+            WHERE updated_at < $1 OR (updated_at = $1 AND id < $2)
+            ORDER BY updated_at DESC, id DESC
+            LIMIT $3
+            "#,
+        )
+        .bind(ts)
+        .bind(id)
+        .bind(limit)
+        .fetch_all(&state.db)
+        .await
+    } else {
+        sqlx::query(
+            r#"
+            SELECT id, name, username, type, avatar_url, updated_at, muted
+            FROM entities
+            ORDER BY updated_at DESC, id DESC
+            LIMIT $1
+            "#,
+        )
+        .bind(limit)
+        .fetch_all(&state.db)
+        .await
+    }
+    .map_err(|e| {
+        tracing::error!("Failed to fetch entities: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let mut entities = Vec::new();
+    let mut next_cursor: Option<String> = None;
+
+    for row in rows.iter() {
+        let id: i64 = row.get("id");
+        let name: String = row.get("name");
+        let username: Option<String> = row.get("username");
+        let entity_type: String = row.get("type");
+        let avatar_url: Option<String> = row.get("avatar_url");
+        let updated_at: Option<chrono::DateTime<chrono::Utc>> = row.try_get("updated_at").ok();
+
+        let avatar_final_url = resolve_proxy_url(&state, avatar_url).await;
+
+        entities.push(json!({
+            "id": id.to_string(),
+            "name": name,
+            "username": username,
+            "type": entity_type,
+            "avatar_url": avatar_final_url,
+            "updated_at": updated_at,
+            "muted": row.try_get::<bool, _>("muted").unwrap_or(false),
+        }));
+    }
+
+    if entities.len() == limit as usize {
+        if let Some(last) = rows.last() {
+            let last_id: i64 = last.get("id");
+            let last_ts: Option<chrono::DateTime<chrono::Utc>> = last.try_get("updated_at").ok();
+            if let Some(ts) = last_ts {
+                next_cursor = Some(format!("{}|{}", ts.to_rfc3339(), last_id));
+            }
+        }
+    }
+
+    Ok(Json(json!({
+        "entities": entities,
+        "next_cursor": next_cursor,
+        "total": total
+    })))
+}
+
+/// 随机模式的固定洗牌键：同一组图共享分组键，整组一起出现；与 SQL 中的 md5 表达式保持一致
+fn shuffle_key(id: i64, tg_group_id: Option<i64>, seed: &str) -> String {
+    let group_key = match tg_group_id {
+        Some(gid) => format!("g{}", gid),
+        None => format!("i{}", id),
+    };
+    format!("{:x}", md5::compute(format!("{}:{}", group_key, seed)))
+}
+
+/// 解析随机模式的 (seed, 上一页最后的洗牌键)；没有 seed 也没有游标时退回纯随机
+fn parse_shuffle_cursor(cursor: Option<&str>, seed: Option<&str>) -> Result<Option<(String, Option<String>)>, StatusCode> {
+    let valid_seed = |s: &str| {
+        !s.is_empty() && s.len() <= 64 && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    };
+
+    if let Some(raw) = cursor {
+        let (seed, key) = raw.split_once(':').ok_or(StatusCode::BAD_REQUEST)?;
+        if !valid_seed(seed) || key.len() != 32 || !key.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        return Ok(Some((seed.to_string(), Some(key.to_ascii_lowercase()))));
+    }
+
+    match seed {
+        Some(seed) if valid_seed(seed) => Ok(Some((seed.to_string(), None))),
+        Some(_) => Err(StatusCode::BAD_REQUEST),
+        None => Ok(None),
+    }
+}
+
+/// 默认时间线隐藏静音实体的内容；显式按实体或合集过滤、随机模式都不受影响
+fn hides_muted_entities(mode: &str, entity_ids: &[i64], collection_id: Option<i64>) -> bool {
+    mode == "timeline" && entity_ids.is_empty() && collection_id.is_none()
+}
+
+/// bot 写入 meta.source_kind 的取值
+const SOURCE_KINDS: &[&str] = &["forward_channel", "forward_user", "forward_hidden", "self"];
+
+async fn list_items(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<ListParams>,
+    Query(raw_params): Query<Vec<(String, String)>>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let public_only = public_only(&state, &headers);
+    let limit = clamp_limit(params.limit, state.config.default_page_size, state.config.max_page_size);
+    let mode = params.mode.as_deref().unwrap_or("timeline");
+    let entity_ids = raw_params
+        .iter()
+        .filter(|(k, _)| k == "entity_id")
+        .map(|(_, v)| v.trim().parse::<i64>().map_err(|_| StatusCode::BAD_REQUEST))
+        .collect::<Result<Vec<i64>, _>>()?;
+    let hide_muted = hides_muted_entities(mode, &entity_ids, params.collection_id);
+
+    let (timeline_cursor, shuffle) = if mode == "random" {
+        (None, parse_shuffle_cursor(params.cursor.as_deref(), params.seed.as_deref())?)
+    } else {
+        let cursor = params
+            .cursor
+            .as_deref()
+            .map(|c| c.parse::<i64>().map_err(|_| StatusCode::BAD_REQUEST))
+            .transpose()?;
+        (cursor, None)
+    };
+    let push_shuffle_key = |qb: &mut QueryBuilder<Postgres>, seed: &str| {
+        qb.push("md5(COALESCE('g' || tg_group_id::text, 'i' || id::text) || ':' || ");
+        qb.push_bind(seed.to_string());
+        qb.push(")");
+    };
+    let tag_id = params.tag_id;
+    let source_kind = params.source_kind.as_deref().map(str::trim).filter(|k| !k.is_empty());
+    if source_kind.is_some_and(|k| !SOURCE_KINDS.contains(&k)) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
 
     let mut qb: QueryBuilder<Postgres> = QueryBuilder::new(
-        "SELECT id, item_type, content_text, s3_key, thumbnail_key, created_at, meta, tg_chat_id, tg_user_id, tg_message_id, tg_group_id, tags FROM items",
+        "SELECT id, item_type, content_text, s3_key, thumbnail_key, storyboard_key, created_at, processed_at, meta, tg_chat_id, tg_user_id, tg_message_id, tg_group_id, tags, is_pinned, visibility, note, lang FROM items",
     );
 
     let mut has_where = false;
@@ -259,19 +984,35 @@ async fn list_items(
         qb.push(clause);
     };
 
-    if mode != "random" {
-        if let Some(cursor) = params.cursor {
-            push_where(&mut qb, "id < ");
-            qb.push_bind(cursor);
-        }
+    if let Some(cursor) = timeline_cursor {
+        push_where(&mut qb, "id < ");
+        qb.push_bind(cursor);
+    }
+
+    if let Some((seed, Some(after_key))) = &shuffle {
+        push_where(&mut qb, "");
+        push_shuffle_key(&mut qb, seed);
+        qb.push(" > ");
+        qb.push_bind(after_key.clone());
     }
 
-    if let Some(eid) = entity_id {
+    if let [eid] = entity_ids[..] {
         push_where(&mut qb, "(tg_chat_id = ");
         qb.push_bind(eid);
         qb.push(" OR tg_user_id = ");
         qb.push_bind(eid);
         qb.push(")");
+    } else if !entity_ids.is_empty() {
+        push_where(&mut qb, "(tg_chat_id = ANY(");
+        qb.push_bind(entity_ids.clone());
+        qb.push(") OR tg_user_id = ANY(");
+        qb.push_bind(entity_ids);
+        qb.push("))");
+    } else if hide_muted {
+        push_where(
+            &mut qb,
+            "NOT EXISTS (SELECT 1 FROM entities e WHERE e.muted AND e.id = COALESCE(items.tg_user_id, items.tg_chat_id))",
+        );
     }
 
     if let Some(tid) = tag_id {
@@ -289,7 +1030,37 @@ async fn list_items(
         qb.push(")");
     }
 
-    if mode == "random" {
+    if let Some(lang) = params.lang.as_deref().filter(|l| !l.is_empty()) {
+        push_where(&mut qb, "lang = ");
+        qb.push_bind(lang.to_string());
+    }
+
+    if let Some(pinned) = params.pinned {
+        push_where(&mut qb, "is_pinned = ");
+        qb.push_bind(pinned);
+    }
+
+    if let Some(kind) = source_kind {
+        push_where(&mut qb, "meta->>'source_kind' = ");
+        qb.push_bind(kind.to_string());
+    }
+
+    if let Some(collection_id) = params.collection_id {
+        push_where(&mut qb, "id IN (SELECT item_id FROM collection_items WHERE collection_id = ");
+        qb.push_bind(collection_id);
+        qb.push(")");
+    }
+
+    if public_only {
+        push_where(&mut qb, "visibility = 'public'");
+    }
+
+    if let Some((seed, _)) = &shuffle {
+        qb.push(" ORDER BY ");
+        push_shuffle_key(&mut qb, seed);
+        qb.push(", id LIMIT ");
+        qb.push_bind(limit);
+    } else if mode == "random" {
         qb.push(" ORDER BY RANDOM() ");
         qb.push(" LIMIT ");
         qb.push_bind(limit);
@@ -320,9 +1091,10 @@ async fn list_items(
             Vec::new()
         } else {
             sqlx::query(
-                "SELECT id, item_type, content_text, s3_key, thumbnail_key, created_at, meta, tg_chat_id, tg_user_id, tg_message_id, tg_group_id, tags FROM items WHERE tg_group_id = ANY($1)"
+                "SELECT id, item_type, content_text, s3_key, thumbnail_key, storyboard_key, created_at, processed_at, meta, tg_chat_id, tg_user_id, tg_message_id, tg_group_id, tags, is_pinned, visibility, note, lang FROM items WHERE tg_group_id = ANY($1) AND (NOT $2 OR visibility = 'public')"
             )
             .bind(&group_ids)
+            .bind(public_only)
             .fetch_all(&state.db)
             .await
             .unwrap_or_default()
@@ -344,6 +1116,15 @@ async fn list_items(
     unique_tag_ids_vec.sort_unstable();
     let tags_map = fetch_tags_map(&state, &unique_tag_ids_vec).await;
 
+    let mut unique_entity_ids: HashSet<i64> = HashSet::new();
+    for row in base_rows.iter().chain(extra_rows.iter()) {
+        if let Some(eid) = item_source_entity_id(row) {
+            unique_entity_ids.insert(eid);
+        }
+    }
+    let unique_entity_ids_vec: Vec<i64> = unique_entity_ids.into_iter().collect();
+    let entities_map = fetch_entities_map(&state, &unique_entity_ids_vec).await;
+
     let mut seen_item_ids: HashSet<i64> = HashSet::new();
     for row in base_rows.iter().chain(extra_rows.iter()) {
         let id: i64 = row.get("id");
@@ -354,13 +1135,19 @@ async fn list_items(
         let content_text: Option<String> = row.get("content_text");
         let s3_key: Option<String> = row.get("s3_key");
         let thumbnail_key: Option<String> = row.try_get("thumbnail_key").ok();
+        let storyboard_key: Option<String> = row.try_get("storyboard_key").ok().flatten();
         let created_at: Option<chrono::DateTime<chrono::Utc>> = row.try_get("created_at").ok();
+        let processed_at: Option<chrono::DateTime<chrono::Utc>> = row.try_get("processed_at").ok().flatten();
         let meta: serde_json::Value = row.try_get("meta").unwrap_or(json!({}));
         let tg_chat_id: Option<i64> = row.try_get("tg_chat_id").ok();
         let tg_user_id: Option<i64> = row.try_get("tg_user_id").ok();
         let tg_message_id: Option<i64> = row.try_get("tg_message_id").ok();
         let tg_group_id: Option<i64> = row.try_get("tg_group_id").ok();
         let tags: Vec<i32> = row.try_get("tags").unwrap_or_default();
+        let is_pinned: bool = row.try_get("is_pinned").unwrap_or(false);
+        let visibility: String = row.try_get("visibility").unwrap_or_else(|_| "private".to_string());
+        let note: Option<String> = row.try_get("note").ok().flatten();
+        let lang: Option<String> = row.try_get("lang").ok().flatten();
         let tag_objects: Vec<serde_json::Value> = tags
             .iter()
             .filter_map(|id| tags_map.get(id).cloned())
@@ -378,6 +1165,12 @@ async fn list_items(
              None
         };
 
+        let storyboard_url = if let Some(key) = storyboard_key.as_ref() {
+             state.s3_signing_client.presign_get(key, 3600, None).await.ok()
+        } else {
+             None
+        };
+
         let source_url = if let Some(user_id) = tg_user_id {
             if user_id > 0 {
                 Some(format!("tg://user?id={}", user_id))
@@ -395,55 +1188,84 @@ async fn list_items(
                 (Some(chat_id), None) if chat_id <= -1000000000000 => {
                     Some(format!("https://t.me/c/{}", (-chat_id - 1000000000000_i64)))
                 }
-                _ => None
+                // 网页链接 item 的来源就是链接本身
+                _ => meta.pointer("/link/url").and_then(|u| u.as_str()).map(str::to_string),
             }
         };
 
-        let _entity_avatar: Option<String> = None;
+        let source = item_source_entity_id(row).and_then(|eid| entities_map.get(&eid).cloned());
 
         items.push(json!({
-            "id": id,
+            "id": id.to_string(),
             "type": item_type,
             "content": content_text,
             "s3_url": s3_url,
             "thumbnail_url": thumbnail_url,
+            "storyboard_url": storyboard_url,
+            "storyboard": meta.get("storyboard"),
             "created_at": created_at,
+            "processed_at": processed_at,
             "width": meta.get("width"),
             "height": meta.get("height"),
             "source_url": source_url,
+            "source": source,
+            "source_name": item_source_name(&meta),
+            "summary": meta.get("summary"),
+            "link": link_preview_json(&meta),
+            "source_kind": meta.get("source_kind"),
+            "lang": lang,
+            // i64 id 可能超出 JS 安全整数范围，统一以字符串返回
+            "tg_chat_id": tg_chat_id.map(|v| v.to_string()),
+            "tg_message_id": tg_message_id.map(|v| v.to_string()),
             "tg_group_id": tg_group_id.map(|v| v.to_string()),
             "tags": tags,
             "tag_objects": tag_objects,
+            "is_pinned": is_pinned,
+            "visibility": visibility,
+            "note": note,
         }));
     }
 
     // 计算下一页游标
-    let next_cursor = if mode != "random" && items.len() == limit as usize {
-        base_rows.last().map(|r| r.get::<i64, _>("id"))
-    } else {
-        None
+    // 洗牌模式下游标取最后一条的洗牌键，下一页从更大的键开始；组图已在本页整组展开，不会再次出现
+    let next_cursor = match &shuffle {
+        Some((seed, _)) if base_rows.len() == limit as usize => base_rows.last().map(|r| {
+            let key = shuffle_key(r.get("id"), r.try_get("tg_group_id").ok().flatten(), seed);
+            json!(format!("{}:{}", seed, key))
+        }),
+        None if mode != "random" && items.len() == limit as usize => {
+            base_rows.last().map(|r| json!(r.get::<i64, _>("id").to_string()))
+        }
+        _ => None,
     };
 
-    Json(json!({
+    Ok(Json(json!({
         "items": items,
         "next_cursor": next_cursor
-    }))
+    })))
 }
 
 /// 获取单个 item 详情
 async fn get_item(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Path(id): Path<i64>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
+    load_item_detail(&state, id, public_only(&state, &headers)).await
+}
+
+/// item 详情；public_only 时私有 item 按不存在处理
+async fn load_item_detail(state: &AppState, id: i64, public_only: bool) -> Result<Json<serde_json::Value>, StatusCode> {
     let row = sqlx::query(
         r#"
-        SELECT id, item_type, content_text, searchable_text, s3_key, 
-               tg_chat_id, tg_message_id, created_at, processed_at, meta, tags
+        SELECT id, item_type, content_text, searchable_text, s3_key, storyboard_key,
+               tg_chat_id, tg_message_id, created_at, processed_at, meta, tags, is_pinned, visibility, note, lang
         FROM items 
-        WHERE id = $1
+        WHERE id = $1 AND (NOT $2 OR visibility = 'public')
         "#
     )
     .bind(id)
+    .bind(public_only)
     .fetch_optional(&state.db)
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
@@ -455,13 +1277,19 @@ async fn get_item(
             let content_text: Option<String> = row.get("content_text");
             let searchable_text: Option<String> = row.get("searchable_text");
             let s3_key: Option<String> = row.get("s3_key");
+            let storyboard_key: Option<String> = row.get("storyboard_key");
             let tg_chat_id: Option<i64> = row.get("tg_chat_id");
             let tg_message_id: Option<i64> = row.get("tg_message_id");
             let created_at: Option<chrono::DateTime<chrono::Utc>> = row.try_get("created_at").ok();
             let processed_at: Option<chrono::DateTime<chrono::Utc>> = row.try_get("processed_at").ok();
             let meta: serde_json::Value = row.try_get("meta").unwrap_or(json!({}));
             let tags: Vec<i32> = row.try_get("tags").unwrap_or_default();
-            let tags_map = fetch_tags_map(&state, &tags).await;
+            let is_pinned: bool = row.try_get("is_pinned").unwrap_or(false);
+            let visibility: String = row.try_get("visibility").unwrap_or_else(|_| "private".to_string());
+            let note: Option<String> = row.try_get("note").ok().flatten();
+            let lang: Option<String> = row.try_get("lang").ok().flatten();
+            let processing_state = item_processing_state(state, id).await;
+            let tags_map = fetch_tags_map(state, &tags).await;
             let tag_objects: Vec<serde_json::Value> = tags
                 .iter()
                 .filter_map(|id| tags_map.get(id).cloned())
@@ -473,6 +1301,12 @@ async fn get_item(
                 None
             };
 
+            let storyboard_url = if let Some(key) = storyboard_key.as_ref() {
+                state.s3_signing_client.presign_get(key, 3600, None).await.ok()
+            } else {
+                None
+            };
+
             // 构建 TG 跳转链接
             let tg_link = match (tg_chat_id, tg_message_id) {
                 // 频道/超级群组消息：https://t.me/c/ID/MSG_ID
@@ -492,148 +1326,677 @@ async fn get_item(
             };
 
             Ok(Json(json!({
-                "id": id,
+                "id": id.to_string(),
                 "type": item_type,
                 "content": content_text,
                 "searchable_text": searchable_text,
                 "s3_url": s3_url,
+                "storyboard_url": storyboard_url,
                 "tg_link": tg_link,
+                "tg_chat_id": tg_chat_id.map(|v| v.to_string()),
+                "tg_message_id": tg_message_id.map(|v| v.to_string()),
+                "source_name": item_source_name(&meta),
+                "summary": meta.get("summary"),
+                "link": link_preview_json(&meta),
+                "source_kind": meta.get("source_kind"),
+                "lang": lang,
                 "created_at": created_at,
                 "processed_at": processed_at,
                 "meta": meta,
                 "tags": tags,
                 "tag_objects": tag_objects,
+                "is_pinned": is_pinned,
+                "visibility": visibility,
+                "note": note,
+                "processing_state": processing_state,
             })))
         }
         None => Err(StatusCode::NOT_FOUND),
     }
 }
 
-/// 删除 item
-async fn delete_item(
+/// 隐藏账号的用户转发没有来源实体，只有转发时显示的名字（bot 写入 meta.forward_sender_name）
+pub(crate) fn item_source_name(meta: &serde_json::Value) -> Option<&serde_json::Value> {
+    meta.get("forward_sender_name")
+}
+
+/// 列表里只带链接预览的展示字段，正文摘录（meta.link.excerpt）只在详情的 meta 中返回
+fn link_preview_json(meta: &serde_json::Value) -> Option<serde_json::Value> {
+    let link = meta.get("link")?;
+    Some(json!({
+        "url": link.get("url"),
+        "title": link.get("title"),
+        "description": link.get("description"),
+        "site_name": link.get("site_name"),
+    }))
+}
+
+/// 按 Telegram 消息坐标（tg_chat_id + tg_message_id）查找 item，即 tg_link 的逆向
+async fn get_item_by_message(
     State(state): State<AppState>,
-    Path(id): Path<i64>,
+    headers: HeaderMap,
+    Query(params): Query<ByMessageParams>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    // 1. Fetch info for S3 cleanup and Entity cleanup
-    let row = sqlx::query("SELECT s3_key, thumbnail_key, tg_chat_id, tg_user_id FROM items WHERE id = $1")
-        .bind(id)
-        .fetch_optional(&state.db)
+    let id: Option<i64> = sqlx::query_scalar(
+        r#"
+        SELECT id
+        FROM items
+        WHERE tg_chat_id = $1 AND tg_message_id = $2
+        ORDER BY id ASC
+        LIMIT 1
+        "#,
+    )
+    .bind(params.chat_id)
+    .bind(params.message_id)
+    .fetch_optional(&state.db)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to look up item by message: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    match id {
+        Some(id) => get_item(State(state), headers, Path(id)).await,
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+/// 整体替换 item 的 tags（用于勾选式的标签编辑器）
+async fn set_item_tags(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(req): Json<SetItemTagsRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    // 去重但保留调用方给出的顺序
+    let mut seen: HashSet<i32> = HashSet::new();
+    let tag_ids: Vec<i32> = req.tags.into_iter().filter(|t| seen.insert(*t)).collect();
+
+    let existing: Vec<i32> = sqlx::query_scalar("SELECT id FROM tags WHERE id = ANY($1)")
+        .bind(&tag_ids)
+        .fetch_all(&state.db)
         .await
         .map_err(|e| {
-            tracing::error!("Failed to fetch item for deletion: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            tracing::error!("Failed to validate tags for item {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": "internal error" })))
         })?;
 
-    let (s3_key, thumbnail_key, tg_chat_id, tg_user_id) = match row {
-        Some(r) => (
-            r.try_get::<Option<String>, _>("s3_key").unwrap_or(None),
-            r.try_get::<Option<String>, _>("thumbnail_key").unwrap_or(None),
-            r.try_get::<Option<i64>, _>("tg_chat_id").unwrap_or(None),
-            r.try_get::<Option<i64>, _>("tg_user_id").unwrap_or(None),
-        ),
-        None => return Err(StatusCode::NOT_FOUND),
-    };
+    let existing: HashSet<i32> = existing.into_iter().collect();
+    let unknown: Vec<i32> = tag_ids.iter().copied().filter(|t| !existing.contains(t)).collect();
+    if !unknown.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": "unknown tag ids", "unknown_tags": unknown })),
+        ));
+    }
 
-    // 2. Database Transaction
-    let mut tx = state.db.begin().await.map_err(|e| {
-        tracing::error!("Failed to begin transaction: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    let result = sqlx::query("UPDATE items SET tags = $1 WHERE id = $2")
+        .bind(&tag_ids)
+        .bind(id)
+        .execute(&state.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to set tags for item {}: {}", id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": "internal error" })))
+        })?;
+
+    if result.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, Json(json!({ "error": "item not found" }))));
+    }
+
+    load_item_detail(&state, id, false)
+        .await
+        .map_err(|status| (status, Json(json!({ "error": "failed to load item" }))))
+}
+
+#[derive(Deserialize)]
+struct SetVisibilityRequest {
+    visibility: String,
+}
+
+/// 设置 item 可见性（private / public）；开启 RESTRICT_PUBLIC_READS 时需要 API token
+async fn set_item_visibility(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+    Json(req): Json<SetVisibilityRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    if state.config.restrict_public_reads {
+        require_api_token(&state, &headers)?;
+    }
+    let visibility = req.visibility.trim().to_ascii_lowercase();
+    if visibility != "private" && visibility != "public" {
+        return Err(StatusCode::BAD_REQUEST);
+    }
 
-    // Delete tasks first (to satisfy FK)
-    sqlx::query("DELETE FROM tasks WHERE item_id = $1")
+    let result = sqlx::query("UPDATE items SET visibility = $1 WHERE id = $2")
+        .bind(&visibility)
         .bind(id)
-        .execute(&mut *tx)
+        .execute(&state.db)
         .await
         .map_err(|e| {
-            tracing::error!("Failed to delete tasks: {}", e);
+            tracing::error!("Failed to set visibility={} for item {}: {}", visibility, id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    if result.rows_affected() == 0 {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    Ok(Json(json!({ "success": true, "visibility": visibility })))
+}
+
+#[derive(Deserialize)]
+struct SetNoteRequest {
+    note: Option<String>, // null 或空白表示清除备注
+}
+
+/// 设置 item 的备注；开启 NOTE_SEARCHABLE 且备注有变化时入队重建索引，让备注可以被搜到
+async fn set_item_note(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+    Json(req): Json<SetNoteRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    if state.config.restrict_public_reads {
+        require_api_token(&state, &headers)?;
+    }
+    let note = req.note.map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
+
+    // 返回旧值判断是否真的变化，避免无意义的重建索引
+    let previous: Option<Option<String>> = sqlx::query_scalar(
+        "UPDATE items i SET note = $1 FROM items old WHERE i.id = $2 AND old.id = i.id RETURNING old.note",
+    )
+    .bind(&note)
+    .bind(id)
+    .fetch_optional(&state.db)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to set note for item {}: {}", id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let Some(previous) = previous else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+
+    let reindex = state.config.note_searchable && previous != note;
+    if reindex {
+        enqueue_note_reprocess(&state, id).await.map_err(|e| {
+            tracing::error!("Failed to enqueue reindex for item {}: {}", id, e);
             StatusCode::INTERNAL_SERVER_ERROR
         })?;
+    }
+
+    Ok(Json(json!({ "success": true, "note": note, "reindex": reindex })))
+}
+
+/// 备注修改后为 item 入队只重建文本的任务（不重新 OCR 与生成视觉向量）。
+/// 只与 pending 的重建任务去重：正在处理的任务可能已经读过旧备注
+async fn enqueue_note_reprocess(state: &AppState, item_id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO tasks (item_id, bot_chat_id, bot_message_id, status, payload)
+        SELECT $1, 0, 0, 'pending', jsonb_build_object('reprocess_item_id', $1, 'text_only', true)
+        WHERE NOT EXISTS (
+            SELECT 1 FROM tasks t
+            WHERE t.status = 'pending'
+              AND t.payload->>'reprocess_item_id' = $1::text
+        )
+        "#,
+    )
+    .bind(item_id)
+    .execute(&state.db)
+    .await?;
+    Ok(())
+}
+
+/// 收藏 item
+async fn pin_item(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    set_item_pinned(&state, id, true).await
+}
 
-    // Delete item
-    let result = sqlx::query("DELETE FROM items WHERE id = $1")
+/// 取消收藏
+async fn unpin_item(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    set_item_pinned(&state, id, false).await
+}
+
+async fn set_item_pinned(state: &AppState, id: i64, pinned: bool) -> Result<Json<serde_json::Value>, StatusCode> {
+    let result = sqlx::query("UPDATE items SET is_pinned = $1 WHERE id = $2")
+        .bind(pinned)
         .bind(id)
-        .execute(&mut *tx)
+        .execute(&state.db)
         .await
         .map_err(|e| {
-            tracing::error!("Failed to delete item: {}", e);
+            tracing::error!("Failed to set pinned={} for item {}: {}", pinned, id, e);
             StatusCode::INTERNAL_SERVER_ERROR
         })?;
 
-    // 2.5 Entity Cleanup: If this was the last item for these entities, delete them
-    let mut entities_to_check = Vec::new();
-    if let Some(cid) = tg_chat_id { entities_to_check.push(cid); }
-    if let Some(uid) = tg_user_id { entities_to_check.push(uid); }
+    if result.rows_affected() == 0 {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    Ok(Json(json!({ "success": true, "is_pinned": pinned })))
+}
 
-    for eid in entities_to_check {
-        // Check if any other items remain for this entity
-        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM items WHERE tg_chat_id = $1 OR tg_user_id = $1")
-            .bind(eid)
-            .fetch_one(&mut *tx)
-            .await
-            .map_err(|e| {
-                tracing::error!("Failed to count remaining items: {}", e);
-                StatusCode::INTERNAL_SERVER_ERROR
-            })?;
-        
-        if count == 0 {
-            tracing::info!("Entity {} has no more items. Deleting entity.", eid);
-            sqlx::query("DELETE FROM entities WHERE id = $1")
-                .bind(eid)
-                .execute(&mut *tx)
-                .await
-                .map_err(|e| {
-                    tracing::error!("Failed to delete entity: {}", e);
-                    StatusCode::INTERNAL_SERVER_ERROR
-                })?;
+#[derive(Deserialize)]
+struct DeleteItemParams {
+    dry_run: Option<bool>, // 只返回将被删除的内容，不做任何修改
+}
+
+/// 删除 item
+async fn delete_item(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Query(params): Query<DeleteItemParams>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    if params.dry_run.unwrap_or(false) {
+        let plan = purge::plan_delete_item(&state, id).await.map_err(|e| {
+            tracing::error!("Failed to plan deletion of item {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        let Some(plan) = plan else {
+            return Err(StatusCode::NOT_FOUND);
+        };
+        return Ok(Json(json!({
+            "dry_run": true,
+            "item": { "id": id.to_string(), "type": plan.item_type },
+            "s3_keys": plan.s3_keys,
+            "entities_removed": plan.entities_removed.iter().map(|eid| eid.to_string()).collect::<Vec<_>>(),
+            "tasks_removed": plan.tasks_removed,
+        })));
+    }
+
+    match purge::delete_item(&state, id).await {
+        Ok(true) => Ok(Json(json!({ "success": true }))),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("Failed to delete item {}: {}", id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
+}
+
+// ============ Tasks API ============
+
+const PENDING_DEFAULT_LIMIT: i64 = 100;
+
+#[derive(Deserialize)]
+struct PendingParams {
+    limit: Option<i64>,
+    include_failed: Option<bool>, // 同时返回 failed 任务（带 error_code）
+}
+
+/// 仍在处理中的任务（pending / processing），前端据此为尚未完成的转发显示处理中状态。
+/// item_id 在任务完成（或重建索引任务创建）时才有值
+async fn list_pending_tasks(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<PendingParams>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    if state.config.restrict_public_reads {
+        require_api_token(&state, &headers)?;
+    }
+    let limit = clamp_limit(params.limit, PENDING_DEFAULT_LIMIT, state.config.max_page_size.max(PENDING_DEFAULT_LIMIT));
+    let statuses: Vec<&str> = if params.include_failed.unwrap_or(false) {
+        vec!["pending", "processing", "failed"]
+    } else {
+        vec!["pending", "processing"]
+    };
+
+    let rows = sqlx::query(
+        r#"
+        SELECT id, status, bot_chat_id, bot_message_id, source_chat_id, source_message_id,
+               COALESCE(item_id, (payload->>'reprocess_item_id')::bigint) AS item_id,
+               payload->>'item_type' AS item_type,
+               payload->>'tg_group_id' AS tg_group_id,
+               retry_count, error_code, created_at, updated_at
+        FROM tasks
+        WHERE status = ANY($1)
+        ORDER BY id DESC
+        LIMIT $2
+        "#,
+    )
+    .bind(&statuses)
+    .bind(limit)
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to fetch pending tasks: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let tasks: Vec<serde_json::Value> = rows
+        .iter()
+        .map(|row| {
+            let created_at: Option<chrono::DateTime<chrono::Utc>> = row.try_get("created_at").ok();
+            let updated_at: Option<chrono::DateTime<chrono::Utc>> = row.try_get("updated_at").ok();
+            json!({
+                "id": row.get::<i64, _>("id").to_string(),
+                "status": row.get::<Option<String>, _>("status"),
+                "bot_chat_id": row.get::<i64, _>("bot_chat_id").to_string(),
+                "bot_message_id": row.get::<i64, _>("bot_message_id").to_string(),
+                "source_chat_id": row.get::<Option<i64>, _>("source_chat_id").map(|v| v.to_string()),
+                "source_message_id": row.get::<Option<i64>, _>("source_message_id").map(|v| v.to_string()),
+                "item_id": row.get::<Option<i64>, _>("item_id").map(|v| v.to_string()),
+                "item_type": row.get::<Option<String>, _>("item_type"),
+                "tg_group_id": row.get::<Option<String>, _>("tg_group_id"),
+                "retry_count": row.get::<Option<i32>, _>("retry_count").unwrap_or(0),
+                "error_code": row.get::<Option<String>, _>("error_code"),
+                "created_at": created_at,
+                "updated_at": updated_at,
+            })
+        })
+        .collect();
 
-    tx.commit().await.map_err(|e| {
-        tracing::error!("Failed to commit transaction: {}", e);
+    let queue_depth = task_queue_depth(&state.db).await.map_err(|e| {
+        tracing::error!("Failed to count pending tasks: {}", e);
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
 
-    // 3. S3 Cleanup
-    if result.rows_affected() > 0 {
-        // Init internal bucket for deletion
-        let region = Region::Custom {
-            region: "us-east-1".to_owned(),
-            endpoint: state.config.s3_endpoint.clone(),
+    Ok(Json(json!({
+        "tasks": tasks,
+        "total": tasks.len(),
+        // 计入 MAX_PENDING_TASKS 的任务数（见 task_queue_depth），不受 limit 影响
+        "queue_depth": queue_depth,
+        "max_pending_tasks": state.config.max_pending_tasks,
+    })))
+}
+
+/// item 的处理状态：有未完成的任务（如重建索引）时返回其状态，否则为 completed
+async fn item_processing_state(state: &AppState, item_id: i64) -> String {
+    sqlx::query_scalar::<_, String>(
+        r#"
+        SELECT status
+        FROM tasks
+        WHERE (item_id = $1 OR payload->>'reprocess_item_id' = $1::text)
+          AND status IN ('pending', 'processing')
+        ORDER BY id DESC
+        LIMIT 1
+        "#,
+    )
+    .bind(item_id)
+    .fetch_optional(&state.db)
+    .await
+    .ok()
+    .flatten()
+    .unwrap_or_else(|| "completed".to_string())
+}
+
+// ============ Upload API ============
+
+/// 浏览器直传的对象都放在这个前缀下，登记 item 时只接受该前缀的 key
+const UPLOAD_KEY_PREFIX: &str = "uploads/";
+const UPLOAD_PRESIGN_EXPIRY_SECS: u32 = 900;
+
+#[derive(Deserialize)]
+struct PresignUploadRequest {
+    filename: Option<String>,
+}
+
+/// 生成浏览器直传用的预签名 PUT URL，返回最终的对象 key
+async fn presign_upload(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<PresignUploadRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    require_api_token(&state, &headers)?;
+    let ext = req
+        .filename
+        .as_deref()
+        .and_then(|f| f.rsplit_once('.').map(|(_, e)| e.to_ascii_lowercase()))
+        .filter(|e| !e.is_empty() && e.len() <= 10 && e.chars().all(|c| c.is_ascii_alphanumeric()))
+        .unwrap_or_else(|| "bin".to_string());
+    let key = format!(
+        "{}{}/{}.{}",
+        UPLOAD_KEY_PREFIX,
+        chrono::Utc::now().format("%Y/%m/%d"),
+        uuid::Uuid::new_v4(),
+        ext
+    );
+
+    let url = state
+        .s3_signing_client
+        .presign_put(&key, UPLOAD_PRESIGN_EXPIRY_SECS, None, None)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to presign upload {}: {}", key, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(json!({
+        "key": key,
+        "url": url,
+        "method": "PUT",
+        "expires_in": UPLOAD_PRESIGN_EXPIRY_SECS,
+    })))
+}
+
+#[derive(Deserialize)]
+struct CreateItemRequest {
+    // 直传对象的 key 与类型；与 url 二选一
+    s3_key: Option<String>,
+    #[serde(rename = "type")]
+    item_type: Option<String>,
+    url: Option<String>, // 网页链接：由 worker 抓取预览，收录为 link item
+    content_text: Option<String>,
+}
+
+/// 登记一个已直传到 S3 的对象，或一个网页链接：入队一个跳过 Telegram 下载的任务，由 worker 完成抓取/OCR/向量等处理
+async fn create_item_from_upload(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<CreateItemRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    require_api_token(&state, &headers)?;
+    let content_text = req.content_text.unwrap_or_default();
+
+    let payload = if let Some(url) = req.url.as_deref().map(str::trim) {
+        let valid = reqwest::Url::parse(url)
+            .is_ok_and(|u| matches!(u.scheme(), "http" | "https") && u.host_str().is_some());
+        if !valid {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        if task_queue_full(&state.db, state.config.max_pending_tasks).await {
+            return Err(StatusCode::SERVICE_UNAVAILABLE);
+        }
+        json!({
+            "link_url": url,
+            "item_type": "link",
+            "content_text": content_text,
+            "meta": {},
+        })
+    } else {
+        let (Some(s3_key), Some(item_type)) = (req.s3_key, req.item_type) else {
+            return Err(StatusCode::BAD_REQUEST);
         };
-        let credentials = Credentials::new(
-            Some(&state.config.s3_access_key),
-            Some(&state.config.s3_secret_key),
-            None, None, None
-        ).expect("Failed to create S3 credentials");
-        
-        let bucket = Bucket::new(
-            &state.config.s3_bucket,
-            region,
-            credentials
-        ).expect("Failed to create S3 bucket").with_path_style();
+        if !s3_key.starts_with(UPLOAD_KEY_PREFIX) || s3_key.contains("..") {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        if !matches!(item_type.as_str(), "image" | "video" | "audio" | "document" | "animation") {
+            return Err(StatusCode::BAD_REQUEST);
+        }
 
-        if let Some(key) = s3_key {
-            let _ = bucket.delete_object(&key).await
-                .map_err(|e| tracing::warn!("Failed to delete S3 object {}: {}", key, e));
+        if task_queue_full(&state.db, state.config.max_pending_tasks).await {
+            return Err(StatusCode::SERVICE_UNAVAILABLE);
         }
-        if let Some(key) = thumbnail_key {
-            let _ = bucket.delete_object(&key).await
-                .map_err(|e| tracing::warn!("Failed to delete S3 thumbnail {}: {}", key, e));
+
+        // 确认对象确实已上传
+        let bucket = internal_bucket(&state);
+        match bucket.head_object(&s3_key).await {
+            Ok((_, 200)) => {}
+            Ok((_, 404)) => return Err(StatusCode::NOT_FOUND),
+            Ok((_, code)) => {
+                tracing::error!("Unexpected HEAD status {} for {}", code, s3_key);
+                return Err(StatusCode::BAD_GATEWAY);
+            }
+            Err(e) => {
+                tracing::error!("Failed to check uploaded object {}: {}", s3_key, e);
+                return Err(StatusCode::BAD_GATEWAY);
+            }
         }
 
-        Ok(Json(json!({ "success": true })))
-    } else {
-        Err(StatusCode::NOT_FOUND)
+        json!({
+            "uploaded_s3_key": s3_key,
+            "item_type": item_type,
+            "content_text": content_text,
+            "meta": {},
+        })
+    };
+
+    let task_id: i64 = sqlx::query_scalar(
+        r#"
+        INSERT INTO tasks (bot_chat_id, bot_message_id, status, payload)
+        VALUES (0, 0, 'pending', $1)
+        RETURNING id
+        "#,
+    )
+    .bind(payload)
+    .fetch_one(&state.db)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to enqueue upload task: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(task_created_json(task_id)))
+}
+
+/// 新建任务的响应；task_id 与其他 BIGINT id 一样以字符串输出，避免 JS 精度丢失
+fn task_created_json(task_id: i64) -> serde_json::Value {
+    json!({ "task_id": task_id.to_string(), "status": "pending" })
+}
+
+/// 服务端内部访问 S3 用的 bucket（走 s3_endpoint，非预签名用途）
+fn internal_bucket(state: &AppState) -> Bucket {
+    storage::internal_bucket(&state.config).expect("Failed to create S3 bucket")
+}
+
+#[derive(Deserialize)]
+struct GcParams {
+    apply: Option<bool>, // 默认只统计（dry run），apply=true 才真正删除
+}
+
+/// 最近这段时间内上传的对象不参与回收：worker 先上传再写库，中间有一个窗口期
+const GC_GRACE_SECS: i64 = 3600;
+
+/// 清理 S3 中没有任何数据库记录引用的对象（item 原文件/缩略图/预览拼图、标签图标、实体头像）
+async fn gc_orphaned_objects(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<GcParams>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    require_api_token(&state, &headers)?;
+    let apply = params.apply.unwrap_or(false);
+    let bucket = internal_bucket(&state);
+
+    // 1. 分页列出所有对象
+    let grace_cutoff = chrono::Utc::now() - chrono::Duration::seconds(GC_GRACE_SECS);
+    let mut candidates: Vec<(String, u64)> = Vec::new();
+    let mut scanned: u64 = 0;
+    let mut skipped_recent: u64 = 0;
+    let mut continuation_token: Option<String> = None;
+    loop {
+        let (page, _) = bucket
+            .list_page(String::new(), None, continuation_token.clone(), None, Some(1000))
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to list S3 objects: {}", e);
+                StatusCode::BAD_GATEWAY
+            })?;
+
+        for object in page.contents {
+            scanned += 1;
+            let recent = chrono::DateTime::parse_from_rfc3339(&object.last_modified)
+                .map(|t| t.with_timezone(&chrono::Utc) > grace_cutoff)
+                .unwrap_or(true);
+            if recent {
+                skipped_recent += 1;
+                continue;
+            }
+            candidates.push((object.key, object.size));
+        }
+
+        continuation_token = page.next_continuation_token;
+        if !page.is_truncated || continuation_token.is_none() {
+            break;
+        }
+    }
+
+    // 2. 列完再取引用，期间新写入的记录也能被看到
+    let referenced: Vec<String> = sqlx::query_scalar(
+        r#"
+        SELECT key FROM (
+            SELECT s3_key AS key FROM items
+            UNION ALL SELECT thumbnail_key FROM items
+            UNION ALL SELECT storyboard_key FROM items
+            UNION ALL SELECT substring(asset_url FROM 7) FROM tags WHERE asset_url LIKE 'PROXY:%'
+            UNION ALL SELECT substring(avatar_url FROM 7) FROM entities WHERE avatar_url LIKE 'PROXY:%'
+            -- 直传的原文件在任务处理完之前只被任务引用
+            UNION ALL SELECT payload->>'uploaded_s3_key' FROM tasks WHERE status IN ('pending', 'processing')
+        ) refs
+        WHERE key IS NOT NULL
+        "#,
+    )
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to load referenced S3 keys: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let referenced: HashSet<String> = referenced.into_iter().collect();
+
+    let orphans: Vec<(String, u64)> = candidates
+        .into_iter()
+        .filter(|(key, _)| !referenced.contains(key))
+        .collect();
+    let orphan_bytes: u64 = orphans.iter().map(|(_, size)| size).sum();
+
+    // 3. 删除（仅 apply=true）
+    let mut deleted: u64 = 0;
+    let mut failed: u64 = 0;
+    if apply {
+        for (key, _) in &orphans {
+            match bucket.delete_object(key).await {
+                Ok(resp) if resp.status_code() < 300 => deleted += 1,
+                Ok(resp) => {
+                    tracing::warn!("Failed to delete orphan {}: HTTP {}", key, resp.status_code());
+                    failed += 1;
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to delete orphan {}: {}", key, e);
+                    failed += 1;
+                }
+            }
+        }
+        tracing::info!("S3 gc: deleted {} orphaned objects ({} failed)", deleted, failed);
     }
+
+    let sample: Vec<&str> = orphans.iter().take(50).map(|(key, _)| key.as_str()).collect();
+    Ok(Json(json!({
+        "dry_run": !apply,
+        "scanned": scanned,
+        "skipped_recent": skipped_recent,
+        "orphaned": orphans.len(),
+        "orphaned_bytes": orphan_bytes,
+        "deleted": deleted,
+        "failed": failed,
+        "sample": sample,
+    })))
 }
 
 async fn get_raw_item(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Path(id): Path<i64>,
 ) -> impl IntoResponse {
-    let row = sqlx::query("SELECT s3_key FROM items WHERE id = $1")
+    let row = sqlx::query("SELECT s3_key FROM items WHERE id = $1 AND (NOT $2 OR visibility = 'public')")
         .bind(id)
+        .bind(public_only(&state, &headers))
         .fetch_optional(&state.db)
         .await;
 
@@ -655,73 +2018,170 @@ async fn get_raw_item(
 #[derive(Deserialize)]
 struct SearchParams {
     q: Option<String>,           // 文本搜索词
-    image_url: Option<String>,   // 以图搜图的图片 URL
+    // image_url：以图搜图的图片 URL，可重复出现（多张参考图取向量均值），由 search_items 从原始参数中解析
     #[serde(rename = "type")]
     item_type: Option<String>,   // 类型过滤
     limit: Option<i64>,          // 返回数量
+    lang: Option<String>,        // 语言过滤（items.lang）
+    fusion: Option<String>,      // 融合方式："rrf"（默认，仅按排名）或 "weighted"（按分数加权）
+    #[serde(rename = "match")]
+    match_mode: Option<String>,  // 全文检索的匹配方式：web/websearch、phrase、any、all/plain、raw，缺省按 FTS_QUERY_MODE
+    min_score: Option<f32>,      // 向量召回的最低相似度，覆盖 MIN_VISUAL_SIM / MIN_TEXT_SIM
+    debug: Option<bool>,         // 附带各路召回与融合分数（需要 API token）
 }
 
 /// 混合检索 API
 /// - q: 文本搜索（走 text_embedding + visual_embedding(text) + FTS）
-/// - image_url: 以图搜图（走 visual_embedding KNN）
+/// - image_url: 以图搜图（走 visual_embedding KNN）；多个 image_url 时用各图 CLIP 向量的均值（归一化后）检索
 async fn search_items(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Query(params): Query<SearchParams>,
+    Query(raw_params): Query<Vec<(String, String)>>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    let limit = params.limit.unwrap_or(50).min(100);
-    let per_channel = 100_i64;  // 每路召回数
+    let image_urls: Vec<&str> = raw_params
+        .iter()
+        .filter(|(k, v)| k == "image_url" && !v.trim().is_empty())
+        .map(|(_, v)| v.trim())
+        .collect();
+    if image_urls.len() > MAX_REFERENCE_IMAGES {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let debug = params.debug.unwrap_or(false);
+    if debug {
+        require_api_token(&state, &headers)?;
+    }
+    let fts_match = parse_fts_match(&state, params.match_mode.as_deref())?;
+    let limit = clamp_limit(params.limit, state.config.default_search_limit, state.config.max_search_limit);
+    let per_channel = state.config.per_channel_recall;  // 每路召回数
     let rrf_k = 60.0;           // RRF 平滑常数
     
     // 至少需要 q 或 image_url 之一
-    if params.q.is_none() && params.image_url.is_none() {
+    if params.q.is_none() && image_urls.is_empty() {
         return Err(StatusCode::BAD_REQUEST);
     }
     
-    let mut channels: Vec<Vec<crate::db::SearchHit>> = Vec::new();
+    // 类型 / 语言 / 可见性过滤在各路召回内部完成，融合截断后的结果不再缺页
+    let public_only = public_only(&state, &headers);
+    let filter = SearchFilter {
+        public_only,
+        item_type: params.item_type.as_deref(),
+        lang: params.lang.as_deref(),
+    };
+
+    // 调试模式需要各路召回明细，不走缓存
+    let weighted = params.fusion.as_deref() == Some("weighted");
+    let cache_key = (!debug && state.search_cache.enabled()).then(|| {
+        format!(
+            "{}\u{1f}{}\u{1f}{:?}\u{1f}{}\u{1f}{}\u{1f}{:?}\u{1f}{:?}",
+            params.q.as_deref().map(normalize_query).unwrap_or_default(),
+            image_urls.join("\u{1e}"),
+            fts_match,
+            limit,
+            weighted,
+            params.min_score,
+            filter,
+        )
+    });
+    let cached = cache_key.as_deref().and_then(|key| state.search_cache.get(key));
+    // 未能参与召回的通道（embedding/CLIP 不可用等），结果可能不完整
+    let mut warnings: Vec<String> = Vec::new();
+
+    let (merged_ids, debug_json) = if let Some(ids) = cached {
+        tracing::info!("Search cache hit: {} items", ids.len());
+        (ids, None)
+    } else {
+        let mut channels: Vec<Vec<SearchHit>> = Vec::new();
+        let mut channel_names: Vec<&'static str> = Vec::new();
     
-    // 文本搜索模式
-    if let Some(ref query_text) = params.q {
-        // 1. 获取文本向量（BGE-M3）用于 text_embedding 召回
-        if let Some(text_vec) = get_text_embedding(&state, query_text).await {
-            if let Ok(hits) = search_text_vec(&state.db, &text_vec, per_channel).await {
-                tracing::info!("text_vec recall: {} hits", hits.len());
+        // 文本搜索模式
+        if let Some(ref query_text) = params.q {
+            for (name, hits) in recall_text_filtered(&state, query_text, fts_match, per_channel, params.min_score, filter, &mut warnings).await {
+                channel_names.push(name);
                 channels.push(hits);
             }
         }
-        
-        // 2. 获取文本的视觉向量（CLIP text embedding）用于 visual_embedding 召回
-        if let Some(visual_vec) = get_clip_text_embedding(&state, query_text).await {
-            if let Ok(hits) = search_visual_vec(&state.db, &visual_vec, per_channel).await {
-                tracing::info!("visual_vec (text) recall: {} hits", hits.len());
-                channels.push(hits);
+    
+        // 以图搜图模式
+        if !image_urls.is_empty() {
+            // 下载图片并获取 CLIP 视觉向量；多张参考图并发处理，单张失败时跳过
+            let results = futures::future::join_all(
+                image_urls.iter().map(|url| get_clip_image_embedding_from_url(&state, url)),
+            )
+            .await;
+            let mut vectors = Vec::with_capacity(results.len());
+            for result in results {
+                if let Some(vec) = result? {
+                    vectors.push(vec);
+                }
+            }
+            match normalized_centroid(&vectors) {
+                Some(visual_vec) => match search_visual_vec(&state.db, &visual_vec, per_channel, filter).await {
+                    Ok(hits) => {
+                        tracing::info!("visual_vec (image) recall: {} hits", hits.len());
+                        let min_sim = params.min_score.unwrap_or(state.config.min_visual_sim);
+                        channels.push(drop_below_score(hits, min_sim));
+                        channel_names.push("visual_vec_image");
+                    }
+                    Err(e) => {
+                        tracing::warn!("visual_vec (image) recall failed: {}", e);
+                        warnings.push("visual_vec_image recall failed: database error".to_string());
+                    }
+                },
+                None => warnings.push("visual_vec_image recall skipped: no reference image could be embedded".to_string()),
+            }
+            if !vectors.is_empty() && vectors.len() < image_urls.len() {
+                warnings.push(format!(
+                    "visual_vec_image: {} of {} reference images could not be embedded",
+                    image_urls.len() - vectors.len(),
+                    image_urls.len()
+                ));
             }
         }
-        
-        // 3. 全文检索召回
-        if let Ok(hits) = search_fts(&state.db, query_text, per_channel).await {
-            tracing::info!("fts recall: {} hits", hits.len());
-            channels.push(hits);
+
+        if !warnings.is_empty() && state.config.search_strict_recall {
+            tracing::warn!("Search degraded, failing under SEARCH_STRICT_RECALL: {:?}", warnings);
+            return Err(StatusCode::SERVICE_UNAVAILABLE);
         }
-    }
     
-    // 以图搜图模式
-    if let Some(ref image_url) = params.image_url {
-        // 下载图片并获取 CLIP 视觉向量
-        if let Some(visual_vec) = get_clip_image_embedding_from_url(&state, image_url).await {
-            if let Ok(hits) = search_visual_vec(&state.db, &visual_vec, per_channel).await {
-                tracing::info!("visual_vec (image) recall: {} hits", hits.len());
-                channels.push(hits);
-            }
+        if channels.is_empty() {
+            return Ok(Json(json!({ "items": [], "total": 0, "warnings": warnings })));
         }
-    }
-    
-    if channels.is_empty() {
-        return Ok(Json(json!({ "items": [], "total": 0 })));
-    }
     
-    // RRF 融合
-    let merged_ids = rrf_merge(channels, rrf_k, limit as usize);
-    tracing::info!("RRF merged: {} items", merged_ids.len());
+        // 调试信息：融合前各路的 (id, rank, score) 与融合分数
+        let debug_json = if debug {
+            let mut channels_json = serde_json::Map::new();
+            for (name, hits) in channel_names.iter().zip(&channels) {
+                let hits_json: Vec<serde_json::Value> = hits
+                    .iter()
+                    .map(|h| json!({ "id": h.id.to_string(), "rank": h.rank, "score": h.score }))
+                    .collect();
+                channels_json.insert(name.to_string(), json!(hits_json));
+            }
+            let fused_scores = if weighted { rrf_scores_weighted(&channels, rrf_k) } else { rrf_scores(&channels, rrf_k) };
+            let fused: Vec<serde_json::Value> = fused_scores
+                .into_iter()
+                .take(limit as usize)
+                .map(|(id, score)| json!({ "id": id.to_string(), "score": score }))
+                .collect();
+            Some(json!({ "channels": channels_json, "fused": fused }))
+        } else {
+            None
+        };
+
+        // RRF 融合
+        let merged_ids = if weighted {
+            rrf_merge_weighted(channels, rrf_k, limit as usize)
+        } else {
+            rrf_merge(channels, rrf_k, limit as usize)
+        };
+        tracing::info!("RRF merged: {} items", merged_ids.len());
+        // 降级的结果不缓存，依赖的服务恢复后下一次搜索即可拿到完整结果
+        if let Some(key) = cache_key.filter(|_| warnings.is_empty()) {
+            state.search_cache.insert(key, merged_ids.clone());
+        }
+        (merged_ids, debug_json)
+    };
     
     // 批量获取详情
     let rows = fetch_items_by_ids(&state.db, &merged_ids)
@@ -731,10 +2191,46 @@ async fn search_items(
             StatusCode::INTERNAL_SERVER_ERROR
         })?;
     
-    let mut items = Vec::new();
+    // 命中缓存时 id 列表可能早于最近的可见性修改，按当前状态再确认一次
+    let rows: Vec<PgRow> = rows
+        .into_iter()
+        .filter(|row| !public_only || row_is_public(row))
+        .collect();
+
+    let items = render_item_rows(&state, &rows).await;
+
+    let mut response = json!({
+        "items": items,
+        "total": items.len(),
+        "warnings": warnings,
+    });
+    if let Some(debug_json) = debug_json {
+        response["debug"] = debug_json;
+    }
+    Ok(Json(response))
+}
+
+/// 以图搜图单次最多的参考图数量（每张都要下载并调用一次 CLIP）
+const MAX_REFERENCE_IMAGES: usize = 8;
+
+fn row_is_public(row: &PgRow) -> bool {
+    row.try_get::<String, _>("visibility").is_ok_and(|v| v == "public")
+}
+
+/// 丢弃相似度低于阈值的命中；KNN 总会返回结果，即使没有任何真正相似的条目
+fn drop_below_score(hits: Vec<SearchHit>, min_score: f32) -> Vec<SearchHit> {
+    let before = hits.len();
+    let kept: Vec<SearchHit> = hits.into_iter().filter(|h| h.score >= min_score).collect();
+    if kept.len() < before {
+        tracing::info!("min_score {}: dropped {} of {} hits", min_score, before - kept.len(), before);
+    }
+    kept
+}
 
+/// 把 `fetch_items_by_ids` 取出的行渲染成列表用的 item 对象（预签名 URL + 标签对象），保持行顺序
+async fn render_item_rows(state: &AppState, rows: &[PgRow]) -> Vec<serde_json::Value> {
     let mut unique_tag_ids: HashSet<i32> = HashSet::new();
-    for row in &rows {
+    for row in rows {
         let ids: Vec<i32> = row.try_get("tags").unwrap_or_default();
         for id in ids {
             unique_tag_ids.insert(id);
@@ -742,26 +2238,23 @@ async fn search_items(
     }
     let mut unique_tag_ids_vec: Vec<i32> = unique_tag_ids.into_iter().collect();
     unique_tag_ids_vec.sort_unstable();
-    let tags_map = fetch_tags_map(&state, &unique_tag_ids_vec).await;
+    let tags_map = fetch_tags_map(state, &unique_tag_ids_vec).await;
 
-    for row in &rows {
+    let mut items = Vec::with_capacity(rows.len());
+    for row in rows {
         let id: i64 = row.get("id");
         let item_type: String = row.get("item_type");
-        
-        // 类型过滤
-        if let Some(ref filter_type) = params.item_type {
-            if &item_type != filter_type {
-                continue;
-            }
-        }
-        
+        let meta: serde_json::Value = row.try_get("meta").unwrap_or(json!({}));
         let content_text: Option<String> = row.get("content_text");
         let s3_key: Option<String> = row.get("s3_key");
         let thumbnail_key: Option<String> = row.get("thumbnail_key");
+        let storyboard_key: Option<String> = row.try_get("storyboard_key").ok().flatten();
         let created_at: Option<chrono::DateTime<chrono::Utc>> = row.try_get("created_at").ok();
-        let meta: serde_json::Value = row.try_get("meta").unwrap_or(json!({}));
         let tg_group_id: Option<i64> = row.try_get("tg_group_id").ok();
         let tags: Vec<i32> = row.try_get("tags").unwrap_or_default();
+        let visibility: String = row.try_get("visibility").unwrap_or_else(|_| "private".to_string());
+        let note: Option<String> = row.try_get("note").ok().flatten();
+        let lang: Option<String> = row.try_get("lang").ok().flatten();
         let tag_objects: Vec<serde_json::Value> = tags
             .iter()
             .filter_map(|id| tags_map.get(id).cloned())
@@ -779,39 +2272,442 @@ async fn search_items(
             None
         };
 
+        let storyboard_url = if let Some(key) = storyboard_key.as_ref() {
+            state.s3_signing_client.presign_get(key, 3600, None).await.ok()
+        } else {
+            None
+        };
+
         items.push(json!({
-            "id": id,
+            "id": id.to_string(),
             "type": item_type,
             "content": content_text,
             "s3_url": s3_url,
             "thumbnail_url": thumbnail_url,
+            "storyboard_url": storyboard_url,
+            "storyboard": meta.get("storyboard"),
             "created_at": created_at,
             "width": meta.get("width"),
             "height": meta.get("height"),
+            "source_name": item_source_name(&meta),
+            "summary": meta.get("summary"),
+            "link": link_preview_json(&meta),
+            "source_kind": meta.get("source_kind"),
+            "lang": lang,
             "tg_group_id": tg_group_id.map(|v| v.to_string()),
             "tags": tags,
             "tag_objects": tag_objects,
+            "visibility": visibility,
+            "note": note,
         }));
     }
+    items
+}
+
+#[derive(Deserialize)]
+struct BatchItemsRequest {
+    #[serde(deserialize_with = "deserialize_ids")]
+    ids: Vec<i64>,
+}
+
+/// 按 id 批量获取 item，按请求顺序返回；不存在的 id 直接省略
+async fn batch_get_items(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<BatchItemsRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    // 每个 item 都要预签名，限制单次数量
+    if req.ids.len() > state.config.max_page_size as usize {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    let mut seen: HashSet<i64> = HashSet::new();
+    let ids: Vec<i64> = req.ids.into_iter().filter(|id| seen.insert(*id)).collect();
+
+    let rows = fetch_items_by_ids(&state.db, &ids)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to fetch batch items: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let rows: Vec<PgRow> = if public_only(&state, &headers) {
+        rows.into_iter().filter(row_is_public).collect()
+    } else {
+        rows
+    };
+
+    let items = render_item_rows(&state, &rows).await;
+    Ok(Json(json!({
+        "items": items,
+        "total": items.len()
+    })))
+}
+
+#[derive(Deserialize)]
+struct RandomItemParams {
+    #[serde(rename = "type")]
+    item_type: Option<String>,
+    tag_id: Option<i32>,
+    album: Option<bool>, // 同时返回同一组图的所有成员
+}
+
+/// 随机返回一个 item（「随便看看」）；筛选条件下没有 item 时 404
+async fn get_random_item(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<RandomItemParams>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let public_only = public_only(&state, &headers);
+    let id: Option<i64> = sqlx::query_scalar(
+        r#"
+        SELECT id FROM items
+        WHERE ($1::text IS NULL OR item_type = $1)
+          AND ($2::int IS NULL OR tags @> ARRAY[$2::int])
+          AND (NOT $3 OR visibility = 'public')
+        ORDER BY random()
+        LIMIT 1
+        "#,
+    )
+    .bind(params.item_type.as_deref())
+    .bind(params.tag_id)
+    .bind(public_only)
+    .fetch_optional(&state.db)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to pick random item: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let id = id.ok_or(StatusCode::NOT_FOUND)?;
+
+    let rows = fetch_items_by_ids(&state.db, &[id]).await.map_err(|e| {
+        tracing::error!("Failed to fetch item {}: {}", id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let Some(row) = rows.first() else { return Err(StatusCode::NOT_FOUND); };
+
+    let album_rows: Vec<PgRow> = match row.try_get::<Option<i64>, _>("tg_group_id").ok().flatten() {
+        Some(gid) if params.album.unwrap_or(false) => sqlx::query(
+            r#"
+            SELECT id, item_type, content_text, s3_key, thumbnail_key, storyboard_key,
+                   created_at, meta, tags, tg_group_id, visibility, note, lang
+            FROM items
+            WHERE tg_group_id = $1 AND (NOT $2 OR visibility = 'public')
+            ORDER BY id
+            "#,
+        )
+        .bind(gid)
+        .bind(public_only)
+        .fetch_all(&state.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to fetch album {}: {}", gid, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?,
+        _ => Vec::new(),
+    };
+
+    let item = render_item_rows(&state, &rows).await.into_iter().next();
+    let album = render_item_rows(&state, &album_rows).await;
+    Ok(Json(json!({
+        "item": item,
+        "album": album,
+    })))
+}
+
+#[derive(Deserialize)]
+struct DuplicatesParams {
+    max_distance: Option<i32>, // 汉明距离上限，默认 PHASH_MAX_DISTANCE
+    limit: Option<i64>,
+}
+
+/// 与该 item 感知哈希相近的其他 items（重新压缩、缩放后再次转发的同一张图），按距离升序；
+/// 每个 item 附带 phash_distance。没有感知哈希的 item（文本、旧数据）返回空列表
+async fn get_item_duplicates(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+    Query(params): Query<DuplicatesParams>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let public_only = public_only(&state, &headers);
+    let row = sqlx::query("SELECT phash FROM items WHERE id = $1 AND (NOT $2 OR visibility = 'public')")
+        .bind(id)
+        .bind(public_only)
+        .fetch_optional(&state.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to fetch item {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let Some(phash) = row.get::<Option<i64>, _>("phash") else {
+        return Ok(Json(json!({ "items": [], "total": 0 })));
+    };
+    let max_distance = params.max_distance.unwrap_or(state.config.phash_max_distance).clamp(0, 64);
+    let limit = clamp_limit(params.limit, state.config.default_page_size, state.config.max_page_size);
+
+    let found = find_near_duplicates(&state.db, phash, max_distance, Some(id), limit)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to find near duplicates of item {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let distances: HashMap<i64, i32> = found.iter().copied().collect();
+    let ids: Vec<i64> = found.iter().map(|(dup_id, _)| *dup_id).collect();
 
+    let rows = fetch_items_by_ids(&state.db, &ids)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to fetch duplicate items: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let rows: Vec<PgRow> = if public_only {
+        rows.into_iter().filter(row_is_public).collect()
+    } else {
+        rows
+    };
+
+    let mut items = render_item_rows(&state, &rows).await;
+    for (item, row) in items.iter_mut().zip(&rows) {
+        let dup_id: i64 = row.get("id");
+        item["phash_distance"] = json!(distances.get(&dup_id));
+    }
     Ok(Json(json!({
         "items": items,
         "total": items.len()
     })))
 }
 
+/// 文本查询的三路召回：text_vec（BGE-M3）、visual_vec（CLIP 文本向量）、fts（无命中时换成 trgm）
+/// 返回 (通道名, 命中列表)；某一路失败时跳过，并在 warnings 中说明原因
+async fn recall_text_channels(
+    state: &AppState,
+    query_text: &str,
+    fts_match: FtsMatch,
+    per_channel: i64,
+    filter: SearchFilter<'_>,
+    warnings: &mut Vec<String>,
+) -> Vec<(&'static str, Vec<SearchHit>)> {
+    let mut channels = Vec::new();
+
+    // 1. 获取文本向量（BGE-M3）用于 text_embedding 召回
+    match get_text_embedding(state, query_text).await {
+        Some(text_vec) => match search_text_vec(&state.db, &text_vec, per_channel, filter).await {
+            Ok(hits) => {
+                tracing::info!("text_vec recall: {} hits", hits.len());
+                channels.push(("text_vec", hits));
+            }
+            Err(e) => {
+                tracing::warn!("text_vec recall failed: {}", e);
+                warnings.push("text_vec recall failed: database error".to_string());
+            }
+        },
+        None => warnings.push("text_vec recall skipped: embedding service unavailable".to_string()),
+    }
+
+    // 2. 获取文本的视觉向量（CLIP text embedding）用于 visual_embedding 召回
+    match get_clip_text_embedding(state, query_text).await {
+        Some(visual_vec) => match search_visual_vec(&state.db, &visual_vec, per_channel, filter).await {
+            Ok(hits) => {
+                tracing::info!("visual_vec (text) recall: {} hits", hits.len());
+                channels.push(("visual_vec", hits));
+            }
+            Err(e) => {
+                tracing::warn!("visual_vec recall failed: {}", e);
+                warnings.push("visual_vec recall failed: database error".to_string());
+            }
+        },
+        None => warnings.push("visual_vec recall skipped: CLIP unavailable".to_string()),
+    }
+
+    // 3. 全文检索召回；默认模式没有命中（拼写错误、部分词）时用三元组模糊匹配兜底，
+    //    显式指定 phrase/any/all 时按字面要求匹配，不做模糊兜底
+    let fts_hits = match search_fts(&state.db, query_text, fts_match, per_channel, filter).await {
+        Ok(hits) => hits,
+        Err(e) => {
+            tracing::warn!("fts recall failed: {}", e);
+            warnings.push("fts recall failed: database error".to_string());
+            Vec::new()
+        }
+    };
+    tracing::info!("fts recall: {} hits", fts_hits.len());
+    if fts_hits.is_empty() && fts_match == FtsMatch::Web {
+        if let Ok(hits) = search_trgm(&state.db, query_text, per_channel, filter).await {
+            tracing::info!("trgm recall: {} hits", hits.len());
+            channels.push(("trgm", hits));
+        }
+    } else if !fts_hits.is_empty() {
+        channels.push(("fts", fts_hits));
+    }
+
+    channels
+}
+
+/// recall_text_channels 之后按相似度阈值过滤向量通道；min_score 缺省时用 MIN_TEXT_SIM / MIN_VISUAL_SIM
+async fn recall_text_filtered(
+    state: &AppState,
+    query_text: &str,
+    fts_match: FtsMatch,
+    per_channel: i64,
+    min_score: Option<f32>,
+    filter: SearchFilter<'_>,
+    warnings: &mut Vec<String>,
+) -> Vec<(&'static str, Vec<SearchHit>)> {
+    recall_text_channels(state, query_text, fts_match, per_channel, filter, warnings)
+        .await
+        .into_iter()
+        .map(|(name, hits)| {
+            let min_sim = match name {
+                "text_vec" => Some(min_score.unwrap_or(state.config.min_text_sim)),
+                "visual_vec" => Some(min_score.unwrap_or(state.config.min_visual_sim)),
+                _ => None, // FTS 的 ts_rank 不是相似度，不参与阈值过滤
+            };
+            match min_sim {
+                Some(min_sim) => (name, drop_below_score(hits, min_sim)),
+                None => (name, hits),
+            }
+        })
+        .collect()
+}
+
+/// 文本混合检索（三路召回 + RRF 融合，参数取默认值），返回前 limit 个 item id；
+/// 供 Telegram inline query 复用与 /api/v1/search 相同的检索逻辑；public_only 时只召回 public item
+pub async fn text_search_ids(state: &AppState, query_text: &str, limit: usize, public_only: bool) -> Vec<i64> {
+    let fts_match = FtsMatch::parse(&state.config.fts_query_mode).unwrap_or(FtsMatch::Web);
+    let filter = SearchFilter { public_only, ..Default::default() };
+    let channels: Vec<Vec<SearchHit>> =
+        recall_text_filtered(state, query_text, fts_match, state.config.per_channel_recall, None, filter, &mut Vec::new())
+            .await
+            .into_iter()
+            .map(|(_, hits)| hits)
+            .collect();
+    rrf_merge(channels, 60.0, limit)
+}
+
+/// 解析 match 参数，缺省为 FTS_QUERY_MODE
+fn parse_fts_match(state: &AppState, value: Option<&str>) -> Result<FtsMatch, StatusCode> {
+    match value.map(str::trim).filter(|v| !v.is_empty()) {
+        // 配置已在启动时校验
+        None => Ok(FtsMatch::parse(&state.config.fts_query_mode).unwrap_or(FtsMatch::Web)),
+        Some(v) => FtsMatch::parse(v).ok_or(StatusCode::BAD_REQUEST),
+    }
+}
+
+#[derive(Deserialize)]
+struct ExplainParams {
+    q: String,
+    limit: Option<i64>,
+    #[serde(rename = "match")]
+    match_mode: Option<String>,
+}
+
+/// 搜索诊断：返回每一路召回的原始 (id, rank, score) 以及 RRF 融合后的顺序
+/// 暴露内部排序细节，需要 API token
+async fn explain_search(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<ExplainParams>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    require_api_token(&state, &headers)?;
+
+    let limit = clamp_limit(params.limit, state.config.default_search_limit, state.config.max_search_limit);
+    let per_channel = state.config.per_channel_recall;
+    let rrf_k = 60.0;
+
+    let fts_match = parse_fts_match(&state, params.match_mode.as_deref())?;
+    let mut warnings = Vec::new();
+    let recalled = recall_text_channels(&state, &params.q, fts_match, per_channel, SearchFilter::default(), &mut warnings).await;
+
+    let mut channels_json = serde_json::Map::new();
+    for (name, hits) in &recalled {
+        let hits_json: Vec<serde_json::Value> = hits
+            .iter()
+            .map(|h| json!({ "id": h.id.to_string(), "rank": h.rank, "score": h.score }))
+            .collect();
+        channels_json.insert(name.to_string(), json!(hits_json));
+    }
+
+    let channels: Vec<Vec<SearchHit>> = recalled.into_iter().map(|(_, hits)| hits).collect();
+    let fused: Vec<serde_json::Value> = rrf_scores(&channels, rrf_k)
+        .into_iter()
+        .take(limit as usize)
+        .map(|(id, score)| json!({ "id": id.to_string(), "score": score }))
+        .collect();
+
+    Ok(Json(json!({
+        "query": params.q,
+        "channels": channels_json,
+        "fused": fused,
+        "warnings": warnings,
+    })))
+}
+
+/// 库中实际存在的 item_type 及各自数量（按数量降序），供前端动态生成类型筛选
+async fn list_item_types(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let rows = sqlx::query(
+        r#"
+        SELECT item_type, COUNT(*)::bigint AS count
+        FROM items
+        WHERE NOT $1 OR visibility = 'public'
+        GROUP BY item_type
+        ORDER BY count DESC, item_type ASC
+        "#,
+    )
+    .bind(public_only(&state, &headers))
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to list item types: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let types: Vec<serde_json::Value> = rows
+        .iter()
+        .map(|row| {
+            let item_type: String = row.get("item_type");
+            let count: i64 = row.get("count");
+            json!({ "item_type": item_type, "count": count })
+        })
+        .collect();
+
+    Ok(Json(json!({ "types": types })))
+}
+
 // ============ Tags API ============
 
 async fn list_tags(
     State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<ListTagsParams>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
+    let public_only = public_only(&state, &headers);
+    let limit = params
+        .limit
+        .map(|l| clamp_limit(Some(l), state.config.default_page_size, state.config.max_page_size));
+    let with_counts = params.with_counts.unwrap_or(true);
+
+    // 计数一次聚合完成；with_counts=false 时子查询被常量条件短路。未授权时只统计公开 item
     let rows = sqlx::query(
         r#"
-        SELECT id, icon_type, icon_value, label, asset_url, asset_mime
-        FROM tags
-        ORDER BY id ASC
+        SELECT t.id, t.icon_type, t.icon_value, t.label, t.asset_url, t.asset_mime,
+               COALESCE(c.count, 0) AS count
+        FROM tags t
+        LEFT JOIN (
+            SELECT tag_id, COUNT(*)::bigint AS count
+            FROM items, unnest(items.tags) AS tag_id
+            WHERE $2 AND (NOT $3 OR items.visibility = 'public')
+            GROUP BY tag_id
+        ) c ON c.tag_id = t.id
+        ORDER BY t.id ASC
+        LIMIT $1
         "#,
     )
+    .bind(limit)
+    .bind(with_counts)
+    .bind(public_only)
     .fetch_all(&state.db)
     .await
     .map_err(|e| {
@@ -827,17 +2723,22 @@ async fn list_tags(
         let label: Option<String> = row.try_get("label").ok();
         let asset_url_raw: Option<String> = row.try_get("asset_url").ok();
         let asset_mime: Option<String> = row.try_get("asset_mime").ok();
+        let count: i64 = row.try_get("count").unwrap_or(0);
 
         let asset_url = resolve_proxy_url(&state, asset_url_raw).await;
 
-        tags.push(json!({
+        let mut tag = json!({
             "id": id,
             "icon_type": icon_type,
             "icon_value": icon_value,
             "label": label,
             "asset_url": asset_url,
             "asset_mime": asset_mime,
-        }));
+        });
+        if with_counts {
+            tag["count"] = json!(count);
+        }
+        tags.push(tag);
     }
 
     Ok(Json(json!({ "tags": tags })))
@@ -885,15 +2786,60 @@ async fn update_tag(
     Path(id): Path<i32>,
     Json(req): Json<UpdateTagRequest>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    sqlx::query("UPDATE tags SET label = $1 WHERE id = $2")
-        .bind(req.label.as_deref())
+    let current = sqlx::query("SELECT icon_type, icon_value FROM tags WHERE id = $1")
         .bind(id)
-        .execute(&state.db)
+        .fetch_optional(&state.db)
         .await
         .map_err(|e| {
-            tracing::error!("Failed to update tag {}: {}", id, e);
+            tracing::error!("Failed to load tag {}: {}", id, e);
             StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let current_type: String = current.get("icon_type");
+    let current_value: String = current.get("icon_value");
+
+    let icon_type = req.icon_type.map(|t| t.trim().to_string()).unwrap_or_else(|| current_type.clone());
+    let icon_value = req.icon_value.map(|v| v.trim().to_string()).unwrap_or_else(|| current_value.clone());
+
+    if icon_type != "emoji" && icon_type != "tmoji" {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if icon_value.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    // 图标变了，旧的自定义表情资源不再对应，清空后由 bot 下次遇到时重新拉取
+    let icon_changed = icon_type != current_type || icon_value != current_value;
+    let label_set = req.label.is_some();
+    let label = req.label.flatten();
+
+    sqlx::query(
+        r#"
+        UPDATE tags
+        SET icon_type = $2,
+            icon_value = $3,
+            label = CASE WHEN $4 THEN $5 ELSE label END,
+            asset_url = CASE WHEN $6 THEN NULL ELSE asset_url END,
+            asset_mime = CASE WHEN $6 THEN NULL ELSE asset_mime END
+        WHERE id = $1
+        "#,
+    )
+    .bind(id)
+    .bind(&icon_type)
+    .bind(&icon_value)
+    .bind(label_set)
+    .bind(label.as_deref())
+    .bind(icon_changed)
+    .execute(&state.db)
+    .await
+    .map_err(|e| {
+        // (icon_type, icon_value) 已被其他标签占用
+        if e.as_database_error().is_some_and(|d| d.is_unique_violation()) {
+            return StatusCode::CONFLICT;
+        }
+        tracing::error!("Failed to update tag {}: {}", id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
 
     Ok(Json(json!({ "success": true })))
 }
@@ -937,81 +2883,694 @@ async fn delete_tag(
     Ok(Json(json!({ "success": true })))
 }
 
-/// 获取文本的 BGE-M3 向量（用于 text_embedding 召回）
-async fn get_text_embedding(state: &AppState, text: &str) -> Option<Vec<f32>> {
-    let embedding_url = format!("{}/embeddings", state.config.embedding_api_base);
-    let body = serde_json::json!({
-        "model": state.config.embedding_model,
-        "input": text
-    });
-    
-    let res = state.http_client
-        .post(&embedding_url)
-        .header("Authorization", format!("Bearer {}", state.config.embedding_api_key))
-        .header("Content-Type", "application/json")
-        .json(&body)
-        .send()
-        .await
-        .ok()?;
-    
-    if !res.status().is_success() {
-        return None;
+/// 批量打标签的单次上限
+const BULK_TAG_MAX_ITEMS: usize = 1000;
+
+/// 给一批 item 加上同一个标签；开启 ALBUM_TAG_PROPAGATE 时同一相册的其余 item 一并加上。
+/// affected 只计入实际发生变化的 item（已带该标签的不算）
+async fn apply_tag_to_items(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<i32>,
+    Json(req): Json<BulkTagRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    if state.config.restrict_public_reads {
+        require_api_token(&state, &headers)?;
     }
-    
-    let json: serde_json::Value = res.json().await.ok()?;
-    let arr = json.get("data")?.get(0)?.get("embedding")?.as_array()?;
-    
-    Some(arr.iter().map(|v| v.as_f64().unwrap_or(0.0) as f32).collect())
-}
+    check_bulk_tag_request(&state, id, &req).await?;
 
-/// 获取文本的 CLIP 视觉向量（用于文本搜图）
-async fn get_clip_text_embedding(state: &AppState, text: &str) -> Option<Vec<f32>> {
-    let clip_url = format!("{}/embed_text", state.config.clip_api_url);
-    
-    let res = state.http_client
-        .post(&clip_url)
-        .query(&[("text", text)])
-        .send()
+    let affected = tag_items(&state.db, &req.item_ids, id, state.config.album_tag_propagate)
         .await
-        .ok()?;
-    
-    if !res.status().is_success() {
-        tracing::warn!("CLIP text embedding failed: {}", res.status());
-        return None;
-    }
-    
-    let json: serde_json::Value = res.json().await.ok()?;
-    let arr = json.get("embedding")?.as_array()?;
-    
-    Some(arr.iter().map(|v| v.as_f64().unwrap_or(0.0) as f32).collect())
+        .map_err(|e| {
+            tracing::error!("Failed to apply tag {} to items: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(json!({ "success": true, "affected": affected })))
+}
+
+/// 从一批 item 上移除同一个标签，相册传播规则同 apply_tag_to_items
+async fn remove_tag_from_items(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<i32>,
+    Json(req): Json<BulkTagRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    if state.config.restrict_public_reads {
+        require_api_token(&state, &headers)?;
+    }
+    check_bulk_tag_request(&state, id, &req).await?;
+
+    let affected = untag_items(&state.db, &req.item_ids, id, state.config.album_tag_propagate)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to remove tag {} from items: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(json!({ "success": true, "affected": affected })))
+}
+
+/// 批量标签操作的前置检查：id 列表非空且不超过上限，标签存在
+async fn check_bulk_tag_request(state: &AppState, id: i32, req: &BulkTagRequest) -> Result<(), StatusCode> {
+    if req.item_ids.is_empty() || req.item_ids.len() > BULK_TAG_MAX_ITEMS {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let exists: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM tags WHERE id = $1)")
+        .bind(id)
+        .fetch_one(&state.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to look up tag {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if !exists {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(())
+}
+
+// ============ Collections API ============
+
+/// 合集列表，附带成员数与封面缩略图；未指定封面时取排在最前的成员
+async fn list_collections(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let public_only = public_only(&state, &headers);
+    let rows = sqlx::query(
+        r#"
+        SELECT c.id, c.name, c.description, c.cover_item_id, c.created_at, c.updated_at,
+               (SELECT COUNT(*) FROM collection_items ci WHERE ci.collection_id = c.id) AS item_count,
+               cover.id AS cover_id, cover.thumbnail_key AS cover_thumbnail_key, cover.visibility AS cover_visibility
+        FROM collections c
+        LEFT JOIN items cover ON cover.id = COALESCE(
+            c.cover_item_id,
+            (SELECT ci.item_id FROM collection_items ci WHERE ci.collection_id = c.id ORDER BY ci.position, ci.item_id LIMIT 1)
+        )
+        ORDER BY c.id DESC
+        "#,
+    )
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to list collections: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let mut collections = Vec::with_capacity(rows.len());
+    for row in rows {
+        let id: i64 = row.get("id");
+        let name: String = row.get("name");
+        let description: Option<String> = row.try_get("description").ok().flatten();
+        let cover_item_id: Option<i64> = row.try_get("cover_item_id").ok().flatten();
+        let created_at: Option<chrono::DateTime<chrono::Utc>> = row.try_get("created_at").ok();
+        let updated_at: Option<chrono::DateTime<chrono::Utc>> = row.try_get("updated_at").ok();
+        let item_count: i64 = row.try_get("item_count").unwrap_or(0);
+        let cover_visibility: Option<String> = row.try_get("cover_visibility").ok().flatten();
+        // 未授权的公开读取不暴露私有 item 的缩略图
+        let cover_visible = !public_only || cover_visibility.as_deref() == Some("public");
+        let cover_thumbnail_key: Option<String> = row
+            .try_get("cover_thumbnail_key")
+            .ok()
+            .flatten()
+            .filter(|_| cover_visible);
+
+        let cover_url = if let Some(key) = cover_thumbnail_key.as_ref() {
+            state.s3_signing_client.presign_get(key, 3600, None).await.ok()
+        } else {
+            None
+        };
+
+        collections.push(json!({
+            "id": id.to_string(),
+            "name": name,
+            "description": description,
+            "cover_item_id": cover_item_id.map(|v| v.to_string()),
+            "cover_url": cover_url,
+            "item_count": item_count,
+            "created_at": created_at,
+            "updated_at": updated_at,
+        }));
+    }
+
+    Ok(Json(json!({ "collections": collections })))
+}
+
+async fn create_collection(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<CreateCollectionRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    require_api_token(&state, &headers)?;
+    let name = req.name.trim().to_string();
+    if name.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let description = req.description.map(|d| d.trim().to_string()).filter(|d| !d.is_empty());
+
+    let id: i64 = sqlx::query_scalar("INSERT INTO collections (name, description) VALUES ($1, $2) RETURNING id")
+        .bind(&name)
+        .bind(description.as_deref())
+        .fetch_one(&state.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to create collection: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(json!({ "id": id.to_string() })))
+}
+
+/// 修改名称、描述或封面；封面只能从合集成员中选择
+async fn update_collection(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+    Json(req): Json<UpdateCollectionRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    require_api_token(&state, &headers)?;
+    let name = req.name.map(|n| n.trim().to_string());
+    if name.as_deref() == Some("") {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    if let Some(Some(cover_item_id)) = req.cover_item_id {
+        let is_member: bool = sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM collection_items WHERE collection_id = $1 AND item_id = $2)",
+        )
+        .bind(id)
+        .bind(cover_item_id)
+        .fetch_one(&state.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to check collection {} membership: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        if !is_member {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+
+    let description_set = req.description.is_some();
+    let description = req.description.flatten().map(|d| d.trim().to_string()).filter(|d| !d.is_empty());
+    let cover_set = req.cover_item_id.is_some();
+    let cover_item_id = req.cover_item_id.flatten();
+
+    let result = sqlx::query(
+        r#"
+        UPDATE collections SET
+            name = COALESCE($2, name),
+            description = CASE WHEN $3 THEN $4 ELSE description END,
+            cover_item_id = CASE WHEN $5 THEN $6 ELSE cover_item_id END,
+            updated_at = NOW()
+        WHERE id = $1
+        "#,
+    )
+    .bind(id)
+    .bind(name.as_deref())
+    .bind(description_set)
+    .bind(description.as_deref())
+    .bind(cover_set)
+    .bind(cover_item_id)
+    .execute(&state.db)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to update collection {}: {}", id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    if result.rows_affected() == 0 {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    Ok(Json(json!({ "success": true })))
+}
+
+/// 删除合集本身，成员 item 不受影响
+async fn delete_collection(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    require_api_token(&state, &headers)?;
+    let result = sqlx::query("DELETE FROM collections WHERE id = $1")
+        .bind(id)
+        .execute(&state.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to delete collection {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    if result.rows_affected() == 0 {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    Ok(Json(json!({ "success": true })))
+}
+
+/// 按请求顺序追加到合集末尾；已在合集中或不存在的 item 忽略
+async fn add_collection_items(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+    Json(req): Json<AddCollectionItemsRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    require_api_token(&state, &headers)?;
+    ensure_collection_exists(&state, id).await?;
+
+    let result = sqlx::query(
+        r#"
+        INSERT INTO collection_items (collection_id, item_id, position)
+        SELECT $1, req.item_id, base.max_position + req.ord::int
+        FROM unnest($2::bigint[]) WITH ORDINALITY AS req(item_id, ord)
+        JOIN items i ON i.id = req.item_id
+        CROSS JOIN (
+            SELECT COALESCE(MAX(position), 0) AS max_position FROM collection_items WHERE collection_id = $1
+        ) base
+        ORDER BY req.ord
+        ON CONFLICT (collection_id, item_id) DO NOTHING
+        "#,
+    )
+    .bind(id)
+    .bind(&req.item_ids)
+    .execute(&state.db)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to add items to collection {}: {}", id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    touch_collection(&state, id).await;
+
+    Ok(Json(json!({ "success": true, "added": result.rows_affected() })))
+}
+
+async fn ensure_collection_exists(state: &AppState, id: i64) -> Result<(), StatusCode> {
+    let exists: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM collections WHERE id = $1)")
+        .bind(id)
+        .fetch_one(&state.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to load collection {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if exists {
+        Ok(())
+    } else {
+        Err(StatusCode::NOT_FOUND)
+    }
+}
+
+async fn touch_collection(state: &AppState, id: i64) {
+    let _ = sqlx::query("UPDATE collections SET updated_at = NOW() WHERE id = $1")
+        .bind(id)
+        .execute(&state.db)
+        .await;
+}
+
+/// 合集成员按 position 顺序返回，游标为上一页最后一条的 position
+async fn list_collection_items(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+    Query(params): Query<CollectionItemsParams>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    ensure_collection_exists(&state, id).await?;
+    let public_only = public_only(&state, &headers);
+    let limit = clamp_limit(params.limit, state.config.default_page_size, state.config.max_page_size);
+
+    let rows = sqlx::query(
+        r#"
+        SELECT i.id, i.item_type, i.content_text, i.s3_key, i.thumbnail_key, i.storyboard_key, i.created_at,
+               i.meta, i.tg_group_id, i.tags, i.visibility, i.note, i.lang, ci.position
+        FROM collection_items ci
+        JOIN items i ON i.id = ci.item_id
+        WHERE ci.collection_id = $1
+          AND ($2::int IS NULL OR ci.position > $2)
+          AND (NOT $3 OR i.visibility = 'public')
+        ORDER BY ci.position, ci.item_id
+        LIMIT $4
+        "#,
+    )
+    .bind(id)
+    .bind(params.cursor)
+    .bind(public_only)
+    .bind(limit)
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to list items of collection {}: {}", id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let mut items = render_item_rows(&state, &rows).await;
+    for (item, row) in items.iter_mut().zip(&rows) {
+        item["position"] = json!(row.get::<i32, _>("position"));
+    }
+    let next_cursor = if rows.len() as i64 == limit {
+        rows.last().map(|row| row.get::<i32, _>("position"))
+    } else {
+        None
+    };
+
+    Ok(Json(json!({
+        "items": items,
+        "next_cursor": next_cursor,
+    })))
+}
+
+/// 调整合集顺序：请求中的成员按给定顺序排到最前（只传一个即为置顶），其余成员保持原有相对顺序；
+/// position 重新从 1 连续编号
+async fn reorder_collection_items(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+    Json(req): Json<ReorderCollectionRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    require_api_token(&state, &headers)?;
+    ensure_collection_exists(&state, id).await?;
+
+    sqlx::query(
+        r#"
+        WITH req AS (
+            SELECT item_id, MIN(ord) AS ord
+            FROM unnest($2::bigint[]) WITH ORDINALITY AS r(item_id, ord)
+            GROUP BY item_id
+        ),
+        ordered AS (
+            SELECT ci.item_id,
+                   ROW_NUMBER() OVER (ORDER BY req.ord NULLS LAST, ci.position, ci.item_id)::int AS new_position
+            FROM collection_items ci
+            LEFT JOIN req ON req.item_id = ci.item_id
+            WHERE ci.collection_id = $1
+        )
+        UPDATE collection_items ci
+        SET position = ordered.new_position
+        FROM ordered
+        WHERE ci.collection_id = $1 AND ci.item_id = ordered.item_id
+        "#,
+    )
+    .bind(id)
+    .bind(&req.item_ids)
+    .execute(&state.db)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to reorder collection {}: {}", id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    touch_collection(&state, id).await;
+
+    Ok(Json(json!({ "success": true })))
+}
+
+/// 从合集移除成员（item 本身不删除）；被移除的成员若是封面则恢复默认封面
+async fn remove_collection_item(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((id, item_id)): Path<(i64, i64)>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    require_api_token(&state, &headers)?;
+
+    let result = sqlx::query("DELETE FROM collection_items WHERE collection_id = $1 AND item_id = $2")
+        .bind(id)
+        .bind(item_id)
+        .execute(&state.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to remove item {} from collection {}: {}", item_id, id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    if result.rows_affected() == 0 {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let _ = sqlx::query(
+        r#"
+        UPDATE collections
+        SET cover_item_id = CASE WHEN cover_item_id = $2 THEN NULL ELSE cover_item_id END,
+            updated_at = NOW()
+        WHERE id = $1
+        "#,
+    )
+    .bind(id)
+    .bind(item_id)
+    .execute(&state.db)
+    .await;
+
+    Ok(Json(json!({ "success": true })))
+}
+
+// ============ Maintenance API ============
+
+#[derive(Deserialize, Default)]
+struct ReindexRequest {
+    #[serde(rename = "type")]
+    item_type: Option<String>, // 只重建某一类型
+    ids: Option<Vec<i64>>,     // 只重建指定 items
+    rate_per_min: Option<i64>, // 覆盖默认的放行速率
+    force: Option<bool>,       // 文本未变也重新生成文本向量（更换 embedding 模型或前缀后使用）
+}
+
+/// 为 items 批量入队重建任务（重新 OCR / 生成向量），返回入队数量
+/// 任务的 created_at 按速率错开，worker 只领取 created_at <= NOW() 的任务，以此限流
+async fn reindex_items(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    req: Option<Json<ReindexRequest>>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    require_api_token(&state, &headers)?;
+
+    let req = req.map(|Json(r)| r).unwrap_or_default();
+    let rate_per_min = req.rate_per_min.unwrap_or(state.config.reindex_rate_per_min).max(1);
+    let interval_secs = 60.0 / rate_per_min as f64;
+
+    let result = sqlx::query(
+        r#"
+        INSERT INTO tasks (item_id, bot_chat_id, bot_message_id, status, payload, created_at)
+        SELECT i.id, 0, 0, 'pending',
+               jsonb_build_object('reprocess_item_id', i.id, 'force', $4::boolean),
+               NOW() + make_interval(secs => (ROW_NUMBER() OVER (ORDER BY i.id) - 1) * $3::float8)
+        FROM items i
+        WHERE ($1::text IS NULL OR i.item_type = $1)
+          AND ($2::bigint[] IS NULL OR i.id = ANY($2))
+          AND NOT EXISTS (
+              SELECT 1 FROM tasks t
+              WHERE t.status IN ('pending', 'processing')
+                AND t.payload->>'reprocess_item_id' = i.id::text
+          )
+        "#,
+    )
+    .bind(req.item_type.as_deref())
+    .bind(req.ids.as_deref())
+    .bind(interval_secs)
+    .bind(req.force.unwrap_or(false))
+    .execute(&state.db)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to enqueue reindex tasks: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let enqueued = result.rows_affected();
+    tracing::info!("Enqueued {} reindex tasks at {}/min", enqueued, rate_per_min);
+
+    Ok(Json(json!({
+        "enqueued": enqueued,
+        "rate_per_min": rate_per_min,
+    })))
+}
+
+#[derive(Deserialize)]
+struct IncompleteParams {
+    cursor: Option<i64>, // 上一页最后一条的 id
+    limit: Option<i64>,
+}
+
+/// 处理不完整的 items：缺文本向量、图片/视频/带配图的链接缺视觉向量、或 searchable_text 为空（OCR 没有结果）。
+/// 返回各类缺失的总数与按 id 倒序分页的列表，ids 可直接交给 POST /api/v1/maintenance/reindex
+async fn list_incomplete_items(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<IncompleteParams>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    require_api_token(&state, &headers)?;
+    let limit = clamp_limit(params.limit, state.config.default_page_size, state.config.max_page_size);
+
+    let counts = sqlx::query(
+        r#"
+        SELECT COUNT(*) FILTER (WHERE text_embedding IS NULL) AS missing_text_embedding,
+               COUNT(*) FILTER (WHERE (item_type IN ('image', 'video') OR (item_type = 'link' AND COALESCE(s3_key, thumbnail_key) IS NOT NULL)) AND visual_embedding IS NULL) AS missing_visual_embedding,
+               COUNT(*) FILTER (WHERE COALESCE(searchable_text, '') = '') AS empty_searchable_text,
+               COUNT(*) FILTER (
+                   WHERE text_embedding IS NULL
+                      OR ((item_type IN ('image', 'video') OR (item_type = 'link' AND COALESCE(s3_key, thumbnail_key) IS NOT NULL)) AND visual_embedding IS NULL)
+                      OR COALESCE(searchable_text, '') = ''
+               ) AS total
+        FROM items
+        "#,
+    )
+    .fetch_one(&state.db)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to count incomplete items: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let rows = sqlx::query(
+        r#"
+        SELECT id, item_type, created_at, processed_at,
+               text_embedding IS NULL AS missing_text_embedding,
+               ((item_type IN ('image', 'video') OR (item_type = 'link' AND COALESCE(s3_key, thumbnail_key) IS NOT NULL)) AND visual_embedding IS NULL) AS missing_visual_embedding,
+               COALESCE(searchable_text, '') = '' AS empty_searchable_text,
+               meta->>'media_error' AS media_error
+        FROM items
+        WHERE ($1::bigint IS NULL OR id < $1)
+          AND (text_embedding IS NULL
+               OR ((item_type IN ('image', 'video') OR (item_type = 'link' AND COALESCE(s3_key, thumbnail_key) IS NOT NULL)) AND visual_embedding IS NULL)
+               OR COALESCE(searchable_text, '') = '')
+        ORDER BY id DESC
+        LIMIT $2
+        "#,
+    )
+    .bind(params.cursor)
+    .bind(limit)
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to fetch incomplete items: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let items: Vec<serde_json::Value> = rows
+        .iter()
+        .map(|row| {
+            let missing: Vec<&str> = ["missing_text_embedding", "missing_visual_embedding", "empty_searchable_text"]
+                .into_iter()
+                .filter(|col| row.get::<bool, _>(*col))
+                .collect();
+            let created_at: Option<chrono::DateTime<chrono::Utc>> = row.try_get("created_at").ok();
+            let processed_at: Option<chrono::DateTime<chrono::Utc>> = row.try_get("processed_at").ok().flatten();
+            json!({
+                "id": row.get::<i64, _>("id").to_string(),
+                "item_type": row.get::<String, _>("item_type"),
+                "missing": missing,
+                // 原文件损坏时重建索引也补不上视觉向量
+                "media_error": row.try_get::<Option<String>, _>("media_error").ok().flatten(),
+                "created_at": created_at,
+                "processed_at": processed_at,
+            })
+        })
+        .collect();
+    let next_cursor = if rows.len() as i64 == limit {
+        rows.last().map(|row| row.get::<i64, _>("id").to_string())
+    } else {
+        None
+    };
+
+    Ok(Json(json!({
+        "counts": {
+            "missing_text_embedding": counts.get::<i64, _>("missing_text_embedding"),
+            "missing_visual_embedding": counts.get::<i64, _>("missing_visual_embedding"),
+            "empty_searchable_text": counts.get::<i64, _>("empty_searchable_text"),
+            "total": counts.get::<i64, _>("total"),
+        },
+        "items": items,
+        "next_cursor": next_cursor,
+    })))
+}
+
+/// 获取查询文本的 BGE-M3 向量（用于 text_embedding 召回），带 query 前缀
+async fn get_text_embedding(state: &AppState, text: &str) -> Option<Vec<f32>> {
+    let input = format!("{}{}", state.config.embedding_query_prefix, text);
+    // 搜索请求与 worker 共用并发闸门，避免流量高峰时压垮推理服务
+    let Ok(_permit) = state.embedding_permits.acquire().await else { return None; };
+    match state.text_embedder.embed(&input).await {
+        Ok(vec) => vec,
+        Err(e) => {
+            tracing::warn!("Text embedding failed: {}", e);
+            None
+        }
+    }
+}
+
+/// 获取文本的 CLIP 视觉向量（用于文本搜图）
+async fn get_clip_text_embedding(state: &AppState, text: &str) -> Option<Vec<f32>> {
+    let Ok(_permit) = state.clip_permits.acquire().await else { return None; };
+    match state.visual_embedder.embed_text(text).await {
+        Ok(vec) => vec,
+        Err(e) => {
+            tracing::warn!("CLIP text embedding failed: {}", e);
+            None
+        }
+    }
 }
 
 /// 从 URL 下载图片并获取 CLIP 视觉向量（用于以图搜图）
-async fn get_clip_image_embedding_from_url(state: &AppState, image_url: &str) -> Option<Vec<f32>> {
+async fn get_clip_image_embedding_from_url(state: &AppState, image_url: &str) -> Result<Option<Vec<f32>>, StatusCode> {
     // 下载图片
-    let res = state.http_client.get(image_url).send().await.ok()?;
+    let Ok(res) = http::send_with_retry(state.config.http_max_retries, || state.http_client.get(image_url)).await else {
+        tracing::warn!("Failed to download image from {}", image_url);
+        return Ok(None);
+    };
     if !res.status().is_success() {
         tracing::warn!("Failed to download image from {}", image_url);
-        return None;
+        return Ok(None);
     }
-    let image_bytes = res.bytes().await.ok()?;
-    
-    // 调用 CLIP embed
-    let clip_url = format!("{}/embed", state.config.clip_api_url);
-    let part = reqwest::multipart::Part::bytes(image_bytes.to_vec())
-        .file_name("image.jpg")
-        .mime_str("image/jpeg")
-        .ok()?;
-    let form = reqwest::multipart::Form::new().part("file", part);
-    
-    let res = state.http_client.post(&clip_url).multipart(form).send().await.ok()?;
-    if !res.status().is_success() {
-        tracing::warn!("CLIP image embedding failed: {}", res.status());
-        return None;
+
+    // 先看 Content-Type，再以文件头为准，不是图片的 URL 直接拒绝
+    let content_type = res
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.to_string());
+    if !media::content_type_may_be_image(content_type.as_deref()) {
+        tracing::warn!("Rejecting non-image URL {} (Content-Type {:?})", image_url, content_type);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let Ok(image_bytes) = res.bytes().await else {
+        return Ok(None);
+    };
+    if media::sniff_image(&image_bytes).is_none() {
+        tracing::warn!("Rejecting URL {}: content is not a recognizable image", image_url);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    // 调用 CLIP embed（下载完成后才占用许可）
+    let Ok(_permit) = state.clip_permits.acquire().await else { return Ok(None); };
+    match state.visual_embedder.embed_image(image_bytes.to_vec()).await {
+        Ok(vec) => Ok(vec),
+        Err(e) => {
+            tracing::warn!("CLIP image embedding failed: {}", e);
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn task_ids_serialize_as_strings() {
+        // 2^53 + 1 无法用 f64 精确表示
+        let task_id = 9_007_199_254_740_993_i64;
+        assert_eq!(task_created_json(task_id)["task_id"], json!("9007199254740993"));
+    }
+
+    #[test]
+    fn default_timeline_hides_muted_entities() {
+        assert!(hides_muted_entities("timeline", &[], None));
+    }
+
+    #[test]
+    fn explicit_filters_still_return_muted_entities() {
+        assert!(!hides_muted_entities("timeline", &[42], None));
+        assert!(!hides_muted_entities("timeline", &[42, 43], None));
+        assert!(!hides_muted_entities("timeline", &[], Some(7)));
+        assert!(!hides_muted_entities("random", &[], None));
     }
-    
-    let json: serde_json::Value = res.json().await.ok()?;
-    let arr = json.get("embedding")?.as_array()?;
-    
-    Some(arr.iter().map(|v| v.as_f64().unwrap_or(0.0) as f32).collect())
 }
@@ -1,29 +1,71 @@
+use crate::config::Config;
 use crate::state::AppState;
+use crate::reactions;
+use crate::api::text_search_ids;
+use crate::db::{fetch_items_by_ids, tag_items, task_queue_full, untag_items};
 use teloxide::prelude::*;
-use teloxide::types::{ChatId, CustomEmojiId, MessageReactionUpdated, ReactionType};
+use teloxide::types::{
+    ChatId, CustomEmojiId, InlineQuery, InlineQueryResult, InlineQueryResultArticle, InlineQueryResultPhoto,
+    InputMessageContent, InputMessageContentText, MessageReactionCountUpdated, MessageReactionUpdated, ReactionType,
+    ReplyParameters,
+};
 use teloxide::net::Download;
 use sqlx::Row;
-use s3::Bucket;
-use s3::creds::Credentials;
-use s3::region::Region;
+use crate::storage;
 use std::collections::HashSet;
 use std::io::Read;
+use std::sync::{Arc, Mutex};
 use flate2::read::GzDecoder;
 
+/// 按配置创建 Bot：设置了 TELEGRAM_API_URL 时请求发往自建的 Bot API 服务器
+pub fn new_bot(config: &Config) -> Bot {
+    let bot = Bot::new(&config.tg_bot_token);
+    // 地址已在 Config::validate 中校验
+    match config.telegram_api_url.as_deref().and_then(|url| reqwest::Url::parse(url).ok()) {
+        Some(url) => bot.set_api_url(url),
+        None => bot,
+    }
+}
+
+/// 下载 get_file 返回的文件。自建 Bot API 服务器以 --local 模式运行时 file_path 是服务器本地的绝对路径，
+/// 不能通过 HTTP 下载，需要与服务器共享该目录后直接读取
+pub async fn download_tg_file<W>(bot: &Bot, path: &str, dst: &mut W) -> anyhow::Result<()>
+where
+    W: tokio::io::AsyncWrite + Unpin + Send,
+{
+    if path.starts_with('/') {
+        let mut src = tokio::fs::File::open(path).await?;
+        tokio::io::copy(&mut src, dst).await?;
+    } else {
+        bot.download_file(path, dst).await?;
+    }
+    Ok(())
+}
+
 pub async fn run_bot(state: AppState) {
     tracing::info!("Starting Telegram Bot...");
-    let bot = Bot::new(&state.config.tg_bot_token);
+    let bot = new_bot(&state.config);
     
+    // 频道支持：bot 需要是频道管理员才能收到 channel_post；频道内的 reaction 是匿名的，
+    // Telegram 只推送聚合后的 message_reaction_count（可能延迟几分钟），同样要求 bot 为管理员。
+    // allowed_updates 由 teloxide 根据下面注册的 handler 自动声明。
     let handler = dptree::entry()
         .branch(
             Update::filter_message().branch(
-                dptree::filter(|msg: Message| {
-                    msg.photo().is_some() || msg.video().is_some() || msg.text().is_some()
-                })
+                dptree::filter(|msg: Message| is_ingestible_message(&msg))
+                .endpoint(process_message),
+            ),
+        )
+        .branch(
+            Update::filter_channel_post().branch(
+                dptree::filter(|msg: Message| is_ingestible_message(&msg))
                 .endpoint(process_message),
             ),
         )
-        .branch(Update::filter_message_reaction_updated().endpoint(process_message_reaction));
+        .branch(Update::filter_message_reaction_updated().endpoint(process_message_reaction))
+        .branch(Update::filter_message_reaction_count_updated().endpoint(process_message_reaction_count))
+        // inline 模式需要先在 BotFather 中 /setinline 开启
+        .branch(Update::filter_inline_query().endpoint(process_inline_query));
 
     Dispatcher::builder(bot, handler)
         .dependencies(dptree::deps![state])
@@ -33,6 +75,127 @@ pub async fn run_bot(state: AppState) {
         .await;
 }
 
+/// 会被 process_message 收录的消息类型；是否真正收录还要看 INGEST_TYPES
+fn is_ingestible_message(msg: &Message) -> bool {
+    msg.photo().is_some()
+        || msg.video().is_some()
+        || msg.animation().is_some()
+        || msg.audio().is_some()
+        || msg.document().is_some()
+        || msg.text().is_some()
+}
+
+/// 每次 inline 应答的结果数（Telegram 上限 50），继续滚动时按 offset 翻页
+const INLINE_PAGE_SIZE: usize = 20;
+/// inline 结果在 Telegram 端的缓存秒数；预签名 URL 有效期 1 小时，远大于此值
+const INLINE_CACHE_SECS: u32 = 30;
+/// Telegram 对 inline 结果文字长度的限制
+const INLINE_TITLE_CHARS: usize = 64;
+const INLINE_CAPTION_CHARS: usize = 1024;
+const INLINE_MESSAGE_CHARS: usize = 4096;
+
+/// inline query（在任意聊天中输入 @bot 关键词）：走与 /api/v1/search 相同的文本混合检索。
+/// INLINE_QUERY_USERS 之外的用户只能搜到公开 item
+async fn process_inline_query(bot: Bot, query: InlineQuery, state: AppState) -> ResponseResult<()> {
+    let text = query.query.trim();
+    let offset: usize = query.offset.parse().unwrap_or(0);
+    let trusted = state.config.inline_query_users.contains(&(query.from.id.0 as i64));
+
+    let mut results = Vec::new();
+    let mut next_offset = String::new();
+    if !text.is_empty() {
+        // 融合结果只能从头取，翻页时取到当前页末尾再切片；总数不超过 MAX_SEARCH_LIMIT
+        let max_results = state.config.max_search_limit as usize;
+        let fetch = (offset + INLINE_PAGE_SIZE).min(max_results);
+        let ids = text_search_ids(&state, text, fetch, !trusted).await;
+        let page: Vec<i64> = ids.iter().skip(offset).copied().collect();
+        if ids.len() == fetch && fetch < max_results {
+            next_offset = fetch.to_string();
+        }
+
+        match fetch_items_by_ids(&state.db, &page).await {
+            Ok(rows) => {
+                for row in &rows {
+                    let visibility: String = row.try_get("visibility").unwrap_or_default();
+                    if !trusted && visibility != "public" {
+                        continue;
+                    }
+                    if let Some(result) = inline_result_for_row(&state, row).await {
+                        results.push(result);
+                    }
+                }
+            }
+            Err(e) => tracing::error!("Failed to fetch inline query results: {}", e),
+        }
+    }
+
+    bot.answer_inline_query(query.id, results)
+        .cache_time(INLINE_CACHE_SECS)
+        // 结果取决于提问者是否在 INLINE_QUERY_USERS 中，不能跨用户共享缓存
+        .is_personal(true)
+        .next_offset(next_offset)
+        .await?;
+    Ok(())
+}
+
+/// 有 JPEG 缩略图的图片/视频返回图片结果，其余返回文本结果（附缩略图）
+async fn inline_result_for_row(state: &AppState, row: &sqlx::postgres::PgRow) -> Option<InlineQueryResult> {
+    let id: i64 = row.get("id");
+    let item_type: String = row.get("item_type");
+    let content_text: String = row.try_get::<Option<String>, _>("content_text").ok().flatten().unwrap_or_default();
+    let meta: serde_json::Value = row.try_get("meta").unwrap_or_default();
+    let thumbnail_key: Option<String> = row.try_get("thumbnail_key").ok().flatten();
+
+    let thumbnail_url = match thumbnail_key.as_deref() {
+        Some(key) => state
+            .s3_signing_client
+            .presign_get(key, 3600, None)
+            .await
+            .ok()
+            .and_then(|url| reqwest::Url::parse(&url).ok()),
+        None => None,
+    };
+    let caption: String = content_text.trim().chars().take(INLINE_CAPTION_CHARS).collect();
+
+    // Telegram 的 photo_url 只接受 JPEG；透明图片的 PNG/WebP 缩略图按文本结果返回
+    let is_jpeg = thumbnail_key.as_deref().is_some_and(|k| k.ends_with(".jpg"));
+    if matches!(item_type.as_str(), "image" | "video" | "animation") && is_jpeg {
+        let url = thumbnail_url?;
+        let mut photo = InlineQueryResultPhoto::new(id.to_string(), url.clone(), url);
+        if !caption.is_empty() {
+            photo = photo.caption(caption);
+        }
+        return Some(InlineQueryResult::Photo(photo));
+    }
+
+    let summary = meta.get("summary").and_then(|s| s.as_str()).unwrap_or_default();
+    let first_line = content_text.lines().map(str::trim).find(|l| !l.is_empty()).unwrap_or_default();
+    let title: String = if first_line.is_empty() {
+        format!("[{}]", item_type)
+    } else {
+        first_line.chars().take(INLINE_TITLE_CHARS).collect()
+    };
+    let message: String = if content_text.trim().is_empty() {
+        title.clone()
+    } else {
+        content_text.trim().chars().take(INLINE_MESSAGE_CHARS).collect()
+    };
+    let description = if summary.is_empty() { caption } else { summary.to_string() };
+
+    let mut article = InlineQueryResultArticle::new(
+        id.to_string(),
+        title,
+        InputMessageContent::Text(InputMessageContentText::new(message)),
+    );
+    if !description.is_empty() {
+        article = article.description(description);
+    }
+    if let Some(url) = thumbnail_url {
+        article = article.thumbnail_url(url);
+    }
+    Some(InlineQueryResult::Article(article))
+}
+
 fn reaction_key(reaction: &ReactionType) -> Option<(String, String)> {
     match reaction {
         ReactionType::Emoji { emoji } => Some(("emoji".to_string(), emoji.to_string())),
@@ -72,6 +235,28 @@ async fn resolve_task_by_bot_message(
     .fetch_optional(&state.db)
     .await?;
 
+    let row = match row {
+        Some(row) => Some(row),
+        // 未命中时按 item 的原始消息坐标关联：例如 reaction 来自频道里的原帖，
+        // 而 item 是把该帖子转发给 bot 后归档的（items.tg_chat_id / tg_message_id）
+        None => sqlx::query(
+            r#"
+            SELECT t.id, t.item_id, t.payload
+            FROM tasks t
+            JOIN items i ON i.id = t.item_id
+            WHERE i.tg_chat_id = $1
+              AND i.tg_message_id = $2
+              AND NOT (COALESCE(t.payload, '{}'::jsonb) ? 'reprocess_item_id')
+            ORDER BY t.id DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(chat_id)
+        .bind(message_id)
+        .fetch_optional(&state.db)
+        .await?,
+    };
+
     let Some(row) = row else { return Ok(None); };
     let id: i64 = row.get("id");
     let item_id: Option<i64> = row.try_get("item_id").ok();
@@ -254,7 +439,7 @@ async fn ensure_custom_emoji_asset(
 
     let file = bot.get_file(sticker.file.id.clone()).await?;
     let mut raw = Vec::new();
-    bot.download_file(&file.path, &mut raw).await?;
+    download_tg_file(bot, &file.path, &mut raw).await?;
 
     let ext = file.path.split('.').last().unwrap_or("bin").to_ascii_lowercase();
     let (bytes, ext, mime) = match ext.as_str() {
@@ -269,30 +454,10 @@ async fn ensure_custom_emoji_asset(
         _ => (raw, ext, "application/octet-stream".to_string()),
     };
 
-    let region = Region::Custom {
-        region: "us-east-1".to_owned(),
-        endpoint: state.config.s3_endpoint.clone(),
-    };
-    let credentials = Credentials::new(
-        Some(&state.config.s3_access_key),
-        Some(&state.config.s3_secret_key),
-        None,
-        None,
-        None,
-    )
-    .ok();
-
-    let bucket = match credentials {
-        Some(creds) => Bucket::new(&state.config.s3_bucket, region, creds)
-            .ok()
-            .map(|b| b.with_path_style()),
-        None => None,
-    };
-
-    let Some(bucket) = bucket else { return Ok(()); };
+    let Ok(bucket) = storage::internal_bucket(&state.config) else { return Ok(()); };
 
     let key = format!("tags/custom_emoji/{}.{}", custom_emoji_id, ext);
-    bucket.put_object(&key, &bytes).await?;
+    storage::with_object_acl(&bucket, &state.config, &key).put_object(&key, &bytes).await?;
 
     let asset_url = format!("PROXY:{}", key);
     sqlx::query("UPDATE tags SET asset_url = $1, asset_mime = $2 WHERE id = $3")
@@ -305,24 +470,6 @@ async fn ensure_custom_emoji_asset(
     Ok(())
 }
 
-async fn attach_tag_to_item(state: &AppState, item_id: i64, tag_id: i32) -> anyhow::Result<()> {
-    sqlx::query(
-        r#"
-        UPDATE items
-        SET tags = CASE
-            WHEN tags @> ARRAY[$1]::int[] THEN tags
-            ELSE array_append(tags, $1)
-        END
-        WHERE id = $2
-        "#,
-    )
-    .bind(tag_id)
-    .bind(item_id)
-    .execute(&state.db)
-    .await?;
-    Ok(())
-}
-
 async fn process_message_reaction(
     bot: Bot,
     reaction: MessageReactionUpdated,
@@ -347,17 +494,128 @@ async fn process_message_reaction(
         return Ok(());
     };
 
-    let mut affected_tasks: Vec<(i64, Option<i64>, serde_json::Value)> = if let Some(gid) = group_id.as_deref() {
-        resolve_tasks_by_album(&state, chat_id, gid).await.unwrap_or_default()
-    } else {
-        vec![(task_id, item_id, task_payload)]
+    let (added, removed) = diff_reactions(&reaction.old_reaction, &reaction.new_reaction);
+    let may_change_visibility = reaction
+        .user()
+        .is_some_and(|u| state.config.reaction_public_users.contains(&(u.id.0 as i64)));
+    apply_reaction_changes(&bot, &state, (task_id, item_id, task_payload), group_id.as_deref(), added, removed, may_change_visibility).await;
+
+    Ok(())
+}
+
+/// 频道等匿名 reaction 场景：Telegram 只给出当前各 reaction 的计数，
+/// 与任务 payload 中保存的上一次快照做差分后复用同一套打标签逻辑。
+async fn process_message_reaction_count(
+    bot: Bot,
+    update: MessageReactionCountUpdated,
+    state: AppState,
+) -> ResponseResult<()> {
+    let chat_id = update.chat.id.0;
+    let message_id = update.message_id.0 as i64;
+    tracing::debug!(
+        "MessageReactionCountUpdated: chat_id={}, message_id={}, reactions={}",
+        chat_id,
+        message_id,
+        update.reactions.len()
+    );
+
+    let Some((task_id, item_id, group_id, task_payload)) = resolve_task_by_bot_message(&state, chat_id, message_id)
+        .await
+        .ok()
+        .flatten() else {
+        tracing::debug!("No task mapped for reaction count chat_id={}, message_id={}", chat_id, message_id);
+        return Ok(());
     };
 
-    let (added, removed) = diff_reactions(&reaction.old_reaction, &reaction.new_reaction);
+    // 计数里包含 bot 自己设置的状态 reaction，不能把它们当成用户打的标签
+    let status_emojis = [
+        state.config.reaction_processing.as_str(),
+        state.config.reaction_done.as_str(),
+        state.config.reaction_failed.as_str(),
+    ];
+    let current: Vec<ReactionType> = update
+        .reactions
+        .iter()
+        .filter(|rc| rc.total_count > 0)
+        .map(|rc| rc.r#type.clone())
+        .filter(|r| !matches!(r, ReactionType::Emoji { emoji } if status_emojis.contains(&emoji.as_str())))
+        .collect();
+
+    let previous: Vec<ReactionType> = task_payload
+        .get("reaction_snapshot")
+        .cloned()
+        .and_then(|v| serde_json::from_value(v).ok())
+        .unwrap_or_default();
+
+    let (added, removed) = diff_reactions(&previous, &current);
+    // 计数不带用户，无法确认是谁点的，可见性 reaction 一律忽略
+    apply_reaction_changes(&bot, &state, (task_id, item_id, task_payload), group_id.as_deref(), added, removed, false).await;
+
+    let snapshot = serde_json::to_value(&current).unwrap_or_else(|_| serde_json::json!([]));
+    if let Err(e) = sqlx::query(
+        "UPDATE tasks SET payload = jsonb_set(COALESCE(payload, '{}'::jsonb), '{reaction_snapshot}', $1), updated_at = NOW() WHERE id = $2",
+    )
+    .bind(snapshot)
+    .bind(task_id)
+    .execute(&state.db)
+    .await
+    {
+        tracing::warn!("Failed to store reaction snapshot for task {}: {}", task_id, e);
+    }
+
+    Ok(())
+}
+
+/// 把 reaction 的增减转换为标签的增减：写入任务 payload（供仍在处理的组图成员继承），
+/// 并直接作用到已经生成的 items 上。may_change_visibility 为 false 时可见性 reaction 被忽略
+async fn apply_reaction_changes(
+    bot: &Bot,
+    state: &AppState,
+    task: (i64, Option<i64>, serde_json::Value),
+    group_id: Option<&str>,
+    added: Vec<ReactionType>,
+    removed: Vec<ReactionType>,
+    may_change_visibility: bool,
+) {
+    let (task_id, item_id, task_payload) = task;
+    let bot_chat_id: Option<i64> = sqlx::query_scalar("SELECT bot_chat_id FROM tasks WHERE id = $1")
+        .bind(task_id)
+        .fetch_optional(&state.db)
+        .await
+        .ok()
+        .flatten();
+
+    // 关闭组图传播时只作用于被点 reaction 的那一条
+    let group_id = group_id.filter(|_| state.config.album_tag_propagate);
+    let mut affected_tasks: Vec<(i64, Option<i64>, serde_json::Value)> = match (group_id, bot_chat_id) {
+        (Some(gid), Some(bot_chat_id)) => resolve_tasks_by_album(state, bot_chat_id, gid).await.unwrap_or_default(),
+        _ => vec![(task_id, item_id, task_payload)],
+    };
+
+    let is_public_reaction = |r: &ReactionType| {
+        matches!(
+            (r, state.config.reaction_public.as_deref()),
+            (ReactionType::Emoji { emoji }, Some(public)) if emoji == public
+        )
+    };
+    let (public_added, added): (Vec<ReactionType>, Vec<ReactionType>) = added.into_iter().partition(|r| is_public_reaction(r));
+    let (public_removed, removed): (Vec<ReactionType>, Vec<ReactionType>) = removed.into_iter().partition(|r| is_public_reaction(r));
+    // 可见性 reaction 不作为标签，直接切换 public/private；只接受 REACTION_PUBLIC_USERS 中的用户
+    let visibility_reacted = !public_added.is_empty() || !public_removed.is_empty();
+    if visibility_reacted && !may_change_visibility {
+        tracing::debug!("Ignoring visibility reaction on task {} from a user not in REACTION_PUBLIC_USERS", task_id);
+    } else if visibility_reacted {
+        let visibility = if public_added.is_empty() { "private" } else { "public" };
+        set_visibility_for_tasks(state, &mut affected_tasks, visibility).await;
+    }
 
     for r in added {
         let Some((icon_type, icon_value)) = reaction_key(&r) else { continue; };
-        let tag_id = match upsert_tag_id(&state, &icon_type, &icon_value).await {
+        if !state.config.reaction_creates_tag(&icon_value) {
+            tracing::debug!("Reaction {} is excluded from tagging", icon_value);
+            continue;
+        }
+        let tag_id = match upsert_tag_id(state, &icon_type, &icon_value).await {
             Ok(id) => id,
             Err(e) => {
                 tracing::warn!("Failed to upsert tag: {}", e);
@@ -366,7 +624,7 @@ async fn process_message_reaction(
         };
 
         if icon_type == "tmoji" {
-            if let Err(e) = ensure_custom_emoji_asset(&bot, &state, tag_id, &icon_value).await {
+            if let Err(e) = ensure_custom_emoji_asset(bot, state, tag_id, &icon_value).await {
                 tracing::warn!("Failed to fetch custom emoji asset: {}", e);
             }
         }
@@ -376,7 +634,7 @@ async fn process_message_reaction(
         for (tid, _, payload) in &mut affected_tasks {
             let next = add_tag_id_to_payload(payload.clone(), tag_id);
             *payload = next;
-            if let Err(e) = update_task_payload(&state, *tid, payload.clone()).await {
+            if let Err(e) = update_task_payload(state, *tid, payload.clone()).await {
                 tracing::warn!("Failed to update task payload for tag: task_id={}, err={}", tid, e);
             }
         }
@@ -384,7 +642,7 @@ async fn process_message_reaction(
         // Apply to all existing items in the album.
         let item_ids: Vec<i64> = affected_tasks.iter().filter_map(|(_, iid, _)| *iid).collect();
         for iid in item_ids {
-            if let Err(e) = attach_tag_to_item(&state, iid, tag_id).await {
+            if let Err(e) = tag_items(&state.db, &[iid], tag_id, state.config.album_tag_propagate).await {
                 tracing::warn!("Failed to attach tag {} to item {}: {}", tag_id, iid, e);
             }
         }
@@ -407,28 +665,96 @@ async fn process_message_reaction(
         for (tid, _, payload) in &mut affected_tasks {
             let next = remove_tag_id_from_payload(payload.clone(), tag_id);
             *payload = next;
-            if let Err(e) = update_task_payload(&state, *tid, payload.clone()).await {
+            if let Err(e) = update_task_payload(state, *tid, payload.clone()).await {
                 tracing::warn!("Failed to update task payload for tag removal: task_id={}, err={}", tid, e);
             }
         }
 
         let item_ids: Vec<i64> = affected_tasks.iter().filter_map(|(_, iid, _)| *iid).collect();
         if !item_ids.is_empty() {
-            if let Err(e) = sqlx::query("UPDATE items SET tags = array_remove(tags, $1) WHERE id = ANY($2)")
-                .bind(tag_id)
-                .bind(&item_ids)
-                .execute(&state.db)
-                .await
-            {
+            if let Err(e) = untag_items(&state.db, &item_ids, tag_id, state.config.album_tag_propagate).await {
                 tracing::warn!("Failed to detach tag {} from album items: {}", tag_id, e);
             }
         }
     }
+}
 
-    Ok(())
+/// 设置可见性：写入任务 payload（尚未生成 item 的组图成员创建时继承），并更新已有 items
+async fn set_visibility_for_tasks(
+    state: &AppState,
+    affected_tasks: &mut [(i64, Option<i64>, serde_json::Value)],
+    visibility: &str,
+) {
+    for (tid, _, payload) in affected_tasks.iter_mut() {
+        if let Some(obj) = payload.as_object_mut() {
+            obj.insert("visibility".to_string(), serde_json::json!(visibility));
+        }
+        if let Err(e) = update_task_payload(state, *tid, payload.clone()).await {
+            tracing::warn!("Failed to update task payload for visibility: task_id={}, err={}", tid, e);
+        }
+    }
+
+    let item_ids: Vec<i64> = affected_tasks.iter().filter_map(|(_, iid, _)| *iid).collect();
+    if item_ids.is_empty() {
+        return;
+    }
+    if let Err(e) = sqlx::query("UPDATE items SET visibility = $1 WHERE id = ANY($2)")
+        .bind(visibility)
+        .bind(&item_ids)
+        .execute(&state.db)
+        .await
+    {
+        tracing::warn!("Failed to set visibility {} for items {:?}: {}", visibility, item_ids, e);
+    }
+}
+
+/// 相册成员通常在几秒内陆续到达，记录保留这么久后清除，之后重发的同一相册会再次收到提示
+const QUEUE_FULL_NOTICE_TTL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// 该相册是否还没回复过「队列已满」；返回 true 时由调用方回复，并在 QUEUE_FULL_NOTICE_TTL 后清除记录
+fn claim_queue_full_notice(state: &AppState, group_id: String) -> bool {
+    let mut albums = state.queue_full_albums.lock().unwrap_or_else(|e| e.into_inner());
+    if !albums.insert(group_id.clone()) {
+        return false;
+    }
+    let albums = state.queue_full_albums.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(QUEUE_FULL_NOTICE_TTL).await;
+        albums.lock().unwrap_or_else(|e| e.into_inner()).remove(&group_id);
+    });
+    true
+}
+
+/// 持有期间表示该实体的头像正在抓取，Drop 时释放
+struct AvatarFetchGuard {
+    inflight: Arc<Mutex<HashSet<i64>>>,
+    id: i64,
+}
+
+impl AvatarFetchGuard {
+    fn acquire(inflight: &Arc<Mutex<HashSet<i64>>>, id: i64) -> Option<Self> {
+        let mut set = inflight.lock().unwrap_or_else(|e| e.into_inner());
+        if !set.insert(id) {
+            return None;
+        }
+        Some(Self { inflight: inflight.clone(), id })
+    }
+}
+
+impl Drop for AvatarFetchGuard {
+    fn drop(&mut self) {
+        let mut set = self.inflight.lock().unwrap_or_else(|e| e.into_inner());
+        set.remove(&self.id);
+    }
 }
 
 async fn update_entity_avatar(bot: Bot, state: AppState, id: i64, name: String) {
+    // 同一实体同时只允许一个抓取流程，突发转发时避免重复调用 TG API 和重复写 S3
+    let Some(_guard) = AvatarFetchGuard::acquire(&state.avatar_inflight, id) else {
+        tracing::debug!("Avatar fetch for entity {} already in flight, skipping", id);
+        return;
+    };
+
     // 检查是否需要更新头像（简单起见，如果 NULL 则更新，或者定期更新）
     let needs_update: bool = sqlx::query_scalar("SELECT avatar_url IS NULL FROM entities WHERE id = $1")
         .bind(id)
@@ -445,32 +771,20 @@ async fn update_entity_avatar(bot: Bot, state: AppState, id: i64, name: String)
         if let Some(photo) = chat.photo {
             if let Ok(file) = bot.get_file(photo.small_file_id).await {
                 let mut dst = Vec::new();
-                if bot.download_file(&file.path, &mut dst).await.is_ok() {
+                if download_tg_file(&bot, &file.path, &mut dst).await.is_ok() {
                     let ext = file.path.split('.').last().unwrap_or("jpg");
                     let key = format!("avatars/{}.{}", id, ext);
                     
-                    let region = Region::Custom {
-                        region: "us-east-1".to_owned(),
-                        endpoint: state.config.s3_endpoint.clone(),
-                    };
-                    let credentials = Credentials::new(
-                        Some(&state.config.s3_access_key),
-                        Some(&state.config.s3_secret_key),
-                        None, None, None
-                    ).ok();
-                    
-                    if let (Some(creds), Some(bucket_name)) = (credentials, Some(&state.config.s3_bucket)) {
-                        let bucket = Bucket::new(bucket_name, region, creds).ok().map(|b| b.with_path_style());
-                        if let Some(bucket) = bucket {
-                            if bucket.put_object(&key, &dst).await.is_ok() {
-                                let avatar_url = format!("PROXY:{}", key); 
-                                let _ = sqlx::query("UPDATE entities SET avatar_url = $1 WHERE id = $2")
-                                    .bind(avatar_url)
-                                    .bind(id)
-                                    .execute(&state.db)
-                                    .await;
-                                tracing::info!("Updated avatar for entity {}: {}", id, name);
-                            }
+                    if let Ok(bucket) = storage::internal_bucket(&state.config) {
+                        let bucket = storage::with_object_acl(&bucket, &state.config, &key);
+                        if bucket.put_object(&key, &dst).await.is_ok() {
+                            let avatar_url = format!("PROXY:{}", key); 
+                            let _ = sqlx::query("UPDATE entities SET avatar_url = $1 WHERE id = $2")
+                                .bind(avatar_url)
+                                .bind(id)
+                                .execute(&state.db)
+                                .await;
+                            tracing::info!("Updated avatar for entity {}: {}", id, name);
                         }
                     }
                 }
@@ -481,6 +795,50 @@ async fn update_entity_avatar(bot: Bot, state: AppState, id: i64, name: String)
 
 async fn process_message(bot: Bot, msg: Message, state: AppState) -> ResponseResult<()> {
     tracing::info!("Received message: {} from chat {}", msg.id, msg.chat.id);
+
+    // Extract content
+    let caption = || msg.caption().map(|s| s.to_string()).unwrap_or_default();
+    let (file, item_type, content_text) = if let Some(photos) = msg.photo() {
+        let photo = photos.last().unwrap();
+        (Some(&photo.file), "image", caption())
+    } else if let Some(video) = msg.video() {
+         (Some(&video.file), "video", caption())
+    } else if let Some(animation) = msg.animation() {
+         (Some(&animation.file), "animation", caption())
+    } else if let Some(audio) = msg.audio() {
+         (Some(&audio.file), "audio", caption())
+    } else if let Some(document) = msg.document() {
+         (Some(&document.file), "document", caption())
+    } else if let Some(text) = msg.text() {
+         (None, "text", text.to_string())
+    } else {
+        return Ok(());
+    };
+    // file_unique_id 不会变，file_id 失效后 worker 用它在重新获取的消息里找回同一个文件
+    let file_id = file.map(|f| f.id.clone());
+    let file_unique_id = file.map(|f| f.unique_id.clone());
+
+    // 未在 INGEST_TYPES 中的类型：不建任务、不点 reaction
+    if !state.config.ingests(item_type) {
+        tracing::debug!("Skipping message {} of type {} (not in INGEST_TYPES)", msg.id, item_type);
+        return Ok(());
+    }
+
+    // 队列已满：不建任务，直接告诉用户稍后再发；相册的每个成员都是单独的消息，同一相册只回复一次
+    if task_queue_full(&state.db, state.config.max_pending_tasks).await {
+        tracing::warn!("Task queue is full, rejecting message {} from chat {}", msg.id, msg.chat.id);
+        let first_in_album = match msg.media_group_id() {
+            Some(group_id) => claim_queue_full_notice(&state, group_id.to_string()),
+            None => true,
+        };
+        if first_in_album {
+            let _ = bot
+                .send_message(msg.chat.id, "⏳ 处理队列已满，请稍后再发送。")
+                .reply_parameters(ReplyParameters::new(msg.id))
+                .await;
+        }
+        return Ok(());
+    }
     
     // 如果是转发消息，尝试获取并更新来源实体的头像
     if let Some(origin) = msg.forward_origin() {
@@ -505,29 +863,33 @@ async fn process_message(bot: Bot, msg: Message, state: AppState) -> ResponseRes
     let bot_chat_id = msg.chat.id.0;
     let bot_message_id = msg.id.0 as i64; 
 
-    // Extract content
-    let (file_id, item_type, content_text) = if let Some(photos) = msg.photo() {
-        let photo = photos.last().unwrap();
-        (Some(photo.file.id.clone()), "image", msg.caption().map(|s| s.to_string()).unwrap_or_default())
-    } else if let Some(video) = msg.video() {
-         (Some(video.file.id.clone()), "video", msg.caption().map(|s| s.to_string()).unwrap_or_default())
-    } else if let Some(text) = msg.text() {
-         (None, "text", text.to_string())
-    } else {
-        return Ok(());
-    };
-
     let tg_group_id = msg.media_group_id().map(|id| id.to_string());
 
     let mut payload = serde_json::json!({
         "file_id": file_id,
+        "file_unique_id": file_unique_id,
         "item_type": item_type,
         "content_text": content_text,
         "tg_group_id": tg_group_id,
         "meta": {}
     });
 
-    // 从 forward_origin 提取来源信息并保存到 entities 表
+    // 回复上下文：用户引用了部分文字时取引用片段，否则取被回复消息的正文/说明
+    let reply_context = msg
+        .quote()
+        .map(|q| q.text.clone())
+        .or_else(|| {
+            msg.reply_to_message()
+                .and_then(|r| r.text().or_else(|| r.caption()))
+                .map(|s| s.to_string())
+        })
+        .filter(|s| !s.trim().is_empty());
+    if let Some(ctx) = reply_context {
+        payload["meta"]["reply_context"] = serde_json::Value::String(ctx);
+    }
+
+    // 从 forward_origin 提取来源信息并保存到 entities 表；
+    // 同时在 meta.source_kind 记录来源类别（forward_channel / forward_user / forward_hidden / self）
     let (source_chat_id, source_message_id, source_user_id) = match msg.forward_origin() {
         Some(origin) => {
             let (eid, ename, eusername, etype) = match origin {
@@ -593,29 +955,42 @@ async fn process_message(bot: Bot, msg: Message, state: AppState) -> ResponseRes
                 .await;
             }
 
-            match origin {
-                teloxide::types::MessageOrigin::Channel { chat, message_id, .. } => {
-                    tracing::info!("Forward from Channel: chat_id={}, msg_id={}", chat.id, message_id.0);
-                    (Some(chat.id.0), Some(message_id.0 as i64), None)
-                }
-                teloxide::types::MessageOrigin::Chat { sender_chat, .. } => {
-                    tracing::info!("Forward from Chat: sender_chat_id={}", sender_chat.id);
-                    (Some(sender_chat.id.0), None, None)
-                }
-                teloxide::types::MessageOrigin::User { sender_user, .. } => {
-                    tracing::info!("Forward from User: user_id={}", sender_user.id);
-                    (None, None, Some(sender_user.id.0 as i64))
-                }
-                teloxide::types::MessageOrigin::HiddenUser { sender_user_name, .. } => {
-                    tracing::info!("Forward from HiddenUser: name={}", sender_user_name);
-                    // 记录 HiddenUser 的名字到 payload 的 meta 中
-                    payload["meta"]["forward_sender_name"] = serde_json::Value::String(sender_user_name.clone());
-                    (None, None, Some(0)) // Hidden User 的 tg_user_id 设为 0
-                }
-            }
+            forward_source(origin, &mut payload["meta"])
+        }
+        None if msg.chat.is_channel() => {
+            // 频道自己发的帖子：来源就是频道本身，保留消息坐标以便 tg_link 与 reaction 关联
+            tracing::info!("Channel post, recording channel as source_chat_id");
+            payload["meta"]["source_kind"] = serde_json::json!("self");
+            let name = msg.chat.title().unwrap_or("Channel").to_string();
+            let _ = sqlx::query(
+                r#"
+                INSERT INTO entities (id, name, username, type, updated_at)
+                VALUES ($1, $2, $3, 'channel', NOW())
+                ON CONFLICT (id) DO UPDATE SET 
+                    name = EXCLUDED.name,
+                    username = EXCLUDED.username,
+                    type = EXCLUDED.type,
+                    updated_at = NOW()
+                "#
+            )
+            .bind(msg.chat.id.0)
+            .bind(name.clone())
+            .bind(msg.chat.username().map(|s| s.to_string()))
+            .execute(&state.db)
+            .await;
+
+            let bot_clone = bot.clone();
+            let state_clone = state.clone();
+            let channel_id = msg.chat.id.0;
+            tokio::spawn(async move {
+                update_entity_avatar(bot_clone, state_clone, channel_id, name).await;
+            });
+
+            (Some(msg.chat.id.0), Some(msg.id.0 as i64), None)
         }
         None => {
             tracing::info!("Not a forwarded message, recording sender as source_user_id");
+            payload["meta"]["source_kind"] = serde_json::json!("self");
             let sender_id = msg.from.as_ref().map(|u| u.id.0 as i64).unwrap_or(0);
             
             // 自动将发送者存入 entities 表
@@ -660,6 +1035,18 @@ async fn process_message(bot: Bot, msg: Message, state: AppState) -> ResponseRes
         source_user_id
     );
 
+    // 来源实体配置了默认标签时并入 payload.tag_ids，worker 建 item 时一并打上
+    if let Some(entity_id) = source_user_id.or(source_chat_id) {
+        let default_tags: Vec<i32> = sqlx::query_scalar("SELECT tag_id FROM entity_default_tags WHERE entity_id = $1")
+            .bind(entity_id)
+            .fetch_all(&state.db)
+            .await
+            .unwrap_or_default();
+        for tag_id in default_tags {
+            payload = add_tag_id_to_payload(payload, tag_id);
+        }
+    }
+
     let row = sqlx::query(
         r#"
         INSERT INTO tasks (bot_chat_id, bot_message_id, source_chat_id, source_message_id, source_user_id, status, payload)
@@ -691,7 +1078,7 @@ async fn process_message(bot: Bot, msg: Message, state: AppState) -> ResponseRes
         }
     }
 
-    // React with eyes 👀 (processing) - for albums, only react on the leader message
+    // React with the processing reaction - for albums, only react on the leader message
     let should_react = match tg_group_id.as_deref() {
         Some(gid) => is_album_reaction_leader(&state, bot_chat_id, bot_message_id, gid)
             .await
@@ -700,16 +1087,103 @@ async fn process_message(bot: Bot, msg: Message, state: AppState) -> ResponseRes
     };
 
     if should_react {
-        let reaction = ReactionType::Emoji { emoji: "👀".to_string() };
-        if let Err(e) = bot
-            .set_message_reaction(msg.chat.id, msg.id)
-            .reaction(vec![reaction])
-            .send()
-            .await
-        {
+        if let Err(e) = reactions::set_reaction(&bot, msg.chat.id, msg.id, &state.config.reaction_processing).await {
             tracing::warn!("Failed to set reaction: {}", e);
         }
     }
 
     Ok(())
 }
+
+/// 转发来源对应的 (source_chat_id, source_message_id, source_user_id)，
+/// 同时把来源类型写入 meta.source_kind；隐藏账号没有 ID，把转发时显示的名字记入 meta.forward_sender_name
+fn forward_source(
+    origin: &teloxide::types::MessageOrigin,
+    meta: &mut serde_json::Value,
+) -> (Option<i64>, Option<i64>, Option<i64>) {
+    match origin {
+        teloxide::types::MessageOrigin::Channel { chat, message_id, .. } => {
+            tracing::info!("Forward from Channel: chat_id={}, msg_id={}", chat.id, message_id.0);
+            meta["source_kind"] = serde_json::json!("forward_channel");
+            (Some(chat.id.0), Some(message_id.0 as i64), None)
+        }
+        teloxide::types::MessageOrigin::Chat { sender_chat, .. } => {
+            // 以群组 / 频道身份发出的消息，同样归为频道类来源
+            tracing::info!("Forward from Chat: sender_chat_id={}", sender_chat.id);
+            meta["source_kind"] = serde_json::json!("forward_channel");
+            (Some(sender_chat.id.0), None, None)
+        }
+        teloxide::types::MessageOrigin::User { sender_user, .. } => {
+            tracing::info!("Forward from User: user_id={}", sender_user.id);
+            meta["source_kind"] = serde_json::json!("forward_user");
+            (None, None, Some(sender_user.id.0 as i64))
+        }
+        teloxide::types::MessageOrigin::HiddenUser { sender_user_name, .. } => {
+            tracing::info!("Forward from HiddenUser: name={}", sender_user_name);
+            meta["source_kind"] = serde_json::json!("forward_hidden");
+            meta["forward_sender_name"] = serde_json::Value::String(sender_user_name.clone());
+            (None, None, Some(0)) // Hidden User 的 tg_user_id 设为 0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(content: serde_json::Value) -> Message {
+        let mut value = serde_json::json!({
+            "message_id": 1,
+            "date": 0,
+            "chat": { "id": 1, "type": "private", "first_name": "test" },
+            "from": { "id": 1, "is_bot": false, "first_name": "test" },
+        });
+        value.as_object_mut().unwrap().extend(content.as_object().unwrap().clone());
+        serde_json::from_value(value).expect("valid message")
+    }
+
+    #[test]
+    fn routes_audio_document_and_animation_messages() {
+        let audio = message(serde_json::json!({
+            "audio": { "file_id": "a", "file_unique_id": "ua", "file_size": 1, "duration": 3, "mime_type": "audio/mpeg" },
+        }));
+        let document = message(serde_json::json!({
+            "document": { "file_id": "d", "file_unique_id": "ud", "file_size": 1, "mime_type": "application/pdf" },
+        }));
+        let animation = message(serde_json::json!({
+            "animation": {
+                "file_id": "g", "file_unique_id": "ug", "file_size": 1,
+                "width": 1, "height": 1, "duration": 1, "mime_type": "video/mp4",
+            },
+            "document": { "file_id": "g", "file_unique_id": "ug", "file_size": 1, "mime_type": "video/mp4" },
+        }));
+        assert!(audio.audio().is_some() && document.document().is_some() && animation.animation().is_some());
+        assert!(is_ingestible_message(&audio));
+        assert!(is_ingestible_message(&document));
+        assert!(is_ingestible_message(&animation));
+    }
+
+    #[test]
+    fn hidden_user_forward_name_reaches_item_response() {
+        let forwarded = message(serde_json::json!({
+            "forward_origin": { "type": "hidden_user", "sender_user_name": "Anonymous Fox", "date": 0 },
+            "text": "hello",
+        }));
+        let origin = forwarded.forward_origin().expect("forwarded message");
+
+        // bot 写入任务 payload 的 meta，worker 原样继承为 item meta
+        let mut meta = serde_json::json!({});
+        assert_eq!(forward_source(origin, &mut meta), (None, None, Some(0)));
+        assert_eq!(meta["source_kind"], "forward_hidden");
+        assert_eq!(crate::api::item_source_name(&meta), Some(&serde_json::json!("Anonymous Fox")));
+    }
+
+    #[test]
+    fn skips_unsupported_messages() {
+        let location = message(serde_json::json!({
+            "location": { "latitude": 1.0, "longitude": 2.0 },
+        }));
+        assert!(location.location().is_some());
+        assert!(!is_ingestible_message(&location));
+    }
+}
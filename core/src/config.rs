@@ -1,60 +1,599 @@
+use crate::reactions;
 use serde::Deserialize;
 
 #[derive(Clone, Deserialize, Debug)]
 pub struct Config {
+    /// Postgres 连接串；URL 中的 sslmode 等参数照常生效（托管数据库用 `?sslmode=require` 或 `verify-full`）
     pub database_url: String,
+    /// 校验数据库服务端证书用的 CA 证书（PEM）路径，配合 sslmode=verify-ca / verify-full 使用
+    pub db_ssl_root_cert: Option<String>,
+    /// 单条 SQL 的超时毫秒数，连接建立时以 statement_timeout 下发；未设置时使用数据库默认值
+    pub db_statement_timeout_ms: Option<u64>,
+    /// 服务端内部访问 S3 的地址（worker 上传、删除等写操作）
     pub s3_endpoint: String,
+    /// 浏览器可达的 S3 地址，仅用于生成预签名 URL；未设置时回退到 `s3_endpoint`
     pub s3_public_endpoint: String,
     pub s3_access_key: String,
     pub s3_secret_key: String,
     pub s3_bucket: String,
+    /// 签名使用的 region（MinIO 等通常无所谓，AWS S3 / GCS 需要真实 region）
+    pub s3_region: String,
+    /// true（默认）为 path-style 寻址 `{endpoint}/{bucket}`；AWS S3、GCS 等需要关闭以使用 virtual-hosted 寻址
+    pub s3_path_style: bool,
     pub clip_api_url: String,
     pub vlm_api_base: String,
     pub vlm_api_key: String,
     pub vlm_model: String,
+    /// 为长文本消息生成 meta.summary（可选，失败不影响入库）。
+    /// 文档不提取正文，content_text 只有 caption，因此不做摘要
+    pub summary_enabled: bool,
+    /// 摘要使用的 OpenAI 兼容接口，未设置时沿用 VLM 的配置
+    pub summary_api_base: String,
+    pub summary_api_key: String,
+    pub summary_model: String,
+    /// 文本达到该字符数才生成摘要
+    pub summary_min_chars: usize,
     pub embedding_api_base: String,
     pub embedding_api_key: String,
     pub embedding_model: String,
+    /// 查询文本（搜索）送入 embedding 前加的前缀，如 instruction 模型的 "query: "
+    pub embedding_query_prefix: String,
+    /// 入库文本（searchable_text）送入 embedding 前加的前缀，如 "passage: "；修改后已有向量不会自动更新
+    pub embedding_passage_prefix: String,
+    /// 入库文本向量化的最大字符数，超出部分截断（数据库仍保存全文）；0 表示不限制
+    pub max_embed_chars: usize,
+    /// 超长文本不截断，而是按 MAX_EMBED_CHARS 分块分别向量化后取均值
+    pub embed_chunking: bool,
     pub tg_bot_token: String,
+    /// 自建 Bot API 服务器地址（如 http://telegram-bot-api:8081），解除公共 API 20MB 的下载限制；未设置时用官方 API
+    pub telegram_api_url: Option<String>,
+    pub detect_language: bool,
+    pub reaction_processing: String,
+    pub reaction_done: String,
+    pub reaction_failed: String,
+    /// 处理成功后除 reaction 外再回复一条确认消息（组图只回复首条消息）
+    pub reply_on_success: bool,
+    /// 成功确认消息保留的秒数，到期自动删除（0 表示不删除）
+    pub reply_ttl_secs: u64,
+    pub worker_batch_claim: i64,
+    /// 管理/诊断类接口的访问令牌（`Authorization: Bearer <token>`），未设置时这些接口一律拒绝
+    pub api_token: Option<String>,
+    /// 重建索引时每分钟放行的任务数，避免压垮外部模型服务
+    pub reindex_rate_per_min: i64,
+    /// 搜索未指定 limit 时的默认返回数
+    pub default_search_limit: i64,
+    /// 搜索 limit 的上限
+    pub max_search_limit: i64,
+    /// 每一路召回的候选数，需不小于 `max_search_limit` 才能保证融合有足够候选
+    pub per_channel_recall: i64,
+    /// 相同搜索（归一化后的查询词 + 召回参数）复用融合结果的秒数，0 表示不缓存
+    pub search_cache_ttl_secs: u64,
+    /// 有召回通道不可用（embedding/CLIP 服务故障等）时直接返回 503，而不是返回降级结果并附带 warnings
+    pub search_strict_recall: bool,
+    /// 搜索未带 match 参数时全文检索的解析方式：websearch（默认）、plain、raw、phrase、any
+    pub fts_query_mode: String,
+    /// 视觉向量召回的最低余弦相似度，低于该值的命中在融合前丢弃（0 表示不过滤）
+    pub min_visual_sim: f32,
+    /// 文本向量召回的最低余弦相似度
+    pub min_text_sim: f32,
+    /// 图片缩略图的 JPEG 质量（1-100）
+    pub thumb_jpeg_quality: u8,
+    /// 图片宽高都小于该值时不生成缩略图，直接以原图作为缩略图（0 表示总是生成）
+    pub thumb_min_source_dim: u32,
+    /// 叠加在图片缩略图右下角的水印（PNG）；文件不存在或缩略图小于水印时跳过，原图不受影响
+    pub thumb_watermark_path: Option<String>,
+    /// 感知哈希汉明距离不超过该值（0-64）的图片视为近似重复
+    pub phash_max_distance: i32,
+    /// 上传原文件时附带的 `x-amz-storage-class`，缩略图不受影响。
+    /// AWS S3 支持 STANDARD_IA / ONEZONE_IA / INTELLIGENT_TIERING / GLACIER_IR / GLACIER / DEEP_ARCHIVE；
+    /// Cloudflare R2 支持 STANDARD / STANDARD_IA；MinIO 仅在配置了存储类别时识别 STANDARD / REDUCED_REDUNDANCY，
+    /// 其他后端可能忽略或拒绝该头。注意 GLACIER / DEEP_ARCHIVE 需要先恢复才能读取，原图查看与重建索引会失败
+    pub s3_original_storage_class: Option<String>,
+    /// 原文件超过该字节数时改用分片上传（S3_MULTIPART_THRESHOLD_MB，默认 32MB）
+    pub s3_multipart_threshold: usize,
+    /// 分片上传的每片字节数（S3_MULTIPART_PART_SIZE_MB，默认 8MB；S3 要求除最后一片外不小于 5MB）
+    pub s3_multipart_part_size: usize,
+    /// 是否在 S3 中保存原文件；关闭后只保留缩略图、向量与元数据，s3_key 为空（原图查看、下载不可用）
+    pub store_originals: bool,
+    /// 组图中任一成员的标签变化是否同步到同组（tg_group_id 相同）的所有 item
+    pub album_tag_propagate: bool,
+    /// 视频预览拼图的帧数，0 表示不生成
+    pub storyboard_frames: u32,
+    /// 时长低于该秒数的视频不生成预览拼图
+    pub storyboard_min_duration: f64,
+    /// 视频封面帧接近全黑（淡入、片头黑场）时改取时长中点的帧；需要额外解码一次，默认关闭
+    pub video_smart_thumb: bool,
+    /// 视频视觉向量使用的帧数（含封面）：大于 1 时在时长内均匀多抽几帧，各自向量化后取归一化均值，
+    /// 让片中才出现的画面也能被以文搜图召回；每帧一次 CLIP 调用，默认 1 只用封面
+    pub video_embed_frames: u32,
+    /// 图片无法解码、视频没有可读的画面时让任务失败（回复失败表情），而不是只在 meta.media_error 中记录后照常入库
+    pub fail_on_unreadable_media: bool,
+    /// 为文本 item 渲染一张文字卡片作为缩略图，让图墙中的文本不再是空白格子
+    pub text_cards: bool,
+    /// 文字卡片使用的字体文件（需要覆盖 CJK，默认是镜像中安装的 Noto Sans CJK）
+    pub text_card_font: String,
+    /// 备注是否并入 searchable_text：开启后修改备注会入队一次重建索引（重新生成文本向量）
+    pub note_searchable: bool,
+    /// 分页接口未指定 limit 时的默认条数（各接口可另设默认值）
+    pub default_page_size: i64,
+    /// 分页接口 limit 的上限
+    pub max_page_size: i64,
+    /// 图片「含文字可能性」低于该值时跳过 VLM OCR（0 表示关闭预筛，总是 OCR）
+    pub ocr_min_text_likelihood: f32,
+    /// 公开资源（头像、标签图标）的 CDN 地址；设置后这些资源直接返回 `{cdn_base_url}/{key}` 而不是预签名 URL
+    pub cdn_base_url: Option<String>,
+    /// 公开读的对象 key 前缀：上传时设置 public-read ACL，并可经 CDN 直接访问；其余对象（item 内容）为 private
+    pub public_key_prefixes: Vec<String>,
+    /// 上传时是否发送 x-amz-acl；不支持对象 ACL 的存储（如强制 BucketOwnerEnforced 的 bucket、R2）需关闭，改用 bucket 策略
+    pub s3_object_acl: bool,
+    /// 任务处于 processing 超过该秒数视为 worker 已崩溃，重置回 pending
+    pub processing_timeout_secs: i64,
+    /// 可重试的失败（下载/存储/推理服务的瞬时故障）与卡住的任务最多重新排队的次数，超过后标记失败；
+    /// 文件过大、格式不支持、文件损坏等永久性错误不重试
+    pub task_max_retries: i32,
+    /// 重试的退避基数：第 n 次重试延后 base * 2^(n-1) 秒
+    pub task_retry_delay_secs: i64,
+    /// 组图部分完成超过该秒数后，已完成的成员标记完成、卡住的成员标记失败（0 表示一直等待整组完成）
+    pub album_complete_timeout_secs: i64,
+    /// 早于该天数的 item 由后台任务定期彻底删除（收藏的除外）；未设置时不清理
+    pub retention_days: Option<i64>,
+    /// 待处理（pending/processing）任务的上限，达到后不再接收新消息并回复用户队列已满；未设置时不限制
+    pub max_pending_tasks: Option<i64>,
+    /// 可以通过 inline query（@bot 关键词）搜索全部 item 的 Telegram 用户 id；其他用户只能搜到公开 item
+    pub inline_query_users: Vec<i64>,
+    /// 收录的消息类型（text,image,video,audio,document,animation），不在列表中的消息直接忽略
+    pub ingest_types: Vec<String>,
+    /// 外部 HTTP 请求（VLM/CLIP/embedding 等）的总超时秒数
+    pub http_timeout_secs: u64,
+    /// 外部 HTTP 请求的连接超时秒数
+    pub http_connect_timeout_secs: u64,
+    /// 幂等外部请求遇到 5xx/超时/连接失败时的最大重试次数
+    pub http_max_retries: u32,
+    /// 同时进行的 CLIP 请求上限（所有 worker 与搜索接口共享），用于保护自建推理服务
+    pub clip_concurrency: usize,
+    /// 同时进行的 VLM OCR 请求上限（所有 worker 共享）
+    pub vlm_concurrency: usize,
+    /// 同时进行的文本 embedding 请求上限（所有 worker 与搜索接口共享）
+    pub embedding_concurrency: usize,
+    /// 开启后未带 API token 的读接口只返回 visibility = 'public' 的 item
+    pub restrict_public_reads: bool,
+    /// 在 Telegram 上点该 emoji 把 item 设为公开，取消则恢复私有；未设置时不启用
+    pub reaction_public: Option<String>,
+    /// 可以通过 REACTION_PUBLIC 切换可见性的 Telegram 用户 id；其他人（包括频道中的匿名 reaction）点了也不生效
+    pub reaction_public_users: Vec<i64>,
+    /// 文本向量后端：http（默认，EMBEDDING_API_BASE）或 onnx（需要编译 `onnx` feature）
+    pub embedding_provider: String,
+    /// CLIP 视觉向量后端：http（默认，CLIP_API_URL）或 onnx
+    pub clip_provider: String,
+    /// 本地 BGE-M3 模型目录（model.onnx + tokenizer.json）
+    pub onnx_text_model_dir: String,
+    /// 本地 Chinese-CLIP 模型目录（image.onnx + text.onnx + tokenizer.json）
+    pub onnx_clip_model_dir: String,
+    /// 不创建标签的 reaction（emoji 或自定义 emoji id）；状态 reaction 总是被排除
+    pub tag_reaction_denylist: Vec<String>,
+    /// 非空时只有列表中的 reaction 会创建标签
+    pub tag_reaction_allowlist: Vec<String>,
+}
+
+const DEFAULT_PUBLIC_KEY_PREFIXES: &[&str] = &["avatars/", "tags/"];
+const DEFAULT_INGEST_TYPES: &str = "text,image,video";
+const SUPPORTED_INGEST_TYPES: &[&str] = &["text", "image", "video", "audio", "document", "animation"];
+
+fn env_parse<T: std::str::FromStr>(key: &str, default: T) -> T {
+    std::env::var(key)
+        .ok()
+        .and_then(|v| v.trim().parse::<T>().ok())
+        .unwrap_or(default)
+}
+
+/// 逗号分隔的列表，去掉空项
+fn env_list(key: &str) -> Vec<String> {
+    std::env::var(key)
+        .unwrap_or_default()
+        .split(',')
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+fn env_bool(key: &str, default: bool) -> bool {
+    match std::env::var(key) {
+        Ok(v) => matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on"),
+        Err(_) => default,
+    }
 }
 
 impl Config {
     pub fn from_env() -> Self {
         // We can use dotenvy before calling this in main
         let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let db_ssl_root_cert = std::env::var("DB_SSL_ROOT_CERT")
+            .ok()
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty());
+        let db_statement_timeout_ms = Some(env_parse("DB_STATEMENT_TIMEOUT_MS", 0_u64)).filter(|ms| *ms > 0);
         let s3_endpoint = std::env::var("S3_ENDPOINT").expect("S3_ENDPOINT must be set");
-        let s3_public_endpoint = std::env::var("S3_PUBLIC_ENDPOINT").unwrap_or_else(|_| "http://localhost:9000".to_string());
+        // 预签名 URL 的签名包含 host，必须用浏览器实际访问的地址来签，不能签完再替换 host
+        let s3_public_endpoint = std::env::var("S3_PUBLIC_ENDPOINT").unwrap_or_else(|_| s3_endpoint.clone());
         let s3_access_key = std::env::var("S3_ACCESS_KEY").expect("S3_ACCESS_KEY must be set");
         let s3_secret_key = std::env::var("S3_SECRET_KEY").expect("S3_SECRET_KEY must be set");
         let s3_bucket = std::env::var("S3_BUCKET").unwrap_or_else(|_| "brainpile".to_string());
+        let s3_region = std::env::var("S3_REGION")
+            .ok()
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| "us-east-1".to_string());
+        let s3_path_style = env_bool("S3_PATH_STYLE", true);
         
-        let clip_api_url = std::env::var("CLIP_API_URL").expect("CLIP_API_URL must be set");
+        let embedding_provider = std::env::var("EMBEDDING_PROVIDER")
+            .unwrap_or_else(|_| "http".to_string())
+            .trim()
+            .to_ascii_lowercase();
+        let clip_provider = std::env::var("CLIP_PROVIDER")
+            .unwrap_or_else(|_| "http".to_string())
+            .trim()
+            .to_ascii_lowercase();
+        // 使用本地 ONNX 模型时不需要对应的 HTTP 服务地址
+        let http_env = |key: &str, provider: &str| match std::env::var(key) {
+            Ok(v) => v,
+            Err(_) if provider == "onnx" => String::new(),
+            Err(_) => panic!("{} must be set", key),
+        };
+
+        let clip_api_url = http_env("CLIP_API_URL", &clip_provider);
         
         let vlm_api_base = std::env::var("VLM_API_BASE").expect("VLM_API_BASE must be set");
         let vlm_api_key = std::env::var("VLM_API_KEY").expect("VLM_API_KEY must be set");
         let vlm_model = std::env::var("VLM_MODEL").expect("VLM_MODEL must be set");
+
+        let summary_enabled = env_bool("SUMMARY_ENABLED", false);
+        let summary_api_base = std::env::var("SUMMARY_API_BASE").unwrap_or_else(|_| vlm_api_base.clone());
+        let summary_api_key = std::env::var("SUMMARY_API_KEY").unwrap_or_else(|_| vlm_api_key.clone());
+        let summary_model = std::env::var("SUMMARY_MODEL").unwrap_or_else(|_| vlm_model.clone());
+        let summary_min_chars = env_parse("SUMMARY_MIN_CHARS", 500_usize);
         
-        let embedding_api_base = std::env::var("EMBEDDING_API_BASE").expect("EMBEDDING_API_BASE must be set");
-        let embedding_api_key = std::env::var("EMBEDDING_API_KEY").expect("EMBEDDING_API_KEY must be set");
-        let embedding_model = std::env::var("EMBEDDING_MODEL").expect("EMBEDDING_MODEL must be set");
+        let embedding_api_base = http_env("EMBEDDING_API_BASE", &embedding_provider);
+        let embedding_api_key = http_env("EMBEDDING_API_KEY", &embedding_provider);
+        let embedding_model = http_env("EMBEDDING_MODEL", &embedding_provider);
+        // 前缀通常以空格结尾，这里不 trim
+        let embedding_query_prefix = std::env::var("EMBEDDING_QUERY_PREFIX").unwrap_or_default();
+        let embedding_passage_prefix = std::env::var("EMBEDDING_PASSAGE_PREFIX").unwrap_or_default();
+        let max_embed_chars = env_parse("MAX_EMBED_CHARS", 8000_usize);
+        let embed_chunking = env_bool("EMBED_CHUNKING", false);
         
         let tg_bot_token = std::env::var("TG_BOT_TOKEN").expect("TG_BOT_TOKEN must be set");
+        let telegram_api_url = std::env::var("TELEGRAM_API_URL")
+            .ok()
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty());
+
+        let detect_language = env_bool("DETECT_LANGUAGE", false);
+
+        let reaction_processing = std::env::var("REACTION_PROCESSING").unwrap_or_else(|_| reactions::DEFAULT_PROCESSING.to_string());
+        let reaction_done = std::env::var("REACTION_DONE").unwrap_or_else(|_| reactions::DEFAULT_DONE.to_string());
+        let reaction_failed = std::env::var("REACTION_FAILED").unwrap_or_else(|_| reactions::DEFAULT_FAILED.to_string());
+        let reply_on_success = env_bool("REPLY_ON_SUCCESS", false);
+        let reply_ttl_secs = env_parse("REPLY_TTL_SECS", 30_u64);
+
+        // worker 每次领取的任务数（>1 时同批任务并发处理）
+        let worker_batch_claim = env_parse("WORKER_BATCH_CLAIM", 1_i64).max(1);
+
+        let api_token = std::env::var("API_TOKEN").ok().filter(|t| !t.trim().is_empty());
+        let reindex_rate_per_min = env_parse("REINDEX_RATE_PER_MIN", 60_i64).max(1);
+
+        let default_search_limit = env_parse("DEFAULT_SEARCH_LIMIT", 50_i64);
+        let max_search_limit = env_parse("MAX_SEARCH_LIMIT", 100_i64);
+        let per_channel_recall = env_parse("PER_CHANNEL_RECALL", 100_i64);
+        let search_cache_ttl_secs = env_parse("SEARCH_CACHE_TTL_SECS", 30_u64);
+        let search_strict_recall = env_bool("SEARCH_STRICT_RECALL", false);
+        let fts_query_mode = std::env::var("FTS_QUERY_MODE")
+            .map(|v| v.trim().to_ascii_lowercase())
+            .ok()
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| "websearch".to_string());
+
+        let min_visual_sim = env_parse("MIN_VISUAL_SIM", 0.0_f32);
+        let min_text_sim = env_parse("MIN_TEXT_SIM", 0.0_f32);
+
+        let thumb_jpeg_quality = env_parse("THUMB_JPEG_QUALITY", 80_u8).clamp(1, 100);
+        let thumb_min_source_dim = env_parse("THUMB_MIN_SOURCE_DIM", 0_u32);
+        let thumb_watermark_path = std::env::var("THUMB_WATERMARK_PATH")
+            .ok()
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty());
+        let phash_max_distance = env_parse("PHASH_MAX_DISTANCE", 6_i32).clamp(0, 64);
+
+        let s3_original_storage_class = std::env::var("S3_ORIGINAL_STORAGE_CLASS")
+            .ok()
+            .map(|v| v.trim().to_ascii_uppercase())
+            .filter(|v| !v.is_empty());
+
+        let s3_multipart_threshold = env_parse("S3_MULTIPART_THRESHOLD_MB", 32_usize).max(5) * 1024 * 1024;
+        let s3_multipart_part_size = env_parse("S3_MULTIPART_PART_SIZE_MB", 8_usize).clamp(5, 5 * 1024) * 1024 * 1024;
+
+        let store_originals = env_bool("STORE_ORIGINALS", true);
+
+        let album_tag_propagate = env_bool("ALBUM_TAG_PROPAGATE", true);
+
+        let storyboard_frames = env_parse("STORYBOARD_FRAMES", 0_u32).min(100);
+        let storyboard_min_duration = env_parse("STORYBOARD_MIN_DURATION", 10.0_f64);
+        let video_smart_thumb = env_bool("VIDEO_SMART_THUMB", false);
+        let video_embed_frames = env_parse("VIDEO_EMBED_FRAMES", 1_u32).clamp(1, 16);
+        let fail_on_unreadable_media = env_bool("FAIL_ON_UNREADABLE_MEDIA", false);
+
+        let text_cards = env_bool("TEXT_CARDS", false);
+        let text_card_font = std::env::var("TEXT_CARD_FONT")
+            .unwrap_or_else(|_| "/usr/share/fonts/opentype/noto/NotoSansCJK-Regular.ttc".to_string());
+
+        let note_searchable = env_bool("NOTE_SEARCHABLE", false);
+
+        let default_page_size = env_parse("DEFAULT_PAGE_SIZE", 20_i64);
+        let max_page_size = env_parse("MAX_PAGE_SIZE", 100_i64);
+
+        let ocr_min_text_likelihood = env_parse("OCR_MIN_TEXT_LIKELIHOOD", 0.0_f32).clamp(0.0, 1.0);
+
+        let cdn_base_url = std::env::var("CDN_BASE_URL")
+            .ok()
+            .map(|v| v.trim().trim_end_matches('/').to_string())
+            .filter(|v| !v.is_empty());
+
+        let mut public_key_prefixes = env_list("PUBLIC_KEY_PREFIXES");
+        if public_key_prefixes.is_empty() {
+            public_key_prefixes = DEFAULT_PUBLIC_KEY_PREFIXES.iter().map(|p| p.to_string()).collect();
+        }
+        let s3_object_acl = env_bool("S3_OBJECT_ACL", true);
+
+        let processing_timeout_secs = env_parse("PROCESSING_TIMEOUT_SECS", 1800_i64).max(60);
+        let task_max_retries = env_parse("TASK_MAX_RETRIES", 2_i32).clamp(0, 10);
+        let task_retry_delay_secs = env_parse("TASK_RETRY_DELAY_SECS", 30_i64).max(1);
+        let album_complete_timeout_secs = env_parse("ALBUM_COMPLETE_TIMEOUT_SECS", 0_i64).max(0);
+        let retention_days = Some(env_parse("RETENTION_DAYS", 0_i64)).filter(|d| *d > 0);
+        let max_pending_tasks = Some(env_parse("MAX_PENDING_TASKS", 0_i64)).filter(|n| *n > 0);
+        let inline_query_users: Vec<i64> = env_list("INLINE_QUERY_USERS")
+            .iter()
+            .map(|id| id.parse().unwrap_or_else(|_| panic!("INLINE_QUERY_USERS: invalid user id {}", id)))
+            .collect();
+
+        let ingest_types: Vec<String> = std::env::var("INGEST_TYPES")
+            .unwrap_or_else(|_| DEFAULT_INGEST_TYPES.to_string())
+            .split(',')
+            .map(|t| t.trim().to_ascii_lowercase())
+            .filter(|t| !t.is_empty())
+            .collect();
+
+        let http_timeout_secs = env_parse("HTTP_TIMEOUT_SECS", 120_u64).max(1);
+        let http_connect_timeout_secs = env_parse("HTTP_CONNECT_TIMEOUT_SECS", 10_u64).max(1);
+        let http_max_retries = env_parse("HTTP_MAX_RETRIES", 2_u32).min(10);
+        // 默认值足够大，相当于不限制
+        let max_permits = tokio::sync::Semaphore::MAX_PERMITS;
+        let clip_concurrency = env_parse("CLIP_CONCURRENCY", 1024_usize).clamp(1, max_permits);
+        let vlm_concurrency = env_parse("VLM_CONCURRENCY", 1024_usize).clamp(1, max_permits);
+        let embedding_concurrency = env_parse("EMBEDDING_CONCURRENCY", 1024_usize).clamp(1, max_permits);
+
+        let restrict_public_reads = env_bool("RESTRICT_PUBLIC_READS", false);
+        let reaction_public = std::env::var("REACTION_PUBLIC")
+            .ok()
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty());
+        let reaction_public_users: Vec<i64> = env_list("REACTION_PUBLIC_USERS")
+            .iter()
+            .map(|id| id.parse().unwrap_or_else(|_| panic!("REACTION_PUBLIC_USERS: invalid user id {}", id)))
+            .collect();
+
+        let onnx_text_model_dir = std::env::var("ONNX_TEXT_MODEL_DIR").unwrap_or_else(|_| "/data/models/bge-m3".to_string());
+        let onnx_clip_model_dir = std::env::var("ONNX_CLIP_MODEL_DIR").unwrap_or_else(|_| "/data/models/cn-clip".to_string());
+
+        let tag_reaction_denylist = env_list("TAG_REACTION_DENYLIST");
+        let tag_reaction_allowlist = env_list("TAG_REACTION_ALLOWLIST");
 
         Self {
             database_url,
+            db_ssl_root_cert,
+            db_statement_timeout_ms,
             s3_endpoint,
             s3_public_endpoint,
             s3_access_key,
             s3_secret_key,
             s3_bucket,
+            s3_region,
+            s3_path_style,
             clip_api_url,
             vlm_api_base,
             vlm_api_key,
             vlm_model,
+            summary_enabled,
+            summary_api_base,
+            summary_api_key,
+            summary_model,
+            summary_min_chars,
             embedding_api_base,
             embedding_api_key,
             embedding_model,
+            embedding_query_prefix,
+            embedding_passage_prefix,
+            max_embed_chars,
+            embed_chunking,
             tg_bot_token,
+            telegram_api_url,
+            detect_language,
+            reaction_processing: reaction_processing.trim().to_string(),
+            reaction_done: reaction_done.trim().to_string(),
+            reaction_failed: reaction_failed.trim().to_string(),
+            reply_on_success,
+            reply_ttl_secs,
+            worker_batch_claim,
+            api_token,
+            reindex_rate_per_min,
+            default_search_limit,
+            max_search_limit,
+            per_channel_recall,
+            search_cache_ttl_secs,
+            search_strict_recall,
+            fts_query_mode,
+            min_visual_sim,
+            min_text_sim,
+            thumb_jpeg_quality,
+            thumb_min_source_dim,
+            thumb_watermark_path,
+            phash_max_distance,
+            s3_original_storage_class,
+            s3_multipart_threshold,
+            s3_multipart_part_size,
+            store_originals,
+            album_tag_propagate,
+            storyboard_frames,
+            storyboard_min_duration,
+            video_smart_thumb,
+            video_embed_frames,
+            fail_on_unreadable_media,
+            text_cards,
+            text_card_font,
+            note_searchable,
+            default_page_size,
+            max_page_size,
+            ocr_min_text_likelihood,
+            cdn_base_url,
+            public_key_prefixes,
+            s3_object_acl,
+            processing_timeout_secs,
+            task_max_retries,
+            task_retry_delay_secs,
+            album_complete_timeout_secs,
+            retention_days,
+            max_pending_tasks,
+            inline_query_users,
+            ingest_types,
+            http_timeout_secs,
+            http_connect_timeout_secs,
+            http_max_retries,
+            clip_concurrency,
+            vlm_concurrency,
+            embedding_concurrency,
+            restrict_public_reads,
+            reaction_public,
+            reaction_public_users,
+            embedding_provider,
+            clip_provider,
+            onnx_text_model_dir,
+            onnx_clip_model_dir,
+            tag_reaction_denylist,
+            tag_reaction_allowlist,
+        }
+    }
+
+    /// 该 reaction（emoji 或自定义 emoji id）是否应该变成标签：
+    /// bot 自己的状态 reaction 与黑名单排除，白名单非空时只放行白名单
+    pub fn reaction_creates_tag(&self, icon_value: &str) -> bool {
+        let is_status = [&self.reaction_processing, &self.reaction_done, &self.reaction_failed]
+            .iter()
+            .any(|s| s.as_str() == icon_value);
+        if is_status || self.tag_reaction_denylist.iter().any(|v| v == icon_value) {
+            return false;
+        }
+        self.tag_reaction_allowlist.is_empty() || self.tag_reaction_allowlist.iter().any(|v| v == icon_value)
+    }
+
+    /// 该对象 key 是否属于公开资源（头像、标签图标等）
+    pub fn is_public_key(&self, key: &str) -> bool {
+        self.public_key_prefixes.iter().any(|p| key.starts_with(p.as_str()))
+    }
+
+    /// 上传该 key 时使用的 canned ACL（x-amz-acl）；关闭 S3_OBJECT_ACL 时不设置
+    pub fn object_acl(&self, key: &str) -> Option<&'static str> {
+        self.s3_object_acl
+            .then(|| if self.is_public_key(key) { "public-read" } else { "private" })
+    }
+
+    /// 该类型的消息是否需要收录
+    pub fn ingests(&self, item_type: &str) -> bool {
+        self.ingest_types.iter().any(|t| t == item_type)
+    }
+
+    /// 启动时校验配置的合法性
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(path) = &self.db_ssl_root_cert {
+            if !std::path::Path::new(path).is_file() {
+                return Err(format!("DB_SSL_ROOT_CERT file not found: {}", path));
+            }
+        }
+        for (key, value) in [
+            ("REACTION_PROCESSING", &self.reaction_processing),
+            ("REACTION_DONE", &self.reaction_done),
+            ("REACTION_FAILED", &self.reaction_failed),
+        ] {
+            if !reactions::is_single_emoji(value) {
+                return Err(format!("{} must be a single emoji, got {:?}", key, value));
+            }
+        }
+        if let Some(emoji) = &self.reaction_public {
+            if !reactions::is_single_emoji(emoji) {
+                return Err(format!("REACTION_PUBLIC must be a single emoji, got {:?}", emoji));
+            }
+            if [&self.reaction_processing, &self.reaction_done, &self.reaction_failed].contains(&emoji) {
+                return Err("REACTION_PUBLIC must differ from the status reactions".to_string());
+            }
+        }
+        for (key, value) in [
+            ("EMBEDDING_PROVIDER", &self.embedding_provider),
+            ("CLIP_PROVIDER", &self.clip_provider),
+        ] {
+            match value.as_str() {
+                "http" => {}
+                "onnx" if cfg!(feature = "onnx") => {}
+                "onnx" => return Err(format!("{}=onnx requires building with the `onnx` feature", key)),
+                other => return Err(format!("{} must be http or onnx, got {:?}", key, other)),
+            }
+        }
+        if let Some(url) = &self.telegram_api_url {
+            reqwest::Url::parse(url).map_err(|e| format!("TELEGRAM_API_URL is not a valid URL ({}): {:?}", e, url))?;
+        }
+        if crate::db::FtsMatch::parse(&self.fts_query_mode).is_none() {
+            return Err(format!(
+                "FTS_QUERY_MODE must be one of websearch, plain, raw, phrase, any, got {:?}",
+                self.fts_query_mode
+            ));
+        }
+        if self.ingest_types.is_empty() {
+            return Err("INGEST_TYPES must not be empty".to_string());
+        }
+        for t in &self.ingest_types {
+            if !SUPPORTED_INGEST_TYPES.contains(&t.as_str()) {
+                return Err(format!(
+                    "INGEST_TYPES contains unknown type {:?} (supported: {})",
+                    t,
+                    SUPPORTED_INGEST_TYPES.join(",")
+                ));
+            }
+        }
+        if self.max_page_size < 1 {
+            return Err(format!("MAX_PAGE_SIZE must be >= 1, got {}", self.max_page_size));
+        }
+        if self.default_page_size < 1 || self.default_page_size > self.max_page_size {
+            return Err(format!(
+                "DEFAULT_PAGE_SIZE must be between 1 and MAX_PAGE_SIZE ({}), got {}",
+                self.max_page_size, self.default_page_size
+            ));
+        }
+        if self.max_search_limit < 1 {
+            return Err(format!("MAX_SEARCH_LIMIT must be >= 1, got {}", self.max_search_limit));
+        }
+        if self.default_search_limit < 1 || self.default_search_limit > self.max_search_limit {
+            return Err(format!(
+                "DEFAULT_SEARCH_LIMIT must be between 1 and MAX_SEARCH_LIMIT ({}), got {}",
+                self.max_search_limit, self.default_search_limit
+            ));
+        }
+        if self.per_channel_recall < self.max_search_limit {
+            return Err(format!(
+                "PER_CHANNEL_RECALL ({}) must be >= MAX_SEARCH_LIMIT ({})",
+                self.per_channel_recall, self.max_search_limit
+            ));
+        }
+        for (key, value) in [("MIN_VISUAL_SIM", self.min_visual_sim), ("MIN_TEXT_SIM", self.min_text_sim)] {
+            if !(-1.0..=1.0).contains(&value) {
+                return Err(format!("{} must be within [-1, 1], got {}", key, value));
+            }
         }
+        Ok(())
     }
 }
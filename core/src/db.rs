@@ -1,11 +1,78 @@
-use sqlx::postgres::PgPoolOptions;
+use crate::config::Config;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
 use sqlx::PgPool;
+use std::str::FromStr;
 
-pub async fn init_pool(database_url: &str) -> Result<PgPool, sqlx::Error> {
+/// 按 DATABASE_URL 建立连接池；DB_SSL_ROOT_CERT、DB_STATEMENT_TIMEOUT_MS 叠加在 URL 的参数之上
+pub async fn init_pool(config: &Config) -> Result<PgPool, sqlx::Error> {
+    let mut options = PgConnectOptions::from_str(&config.database_url)?;
+    if let Some(path) = &config.db_ssl_root_cert {
+        options = options.ssl_root_cert(path);
+    }
+    if let Some(ms) = config.db_statement_timeout_ms {
+        options = options.options([("statement_timeout", ms.to_string())]);
+    }
     PgPoolOptions::new()
         .max_connections(20)
-        .connect(database_url)
+        .connect_with(options)
+        .await
+}
+
+/// items.text_embedding（BGE-M3）与 items.visual_embedding（Chinese-CLIP ViT-L-14）的维度
+pub const TEXT_EMBEDDING_DIM: i32 = 1024;
+pub const VISUAL_EMBEDDING_DIM: i32 = 768;
+
+/// 迁移前检查数据库是否提供 pgvector 扩展；没有时迁移会在 CREATE EXTENSION 处失败，错误信息不直观
+pub async fn check_pgvector_available(pool: &PgPool) -> Result<(), String> {
+    let available: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM pg_available_extensions WHERE name = 'vector')",
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(|e| format!("failed to query available extensions: {}", e))?;
+    if !available {
+        return Err(
+            "the pgvector extension is not installed on this PostgreSQL server; \
+             use an image with pgvector (e.g. pgvector/pgvector:pg16) or install it manually"
+                .to_string(),
+        );
+    }
+    Ok(())
+}
+
+/// 迁移后确认 vector 扩展已启用、向量列的维度与模型输出一致，避免运行时 `::vector` 转换才报错
+pub async fn verify_vector_columns(pool: &PgPool) -> Result<(), String> {
+    let installed: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'vector')")
+        .fetch_one(pool)
+        .await
+        .map_err(|e| format!("failed to query installed extensions: {}", e))?;
+    if !installed {
+        return Err("the pgvector extension is not enabled in this database (CREATE EXTENSION vector)".to_string());
+    }
+
+    for (column, expected) in [("text_embedding", TEXT_EMBEDDING_DIM), ("visual_embedding", VISUAL_EMBEDDING_DIM)] {
+        // vector(n) 的 atttypmod 即维度
+        let dim: Option<i32> = sqlx::query_scalar(
+            r#"
+            SELECT a.atttypmod
+            FROM pg_attribute a
+            JOIN pg_type t ON t.oid = a.atttypid
+            WHERE a.attrelid = 'items'::regclass AND a.attname = $1 AND NOT a.attisdropped AND t.typname = 'vector'
+            "#,
+        )
+        .bind(column)
+        .fetch_optional(pool)
         .await
+        .map_err(|e| format!("failed to inspect items.{}: {}", column, e))?;
+        match dim {
+            None => return Err(format!("items.{} is missing or is not a vector column", column)),
+            Some(dim) if dim != expected => {
+                return Err(format!("items.{} has dimension {}, expected {}", column, dim, expected));
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(())
 }
 
 /// 搜索结果项（用于召回阶段）
@@ -13,33 +80,60 @@ pub async fn init_pool(database_url: &str) -> Result<PgPool, sqlx::Error> {
 pub struct SearchHit {
     pub id: i64,
     pub rank: usize,  // 在该路召回中的排名（从 1 开始）
+    pub score: f32,   // 该路的原始分数：向量召回为余弦相似度，全文检索为 ts_rank
+}
+
+/// 召回阶段的过滤条件：在每一路查询内部生效，保证融合与截断前的候选都满足条件，
+/// 否则公开访问等场景会在截断后被过滤成残缺的一页
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SearchFilter<'a> {
+    /// 只召回 public item（RESTRICT_PUBLIC_READS 且未带 token）
+    pub public_only: bool,
+    pub item_type: Option<&'a str>,
+    pub lang: Option<&'a str>,
+}
+
+impl<'a> SearchFilter<'a> {
+    /// 以 $3..$5 为参数的 WHERE 条件片段，配合 bind 使用
+    const SQL: &'static str =
+        "(NOT $3 OR visibility = 'public') AND ($4::text IS NULL OR item_type = $4) AND ($5::text IS NULL OR lang = $5)";
+
+    fn bind<'q>(
+        &self,
+        query: sqlx::query::Query<'q, sqlx::Postgres, sqlx::postgres::PgArguments>,
+    ) -> sqlx::query::Query<'q, sqlx::Postgres, sqlx::postgres::PgArguments>
+    where
+        'a: 'q,
+    {
+        query.bind(self.public_only).bind(self.item_type).bind(self.lang)
+    }
 }
 
 /// 文本向量召回（text_embedding KNN）
-/// 返回 (id, rank) 列表，按相似度降序
+/// 返回 (id, rank, score) 列表，按相似度降序
 pub async fn search_text_vec(
     pool: &PgPool,
     query_embedding: &[f32],
     limit: i64,
+    filter: SearchFilter<'_>,
 ) -> Result<Vec<SearchHit>, sqlx::Error> {
     let embedding_str = format!(
         "[{}]",
         query_embedding.iter().map(|f| f.to_string()).collect::<Vec<_>>().join(",")
     );
     
-    let rows = sqlx::query(
+    let sql = format!(
         r#"
-        SELECT id
+        SELECT id, (1 - (text_embedding <=> $1::vector))::float8 AS score
         FROM items
-        WHERE text_embedding IS NOT NULL
+        WHERE text_embedding IS NOT NULL AND {}
         ORDER BY text_embedding <=> $1::vector
         LIMIT $2
-        "#
-    )
-    .bind(&embedding_str)
-    .bind(limit)
-    .fetch_all(pool)
-    .await?;
+        "#,
+        SearchFilter::SQL
+    );
+    let query = sqlx::query(&sql).bind(&embedding_str).bind(limit);
+    let rows = filter.bind(query).fetch_all(pool).await?;
     
     Ok(rows
         .iter()
@@ -47,35 +141,36 @@ pub async fn search_text_vec(
         .map(|(i, row)| SearchHit {
             id: sqlx::Row::get(row, "id"),
             rank: i + 1,
+            score: sqlx::Row::get::<f64, _>(row, "score") as f32,
         })
         .collect())
 }
 
 /// 视觉向量召回（visual_embedding KNN）
-/// 返回 (id, rank) 列表，按相似度降序
+/// 返回 (id, rank, score) 列表，按相似度降序
 pub async fn search_visual_vec(
     pool: &PgPool,
     query_embedding: &[f32],
     limit: i64,
+    filter: SearchFilter<'_>,
 ) -> Result<Vec<SearchHit>, sqlx::Error> {
     let embedding_str = format!(
         "[{}]",
         query_embedding.iter().map(|f| f.to_string()).collect::<Vec<_>>().join(",")
     );
     
-    let rows = sqlx::query(
+    let sql = format!(
         r#"
-        SELECT id
+        SELECT id, (1 - (visual_embedding <=> $1::vector))::float8 AS score
         FROM items
-        WHERE visual_embedding IS NOT NULL
+        WHERE visual_embedding IS NOT NULL AND {}
         ORDER BY visual_embedding <=> $1::vector
         LIMIT $2
-        "#
-    )
-    .bind(&embedding_str)
-    .bind(limit)
-    .fetch_all(pool)
-    .await?;
+        "#,
+        SearchFilter::SQL
+    );
+    let query = sqlx::query(&sql).bind(&embedding_str).bind(limit);
+    let rows = filter.bind(query).fetch_all(pool).await?;
     
     Ok(rows
         .iter()
@@ -83,31 +178,245 @@ pub async fn search_visual_vec(
         .map(|(i, row)| SearchHit {
             id: sqlx::Row::get(row, "id"),
             rank: i + 1,
+            score: sqlx::Row::get::<f64, _>(row, "score") as f32,
         })
         .collect())
 }
 
-/// 全文检索召回（GIN tsvector + websearch_to_tsquery）
-/// 返回 (id, rank) 列表，按 ts_rank 降序
+/// 全文检索查询词的解析方式（搜索接口的 match 参数）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FtsMatch {
+    /// websearch_to_tsquery：支持引号短语、or、-排除（默认）
+    Web,
+    /// phraseto_tsquery：所有词按顺序相邻出现
+    Phrase,
+    /// 任意一个词出现即可
+    Any,
+    /// plainto_tsquery：所有词都要出现，顺序不限
+    All,
+    /// to_tsquery：支持前缀（foo:*）与布尔运算符（& | ! <-> 括号），输入先经 sanitize_raw_tsquery 整理
+    Raw,
+}
+
+impl FtsMatch {
+    /// websearch / plain 是 web / all 的别名
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "web" | "websearch" => Some(Self::Web),
+            "phrase" => Some(Self::Phrase),
+            "any" => Some(Self::Any),
+            "all" | "plain" => Some(Self::All),
+            "raw" => Some(Self::Raw),
+            _ => None,
+        }
+    }
+
+    /// 以 $1 为查询词的 tsquery 表达式
+    fn tsquery_sql(self) -> &'static str {
+        match self {
+            Self::Web => "websearch_to_tsquery('simple', $1)",
+            Self::Phrase => "phraseto_tsquery('simple', $1)",
+            // plainto_tsquery 的输出是带引号的词位用 & 连接，换成 | 即为任意词匹配
+            Self::Any => "replace(plainto_tsquery('simple', $1)::text, ' & ', ' | ')::tsquery",
+            Self::All => "plainto_tsquery('simple', $1)",
+            Self::Raw => "to_tsquery('simple', $1)",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RawToken {
+    Term(String),
+    Not,
+    Open,
+    Close,
+    /// & | <-> <N>
+    Binary(String),
+}
+
+/// 把用户输入整理成 to_tsquery 一定能解析的表达式，避免语法错误让查询直接报错：
+/// 词之间缺运算符时补 &，丢弃悬空的运算符、不成对的括号与空括号，其余标点视为分隔符。
+/// 词可以带 :* 前缀匹配或 :A 之类的权重限定
+fn sanitize_raw_tsquery(input: &str) -> String {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_alphanumeric() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let mut term: String = chars[start..i].iter().collect();
+            if i < chars.len() && chars[i] == ':' {
+                let suffix_start = i + 1;
+                let mut end = suffix_start;
+                while end < chars.len() && matches!(chars[end], '*' | 'A'..='D' | 'a'..='d') {
+                    end += 1;
+                }
+                if end > suffix_start {
+                    term.push(':');
+                    term.extend(&chars[suffix_start..end]);
+                    i = end;
+                }
+            }
+            tokens.push(RawToken::Term(term));
+            continue;
+        }
+        match c {
+            '!' => tokens.push(RawToken::Not),
+            '(' => tokens.push(RawToken::Open),
+            ')' => tokens.push(RawToken::Close),
+            '&' | '|' => tokens.push(RawToken::Binary(c.to_string())),
+            '<' => {
+                // <-> 或 <N>
+                let end = chars[i + 1..].iter().position(|&c| c == '>').map(|p| i + 1 + p);
+                if let Some(end) = end {
+                    let inner: String = chars[i + 1..end].iter().collect();
+                    if inner == "-" || (!inner.is_empty() && inner.chars().all(|c| c.is_ascii_digit())) {
+                        tokens.push(RawToken::Binary(format!("<{}>", inner)));
+                        i = end + 1;
+                        continue;
+                    }
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    // 期望操作数时遇到的运算符、右括号都是悬空的
+    let mut out: Vec<RawToken> = Vec::new();
+    let mut depth = 0_usize;
+    let expects_operand = |out: &[RawToken]| {
+        !matches!(out.last(), Some(RawToken::Term(_)) | Some(RawToken::Close))
+    };
+    let pop_dangling = |out: &mut Vec<RawToken>| {
+        while matches!(out.last(), Some(RawToken::Binary(_)) | Some(RawToken::Not)) {
+            out.pop();
+        }
+    };
+    for token in tokens {
+        match token {
+            RawToken::Term(_) | RawToken::Not | RawToken::Open => {
+                if !expects_operand(&out) {
+                    out.push(RawToken::Binary("&".to_string()));
+                }
+                if token == RawToken::Open {
+                    depth += 1;
+                }
+                out.push(token);
+            }
+            RawToken::Binary(_) => {
+                if !expects_operand(&out) {
+                    out.push(token);
+                }
+            }
+            RawToken::Close => {
+                if depth == 0 {
+                    continue;
+                }
+                pop_dangling(&mut out);
+                if out.last() == Some(&RawToken::Open) {
+                    // 空括号整体丢弃
+                    out.pop();
+                    depth -= 1;
+                    pop_dangling(&mut out);
+                } else {
+                    out.push(RawToken::Close);
+                    depth -= 1;
+                }
+            }
+        }
+    }
+    loop {
+        pop_dangling(&mut out);
+        if out.last() == Some(&RawToken::Open) {
+            out.pop();
+            depth -= 1;
+        } else {
+            break;
+        }
+    }
+    out.extend(std::iter::repeat_n(RawToken::Close, depth));
+
+    out.iter()
+        .map(|token| match token {
+            RawToken::Term(term) => term.as_str(),
+            RawToken::Not => "!",
+            RawToken::Open => "(",
+            RawToken::Close => ")",
+            RawToken::Binary(op) => op.as_str(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// 全文检索召回（GIN tsvector，查询词解析方式见 FtsMatch）
+/// 使用加权的 search_tsv：说明文字（权重 A）命中排在只有 OCR 等文本（权重 B）命中之前
+/// 返回 (id, rank, score) 列表，按 ts_rank 降序
 pub async fn search_fts(
     pool: &PgPool,
     query: &str,
+    mode: FtsMatch,
     limit: i64,
+    filter: SearchFilter<'_>,
 ) -> Result<Vec<SearchHit>, sqlx::Error> {
-    let rows = sqlx::query(
+    let tsquery = mode.tsquery_sql();
+    let query = if mode == FtsMatch::Raw {
+        std::borrow::Cow::Owned(sanitize_raw_tsquery(query))
+    } else {
+        std::borrow::Cow::Borrowed(query)
+    };
+    let sql = format!(
+        r#"
+        SELECT id, ts_rank(search_tsv, {tsquery}) AS score
+        FROM items
+        WHERE search_tsv @@ {tsquery} AND {filter_sql}
+        ORDER BY ts_rank(search_tsv, {tsquery}) DESC
+        LIMIT $2
+        "#,
+        filter_sql = SearchFilter::SQL
+    );
+    let rows = filter
+        .bind(sqlx::query(&sql).bind(query.as_ref()).bind(limit))
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows
+        .iter()
+        .enumerate()
+        .map(|(i, row)| SearchHit {
+            id: sqlx::Row::get(row, "id"),
+            rank: i + 1,
+            score: sqlx::Row::get::<f32, _>(row, "score"),
+        })
+        .collect())
+}
+
+/// 三元组模糊召回（pg_trgm），用于 FTS 对拼写错误、部分词无结果时兜底
+/// 用 word_similarity（`<%`）而不是整体 similarity：查询词通常远短于 searchable_text，
+/// 整体相似度会被长文本稀释到阈值以下
+pub async fn search_trgm(
+    pool: &PgPool,
+    query: &str,
+    limit: i64,
+    filter: SearchFilter<'_>,
+) -> Result<Vec<SearchHit>, sqlx::Error> {
+    let sql = format!(
         r#"
-        SELECT id
+        SELECT id, word_similarity($1, searchable_text) AS score
         FROM items
         WHERE searchable_text IS NOT NULL
-          AND to_tsvector('simple', searchable_text) @@ websearch_to_tsquery('simple', $1)
-        ORDER BY ts_rank(to_tsvector('simple', searchable_text), websearch_to_tsquery('simple', $1)) DESC
+          AND $1 <% searchable_text
+          AND {}
+        ORDER BY word_similarity($1, searchable_text) DESC
         LIMIT $2
-        "#
-    )
-    .bind(query)
-    .bind(limit)
-    .fetch_all(pool)
-    .await?;
+        "#,
+        SearchFilter::SQL
+    );
+    let rows = filter.bind(sqlx::query(&sql).bind(query).bind(limit)).fetch_all(pool).await?;
     
     Ok(rows
         .iter()
@@ -115,6 +424,7 @@ pub async fn search_fts(
         .map(|(i, row)| SearchHit {
             id: sqlx::Row::get(row, "id"),
             rank: i + 1,
+            score: sqlx::Row::get::<f32, _>(row, "score"),
         })
         .collect())
 }
@@ -123,21 +433,138 @@ pub async fn search_fts(
 /// k: 平滑常数（通常 60）
 /// 返回按融合分数降序排列的 id 列表
 pub fn rrf_merge(channels: Vec<Vec<SearchHit>>, k: f64, top_n: usize) -> Vec<i64> {
+    rrf_scores(&channels, k)
+        .into_iter()
+        .take(top_n)
+        .map(|(id, _)| id)
+        .collect()
+}
+
+/// 分数加权的 RRF 变体：每条命中的贡献再乘以该路的原始分数，
+/// 同样排名下更相似的结果贡献更大。注意各路分数的量纲不同（ts_rank 通常远小于余弦相似度）
+pub fn rrf_merge_weighted(channels: Vec<Vec<SearchHit>>, k: f64, top_n: usize) -> Vec<i64> {
+    rrf_scores_with(&channels, k, true)
+        .into_iter()
+        .take(top_n)
+        .map(|(id, _)| id)
+        .collect()
+}
+
+/// RRF 融合并保留融合分数，按分数降序（供 explain 等诊断场景使用）
+pub fn rrf_scores(channels: &[Vec<SearchHit>], k: f64) -> Vec<(i64, f64)> {
+    rrf_scores_with(channels, k, false)
+}
+
+/// 分数加权 RRF 的融合分数，按分数降序
+pub fn rrf_scores_weighted(channels: &[Vec<SearchHit>], k: f64) -> Vec<(i64, f64)> {
+    rrf_scores_with(channels, k, true)
+}
+
+fn rrf_scores_with(channels: &[Vec<SearchHit>], k: f64, score_weighted: bool) -> Vec<(i64, f64)> {
     use std::collections::HashMap;
     
-    let mut scores: HashMap<i64, f64> = HashMap::new();
+    // id -> (融合分数, 各路中最好的名次)
+    let mut scores: HashMap<i64, (f64, usize)> = HashMap::new();
     
     for hits in channels {
         for hit in hits {
-            let score = 1.0 / (k + hit.rank as f64);
-            *scores.entry(hit.id).or_insert(0.0) += score;
+            let mut score = 1.0 / (k + hit.rank as f64);
+            if score_weighted {
+                score *= hit.score.max(0.0) as f64;
+            }
+            let entry = scores.entry(hit.id).or_insert((0.0, hit.rank));
+            entry.0 += score;
+            entry.1 = entry.1.min(hit.rank);
         }
     }
     
-    let mut sorted: Vec<(i64, f64)> = scores.into_iter().collect();
-    sorted.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
-    
-    sorted.into_iter().take(top_n).map(|(id, _)| id).collect()
+    // 分数相同时依次按最好名次、id 降序排，保证同一查询的结果顺序稳定（HashMap 迭代顺序不固定）
+    let mut sorted: Vec<(i64, f64, usize)> = scores
+        .into_iter()
+        .map(|(id, (score, best_rank))| (id, score, best_rank))
+        .collect();
+    sorted.sort_by(|a, b| {
+        b.1.total_cmp(&a.1)
+            .then_with(|| a.2.cmp(&b.2))
+            .then_with(|| b.0.cmp(&a.0))
+    });
+    sorted.into_iter().map(|(id, score, _)| (id, score)).collect()
+}
+
+/// 给一批 item 加上标签；propagate_to_album 时同一相册（tg_group_id）的其余 item 一并加上。
+/// 返回实际发生变化的 item 数（已带该标签的不算）
+pub async fn tag_items(
+    pool: &PgPool,
+    item_ids: &[i64],
+    tag_id: i32,
+    propagate_to_album: bool,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        UPDATE items
+        SET tags = array_append(tags, $1)
+        WHERE NOT tags @> ARRAY[$1]::int[]
+          AND (id = ANY($2) OR ($3 AND tg_group_id IN (SELECT tg_group_id FROM items WHERE id = ANY($2))))
+        "#,
+    )
+    .bind(tag_id)
+    .bind(item_ids)
+    .bind(propagate_to_album)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// 从一批 item 上移除标签，相册传播规则同 tag_items
+pub async fn untag_items(
+    pool: &PgPool,
+    item_ids: &[i64],
+    tag_id: i32,
+    propagate_to_album: bool,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        UPDATE items
+        SET tags = array_remove(tags, $1)
+        WHERE tags @> ARRAY[$1]::int[]
+          AND (id = ANY($2) OR ($3 AND tg_group_id IN (SELECT tg_group_id FROM items WHERE id = ANY($2))))
+        "#,
+    )
+    .bind(tag_id)
+    .bind(item_ids)
+    .bind(propagate_to_album)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// 任务队列深度：已到期的 pending 与 processing 任务数。
+/// 按速率错开的重建任务、等待退避重试的任务还没轮到，系统任务（重建索引）不占收录的名额，都不计入
+pub async fn task_queue_depth(pool: &PgPool) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"
+        SELECT COUNT(*) FROM tasks
+        WHERE status IN ('pending', 'processing')
+          AND created_at <= NOW()
+          AND NOT (COALESCE(payload, '{}'::jsonb) ? 'reprocess_item_id')
+        "#,
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// 队列是否已达到 MAX_PENDING_TASKS；查询失败时放行，不因统计出错而拒收
+pub async fn task_queue_full(pool: &PgPool, max_pending_tasks: Option<i64>) -> bool {
+    let Some(max) = max_pending_tasks else {
+        return false;
+    };
+    match task_queue_depth(pool).await {
+        Ok(depth) => depth >= max,
+        Err(e) => {
+            tracing::warn!("Failed to count pending tasks: {}", e);
+            false
+        }
+    }
 }
 
 /// 批量获取 items 详情（按给定 id 顺序返回）
@@ -152,8 +579,8 @@ pub async fn fetch_items_by_ids(
     // 使用 unnest 保持顺序
     let rows = sqlx::query(
         r#"
-         SELECT i.id, i.item_type, i.content_text, i.s3_key, i.thumbnail_key, 
-             i.created_at, i.meta, i.tags, i.tg_group_id
+         SELECT i.id, i.item_type, i.content_text, i.s3_key, i.thumbnail_key, i.storyboard_key, 
+             i.created_at, i.meta, i.tags, i.tg_group_id, i.visibility, i.note, i.lang
         FROM unnest($1::bigint[]) WITH ORDINALITY AS t(id, ord)
         JOIN items i ON i.id = t.id
         ORDER BY t.ord
@@ -165,3 +592,206 @@ pub async fn fetch_items_by_ids(
     
     Ok(rows)
 }
+
+/// 按感知哈希的汉明距离查找近似重复的 items，返回 (id, 距离)，距离小的在前。
+/// 汉明距离无法走索引，这里是全表扫描（只比较一个 BIGINT，个人收藏规模下足够快）
+pub async fn find_near_duplicates(
+    pool: &PgPool,
+    phash: i64,
+    max_distance: i32,
+    exclude_id: Option<i64>,
+    limit: i64,
+) -> Result<Vec<(i64, i32)>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT id, bit_count((phash # $1)::bit(64))::int AS distance
+        FROM items
+        WHERE phash IS NOT NULL
+          AND bit_count((phash # $1)::bit(64)) <= $2
+          AND ($3::bigint IS NULL OR id <> $3)
+        ORDER BY distance, id DESC
+        LIMIT $4
+        "#
+    )
+    .bind(phash)
+    .bind(max_distance)
+    .bind(exclude_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| (sqlx::Row::get(&row, "id"), sqlx::Row::get(&row, "distance")))
+        .collect())
+}
+
+/// 需要 PostgreSQL（带 pgvector）的测试连接 TEST_DATABASE_URL 并执行迁移。
+/// 这类测试标记为 #[ignore]，默认跳过：TEST_DATABASE_URL=postgres://... cargo test -- --ignored
+#[cfg(test)]
+pub(crate) async fn test_pool() -> PgPool {
+    let url = std::env::var("TEST_DATABASE_URL").expect("database tests need TEST_DATABASE_URL");
+    let pool = PgPool::connect(&url).await.expect("connect to TEST_DATABASE_URL");
+    sqlx::migrate!("./migrations").run(&pool).await.expect("migrate test database");
+    pool
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 各测试共用一个库，用随机 id 避免互相干扰
+    fn unique_id() -> i64 {
+        (uuid::Uuid::new_v4().as_u128() >> 65) as i64
+    }
+
+    async fn insert_item(pool: &PgPool, tg_group_id: Option<i64>) -> i64 {
+        sqlx::query_scalar("INSERT INTO items (item_type, content_hash, tg_group_id) VALUES ('image', $1, $2) RETURNING id")
+            .bind(uuid::Uuid::new_v4().simple().to_string())
+            .bind(tg_group_id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    async fn insert_text_item(pool: &PgPool, content_text: &str, searchable_text: &str) -> i64 {
+        sqlx::query_scalar(
+            "INSERT INTO items (item_type, content_hash, content_text, searchable_text) VALUES ('text', $1, $2, $3) RETURNING id",
+        )
+        .bind(uuid::Uuid::new_v4().simple().to_string())
+        .bind(content_text)
+        .bind(searchable_text)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    async fn item_tags(pool: &PgPool, id: i64) -> Vec<i32> {
+        sqlx::query_scalar("SELECT tags FROM items WHERE id = $1").bind(id).fetch_one(pool).await.unwrap()
+    }
+
+    async fn delete_items(pool: &PgPool, ids: &[i64]) {
+        sqlx::query("DELETE FROM items WHERE id = ANY($1)").bind(ids).execute(pool).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn album_tags_propagate_to_siblings() {
+        let pool = test_pool().await;
+        let group = unique_id();
+        let (member, sibling, unrelated) = (
+            insert_item(&pool, Some(group)).await,
+            insert_item(&pool, Some(group)).await,
+            insert_item(&pool, None).await,
+        );
+        let tag: i32 = sqlx::query_scalar("INSERT INTO tags (icon_type, icon_value) VALUES ('emoji', $1) RETURNING id")
+            .bind(unique_id().to_string())
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+
+        let tagged = tag_items(&pool, &[member], tag, true).await.unwrap();
+        let after_tag = (item_tags(&pool, member).await, item_tags(&pool, sibling).await, item_tags(&pool, unrelated).await);
+        let untagged = untag_items(&pool, &[sibling], tag, true).await.unwrap();
+        let after_untag = item_tags(&pool, member).await;
+        // ALBUM_TAG_PROPAGATE=false 时只改动指定的 item
+        let tagged_alone = tag_items(&pool, &[member], tag, false).await.unwrap();
+        let sibling_after_alone = item_tags(&pool, sibling).await;
+
+        delete_items(&pool, &[member, sibling, unrelated]).await;
+        sqlx::query("DELETE FROM tags WHERE id = $1").bind(tag).execute(&pool).await.unwrap();
+
+        assert_eq!(tagged, 2);
+        assert_eq!(after_tag, (vec![tag], vec![tag], vec![]));
+        assert_eq!(untagged, 2);
+        assert!(after_untag.is_empty());
+        assert_eq!(tagged_alone, 1);
+        assert!(sibling_after_alone.is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn caption_matches_rank_above_ocr_matches() {
+        let pool = test_pool().await;
+        let word = format!("w{}", uuid::Uuid::new_v4().simple());
+        // searchable_text 由说明文字与 OCR 文本拼接而成
+        let ocr_only = insert_text_item(&pool, "unrelated caption", &format!("unrelated caption\nscreenshot {}", word)).await;
+        let caption = insert_text_item(&pool, &format!("look at {}", word), &format!("look at {}", word)).await;
+
+        let hits = search_fts(&pool, &word, FtsMatch::Web, 10, SearchFilter::default()).await.unwrap();
+        delete_items(&pool, &[ocr_only, caption]).await;
+
+        assert_eq!(hits.iter().map(|h| h.id).collect::<Vec<_>>(), vec![caption, ocr_only]);
+        assert!(hits[0].score > hits[1].score);
+    }
+
+    fn hit(id: i64, rank: usize) -> SearchHit {
+        SearchHit { id, rank, score: 1.0 }
+    }
+
+    #[test]
+    fn rrf_ties_are_ordered_deterministically() {
+        // 5 与 7、1 与 2 的融合分数分别相同，同分时按 id 降序
+        let channels = vec![
+            vec![hit(5, 1), hit(1, 2)],
+            vec![hit(7, 1), hit(2, 2)],
+            vec![hit(9, 3)],
+        ];
+        let expected = vec![7, 5, 2, 1, 9];
+        let first = rrf_scores(&channels, 60.0);
+        assert_eq!(first.iter().map(|(id, _)| *id).collect::<Vec<_>>(), expected);
+        // HashMap 每次构建的迭代顺序都不同，多跑几次确认结果不受影响
+        for _ in 0..50 {
+            assert_eq!(rrf_scores(&channels, 60.0), first);
+            assert_eq!(rrf_merge(channels.clone(), 60.0, 10), expected);
+        }
+    }
+
+    #[test]
+    fn raw_tsquery_empty_input() {
+        assert_eq!(sanitize_raw_tsquery(""), "");
+        assert_eq!(sanitize_raw_tsquery("  ;,. "), "");
+    }
+
+    #[test]
+    fn raw_tsquery_inserts_missing_and() {
+        assert_eq!(sanitize_raw_tsquery("cat dog"), "cat & dog");
+        assert_eq!(sanitize_raw_tsquery("c++; dog"), "c & dog");
+    }
+
+    #[test]
+    fn raw_tsquery_drops_dangling_operators() {
+        assert_eq!(sanitize_raw_tsquery("cat &"), "cat");
+        assert_eq!(sanitize_raw_tsquery("& cat"), "cat");
+        assert_eq!(sanitize_raw_tsquery("cat | | dog"), "cat | dog");
+        assert_eq!(sanitize_raw_tsquery("cat |"), "cat");
+        assert_eq!(sanitize_raw_tsquery("cat !"), "cat");
+        assert_eq!(sanitize_raw_tsquery("!"), "");
+        assert_eq!(sanitize_raw_tsquery("!cat"), "! cat");
+    }
+
+    #[test]
+    fn raw_tsquery_handles_empty_and_unbalanced_parentheses() {
+        assert_eq!(sanitize_raw_tsquery("()"), "");
+        assert_eq!(sanitize_raw_tsquery("cat ()"), "cat");
+        assert_eq!(sanitize_raw_tsquery("(cat | dog"), "( cat | dog )");
+        assert_eq!(sanitize_raw_tsquery("cat) dog"), "cat & dog");
+        assert_eq!(sanitize_raw_tsquery("(cat &) dog"), "( cat ) & dog");
+        assert_eq!(sanitize_raw_tsquery("((cat"), "( ( cat ) )");
+    }
+
+    #[test]
+    fn raw_tsquery_keeps_prefix_and_weight_suffixes() {
+        assert_eq!(sanitize_raw_tsquery("pre:*"), "pre:*");
+        assert_eq!(sanitize_raw_tsquery("pre:* & title:A"), "pre:* & title:A");
+        assert_eq!(sanitize_raw_tsquery("pre:x"), "pre & x");
+        assert_eq!(sanitize_raw_tsquery(":* cat"), "cat");
+    }
+
+    #[test]
+    fn raw_tsquery_keeps_phrase_operators() {
+        assert_eq!(sanitize_raw_tsquery("cat <-> dog"), "cat <-> dog");
+        assert_eq!(sanitize_raw_tsquery("cat <2> dog"), "cat <2> dog");
+        assert_eq!(sanitize_raw_tsquery("cat <x> dog"), "cat & x & dog");
+    }
+}
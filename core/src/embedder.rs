@@ -0,0 +1,304 @@
+use crate::config::Config;
+use crate::http;
+use crate::media;
+use futures::future::BoxFuture;
+use std::sync::Arc;
+
+/// 向量化调用的结果：Ok(None) 表示后端正常返回但没有给出向量（如接口报错状态码），
+/// Err 表示请求本身失败（网络、超时、模型推理出错）
+pub type EmbedFuture<'a> = BoxFuture<'a, anyhow::Result<Option<Vec<f32>>>>;
+
+/// 文本向量（BGE-M3，对应 items.text_embedding）；query/passage 前缀由调用方拼接
+pub trait TextEmbedder: Send + Sync {
+    fn embed<'a>(&'a self, text: &'a str) -> EmbedFuture<'a>;
+}
+
+/// CLIP 视觉向量（对应 items.visual_embedding）：图片与文本映射到同一空间，用于以文搜图
+pub trait VisualEmbedder: Send + Sync {
+    fn embed_image(&self, image_bytes: Vec<u8>) -> EmbedFuture<'_>;
+    fn embed_text<'a>(&'a self, text: &'a str) -> EmbedFuture<'a>;
+}
+
+/// 图片文字识别；返回模型的原始输出，空结果的判定与日志由调用方处理
+pub trait Ocr: Send + Sync {
+    fn recognize<'a>(&'a self, image_bytes: &'a [u8]) -> BoxFuture<'a, anyhow::Result<Option<String>>>;
+}
+
+/// 长文本摘要；返回模型的原始输出，空结果由调用方处理
+pub trait Summarizer: Send + Sync {
+    fn summarize<'a>(&'a self, text: &'a str) -> BoxFuture<'a, anyhow::Result<Option<String>>>;
+}
+
+/// 按 EMBEDDING_PROVIDER 选择文本向量后端
+pub fn build_text_embedder(config: &Config, client: &reqwest::Client) -> anyhow::Result<Arc<dyn TextEmbedder>> {
+    match config.embedding_provider.as_str() {
+        #[cfg(feature = "onnx")]
+        "onnx" => Ok(Arc::new(crate::onnx::OnnxTextEmbedder::load(&config.onnx_text_model_dir)?)),
+        _ => Ok(Arc::new(HttpTextEmbedder {
+            client: client.clone(),
+            api_base: config.embedding_api_base.clone(),
+            api_key: config.embedding_api_key.clone(),
+            model: config.embedding_model.clone(),
+            max_retries: config.http_max_retries,
+        })),
+    }
+}
+
+/// 按 CLIP_PROVIDER 选择视觉向量后端
+pub fn build_visual_embedder(config: &Config, client: &reqwest::Client) -> anyhow::Result<Arc<dyn VisualEmbedder>> {
+    match config.clip_provider.as_str() {
+        #[cfg(feature = "onnx")]
+        "onnx" => Ok(Arc::new(crate::onnx::OnnxClipEmbedder::load(&config.onnx_clip_model_dir)?)),
+        _ => Ok(Arc::new(HttpVisualEmbedder {
+            client: client.clone(),
+            clip_api_url: config.clip_api_url.clone(),
+            max_retries: config.http_max_retries,
+        })),
+    }
+}
+
+/// VLM OCR 后端（目前只有 HTTP 实现）
+pub fn build_ocr(config: &Config, client: &reqwest::Client) -> Arc<dyn Ocr> {
+    Arc::new(HttpVlmOcr {
+        client: client.clone(),
+        api_base: config.vlm_api_base.clone(),
+        api_key: config.vlm_api_key.clone(),
+        model: config.vlm_model.clone(),
+        max_retries: config.http_max_retries,
+    })
+}
+
+/// 摘要后端（OpenAI 兼容的 chat 接口）；SUMMARY_ENABLED 未开启时为 None
+pub fn build_summarizer(config: &Config, client: &reqwest::Client) -> Option<Arc<dyn Summarizer>> {
+    if !config.summary_enabled {
+        return None;
+    }
+    Some(Arc::new(HttpChatSummarizer {
+        client: client.clone(),
+        api_base: config.summary_api_base.clone(),
+        api_key: config.summary_api_key.clone(),
+        model: config.summary_model.clone(),
+        max_retries: config.http_max_retries,
+    }))
+}
+
+/// OpenAI 兼容的 /embeddings 接口
+pub struct HttpTextEmbedder {
+    client: reqwest::Client,
+    api_base: String,
+    api_key: String,
+    model: String,
+    max_retries: u32,
+}
+
+impl TextEmbedder for HttpTextEmbedder {
+    fn embed<'a>(&'a self, text: &'a str) -> EmbedFuture<'a> {
+        Box::pin(async move {
+            let embedding_url = format!("{}/embeddings", self.api_base);
+            let body = serde_json::json!({
+                "model": self.model,
+                "input": text
+            });
+            let res = http::send_with_retry(self.max_retries, || {
+                self.client
+                    .post(&embedding_url)
+                    .header("Authorization", format!("Bearer {}", self.api_key))
+                    .header("Content-Type", "application/json")
+                    .json(&body)
+            })
+            .await?;
+
+            if !res.status().is_success() {
+                let status = res.status();
+                let text = res.text().await.unwrap_or_default();
+                tracing::warn!("Embedding API error: {} - {}", status, text);
+                return Ok(None);
+            }
+
+            let json: serde_json::Value = res.json().await?;
+            // OpenAI format: {"data": [{"embedding": [...]}]}
+            Ok(json.get("data")
+                .and_then(|d| d.get(0))
+                .and_then(|d| d.get("embedding"))
+                .and_then(parse_embedding))
+        })
+    }
+}
+
+/// clip/main.py 提供的 /embed（图片）与 /embed_text（文本）
+pub struct HttpVisualEmbedder {
+    client: reqwest::Client,
+    clip_api_url: String,
+    max_retries: u32,
+}
+
+impl VisualEmbedder for HttpVisualEmbedder {
+    fn embed_image(&self, image_bytes: Vec<u8>) -> EmbedFuture<'_> {
+        Box::pin(async move {
+            // 按文件头确定真实格式，不是图片的内容不送给 CLIP
+            let Some((mime, ext)) = media::sniff_image(&image_bytes) else {
+                tracing::warn!("Skipping CLIP embedding: content is not a recognizable image");
+                return Ok(None);
+            };
+            let clip_url = format!("{}/embed", self.clip_api_url);
+            let res = http::send_with_retry(self.max_retries, || {
+                // mime 来自 sniff_image 的固定列表，mime_str 不会失败
+                let part = reqwest::multipart::Part::bytes(image_bytes.clone())
+                    .file_name(format!("image.{}", ext))
+                    .mime_str(mime)
+                    .expect("valid mime");
+                let form = reqwest::multipart::Form::new().part("file", part);
+                self.client.post(&clip_url).multipart(form)
+            })
+            .await?;
+            if !res.status().is_success() {
+                tracing::warn!("CLIP embedding error: {}", res.status());
+                return Ok(None);
+            }
+
+            let json: serde_json::Value = res.json().await?;
+            Ok(json.get("embedding").and_then(parse_embedding))
+        })
+    }
+
+    fn embed_text<'a>(&'a self, text: &'a str) -> EmbedFuture<'a> {
+        Box::pin(async move {
+            let clip_url = format!("{}/embed_text", self.clip_api_url);
+            let res = http::send_with_retry(self.max_retries, || {
+                self.client.post(&clip_url).query(&[("text", text)])
+            })
+            .await?;
+            if !res.status().is_success() {
+                tracing::warn!("CLIP text embedding failed: {}", res.status());
+                return Ok(None);
+            }
+
+            let json: serde_json::Value = res.json().await?;
+            Ok(json.get("embedding").and_then(parse_embedding))
+        })
+    }
+}
+
+/// 多个向量的均值并重新归一化（余弦检索下代表这些向量的共同特征，如多张参考图、长文本的各个分块）；
+/// 维度不一致的向量忽略
+pub fn normalized_centroid(vectors: &[Vec<f32>]) -> Option<Vec<f32>> {
+    let dim = vectors.first()?.len();
+    let mut sum = vec![0.0_f32; dim];
+    for vec in vectors.iter().filter(|v| v.len() == dim) {
+        sum.iter_mut().zip(vec).for_each(|(s, x)| *s += x);
+    }
+    let norm = sum.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        sum.iter_mut().for_each(|x| *x /= norm);
+    }
+    Some(sum)
+}
+
+fn parse_embedding(value: &serde_json::Value) -> Option<Vec<f32>> {
+    value
+        .as_array()
+        .map(|arr| arr.iter().map(|v| v.as_f64().unwrap_or(0.0) as f32).collect())
+}
+
+/// OpenAI 兼容的 /chat/completions 多模态接口（VLM）做 OCR
+pub struct HttpVlmOcr {
+    client: reqwest::Client,
+    api_base: String,
+    api_key: String,
+    model: String,
+    max_retries: u32,
+}
+
+impl Ocr for HttpVlmOcr {
+    fn recognize<'a>(&'a self, image_bytes: &'a [u8]) -> BoxFuture<'a, anyhow::Result<Option<String>>> {
+        Box::pin(async move {
+            let mime = media::sniff_image(image_bytes).map(|(mime, _)| mime).unwrap_or("image/jpeg");
+            let base64_image = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, image_bytes);
+            let vlm_url = format!("{}/chat/completions", self.api_base);
+            let body = serde_json::json!({
+                "model": self.model,
+                "messages": [{
+                    "role": "user",
+                    "content": [
+                        {"type": "text", "text": "请识别这张图片中的所有文字内容，只输出识别到的文字，不要任何解释。如果没有文字就输出空。"},
+                        {"type": "image_url", "image_url": {"url": format!("data:{};base64,{}", mime, base64_image)}}
+                    ]
+                }],
+                "max_tokens": 2048
+            });
+
+            let res = http::send_with_retry(self.max_retries, || {
+                self.client
+                    .post(&vlm_url)
+                    .header("Authorization", format!("Bearer {}", self.api_key))
+                    .header("Content-Type", "application/json")
+                    .json(&body)
+            })
+            .await?;
+
+            if !res.status().is_success() {
+                let status = res.status();
+                let text = res.text().await.unwrap_or_default();
+                tracing::warn!("VLM OCR error: {} - {}", status, text);
+                return Ok(None);
+            }
+
+            let json: serde_json::Value = res.json().await?;
+            Ok(json.get("choices")
+                .and_then(|c| c.get(0))
+                .and_then(|c| c.get("message"))
+                .and_then(|m| m.get("content"))
+                .and_then(|c| c.as_str())
+                .map(|s| s.to_string()))
+        })
+    }
+}
+
+/// OpenAI 兼容的 /chat/completions 接口做文本摘要
+pub struct HttpChatSummarizer {
+    client: reqwest::Client,
+    api_base: String,
+    api_key: String,
+    model: String,
+    max_retries: u32,
+}
+
+impl Summarizer for HttpChatSummarizer {
+    fn summarize<'a>(&'a self, text: &'a str) -> BoxFuture<'a, anyhow::Result<Option<String>>> {
+        Box::pin(async move {
+            let chat_url = format!("{}/chat/completions", self.api_base);
+            let body = serde_json::json!({
+                "model": self.model,
+                "messages": [
+                    {"role": "system", "content": "用一两句话概括用户给出的文本，使用原文的语言，只输出摘要本身，不要任何解释。"},
+                    {"role": "user", "content": text}
+                ],
+                "max_tokens": 256
+            });
+
+            let res = http::send_with_retry(self.max_retries, || {
+                self.client
+                    .post(&chat_url)
+                    .header("Authorization", format!("Bearer {}", self.api_key))
+                    .header("Content-Type", "application/json")
+                    .json(&body)
+            })
+            .await?;
+
+            if !res.status().is_success() {
+                let status = res.status();
+                let text = res.text().await.unwrap_or_default();
+                tracing::warn!("Summary API error: {} - {}", status, text);
+                return Ok(None);
+            }
+
+            let json: serde_json::Value = res.json().await?;
+            Ok(json.get("choices")
+                .and_then(|c| c.get(0))
+                .and_then(|c| c.get("message"))
+                .and_then(|m| m.get("content"))
+                .and_then(|c| c.as_str())
+                .map(|s| s.to_string()))
+        })
+    }
+}
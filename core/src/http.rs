@@ -0,0 +1,53 @@
+use crate::config::Config;
+use std::time::Duration;
+
+/// 退避基数：第 n 次重试等待 base * 2^n，再加一点随机抖动
+const RETRY_BASE_DELAY_MS: u64 = 500;
+const RETRY_MAX_JITTER_MS: u64 = 250;
+
+/// 带超时的外部 HTTP 客户端，避免上游挂起时 worker 被无限期卡住
+pub fn build_client(config: &Config) -> reqwest::Result<reqwest::Client> {
+    client_builder(config).build()
+}
+
+/// 已设置好超时的 builder，供需要额外定制（跳转策略、固定解析地址）的调用方使用
+pub fn client_builder(config: &Config) -> reqwest::ClientBuilder {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(config.http_timeout_secs))
+        .connect_timeout(Duration::from_secs(config.http_connect_timeout_secs))
+}
+
+/// 发送幂等请求，遇到超时/连接失败/5xx 时按指数退避重试。
+/// 传入闭包而不是 RequestBuilder，因为 multipart 请求体无法 clone，每次重试都需要重新构造。
+/// 重试耗尽后返回最后一次的结果（可能是 5xx 响应），由调用方按原逻辑处理
+pub async fn send_with_retry<F>(max_retries: u32, build: F) -> reqwest::Result<reqwest::Response>
+where
+    F: Fn() -> reqwest::RequestBuilder,
+{
+    let mut attempt = 0;
+    loop {
+        let result = build().send().await;
+        let retryable = match &result {
+            Ok(res) => res.status().is_server_error(),
+            Err(e) => e.is_timeout() || e.is_connect(),
+        };
+        if !retryable || attempt >= max_retries {
+            return result;
+        }
+
+        let delay = retry_delay(attempt);
+        match &result {
+            Ok(res) => tracing::warn!("Upstream returned {}, retrying in {:?}", res.status(), delay),
+            Err(e) => tracing::warn!("Upstream request failed ({}), retrying in {:?}", e, delay),
+        }
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+fn retry_delay(attempt: u32) -> Duration {
+    let backoff = RETRY_BASE_DELAY_MS.saturating_mul(1 << attempt.min(6));
+    // 抖动只需要分散并发重试，不需要密码学随机
+    let jitter = (uuid::Uuid::new_v4().as_u128() % (RETRY_MAX_JITTER_MS as u128 + 1)) as u64;
+    Duration::from_millis(backoff + jitter)
+}
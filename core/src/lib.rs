@@ -4,3 +4,14 @@ pub mod state;
 pub mod bot;
 pub mod worker;
 pub mod api;
+pub mod reactions;
+pub mod task_error;
+pub mod media;
+pub mod http;
+pub mod storage;
+pub mod purge;
+pub mod search_cache;
+pub mod unfurl;
+pub mod embedder;
+#[cfg(feature = "onnx")]
+pub mod onnx;
@@ -1,9 +1,8 @@
-use brainpile_core::{config, db, api, bot, worker, state};
+use brainpile_core::{config, db, api, bot, worker, state, http, embedder, storage, purge, search_cache};
 
 use dotenvy::dotenv;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use std::sync::Arc;
-use s3::bucket_ops::BucketConfiguration;
 
 #[tokio::main]
 async fn main() {
@@ -18,65 +17,62 @@ async fn main() {
     tracing::info!("Brainpile Core v0.1.0 starting...");
 
     // Load Config
-    let config = Arc::new(config::Config::from_env());
+    let config = config::Config::from_env();
+    config.validate().expect("Invalid configuration");
+    let config = Arc::new(config);
     
     // Connect DB
-    let db = db::init_pool(&config.database_url).await.expect("Failed to connect to DB");
+    let db = db::init_pool(&config).await.expect("Failed to connect to DB");
     
     // Run Migrations
+    db::check_pgvector_available(&db).await.expect("pgvector is required");
     tracing::info!("Running database migrations...");
     sqlx::migrate!("./migrations")
         .run(&db)
         .await
         .expect("Failed to migrate database");
+    db::verify_vector_columns(&db).await.expect("Unexpected vector schema");
 
     // Init S3 & Ensure Bucket Exists
-    let internal_region = s3::region::Region::Custom {
-        region: "us-east-1".to_owned(),
-        endpoint: config.s3_endpoint.clone(),
-    };
-    let credentials = s3::creds::Credentials::new(
-        Some(&config.s3_access_key),
-        Some(&config.s3_secret_key),
-        None, None, None
-    ).expect("Failed to create S3 credentials");
-    
-    let internal_bucket = s3::bucket::Bucket::new(
-        &config.s3_bucket,
-        internal_region,
-        credentials.clone()
-    ).expect("Failed to create bucket struct").with_path_style();
+    let internal_bucket = storage::internal_bucket(&config).expect("Failed to create bucket struct");
 
     if !internal_bucket.exists().await.unwrap_or(false) {
         tracing::info!("Bucket {} missing, creating...", config.s3_bucket);
-        // Try creating with path style
-        let _ = s3::bucket::Bucket::create_with_path_style(
-            &config.s3_bucket,
-            s3::region::Region::Custom {
-                region: "us-east-1".to_owned(),
-                endpoint: config.s3_endpoint.clone(),
-            },
-            credentials.clone(),
-            BucketConfiguration::default()
-        ).await.map_err(|e| tracing::warn!("Failed to create bucket: {}", e));
+        let _ = storage::create_bucket(&config)
+            .await
+            .map_err(|e| tracing::warn!("Failed to create bucket: {}", e));
     }
 
     // Init S3 Signing Client (Public)
-    let region = s3::region::Region::Custom {
-        region: "us-east-1".to_owned(),
-        endpoint: config.s3_public_endpoint.clone(),
-    };
-    let s3_signing_client = s3::bucket::Bucket::new(
-        &config.s3_bucket,
-        region,
-        credentials
-    ).expect("Failed to create S3 bucket").with_path_style();
+    // Presigned URLs embed the host in the signature, so they must be signed against
+    // the browser-reachable endpoint rather than the internal one used for writes.
+    let s3_signing_client = storage::signing_bucket(&config).expect("Failed to create S3 bucket");
+
+    let http_client = http::build_client(&config).expect("Failed to build HTTP client");
+    let text_embedder = embedder::build_text_embedder(&config, &http_client).expect("Failed to init text embedder");
+    let visual_embedder = embedder::build_visual_embedder(&config, &http_client).expect("Failed to init CLIP embedder");
+    let ocr = embedder::build_ocr(&config, &http_client);
+    let summarizer = embedder::build_summarizer(&config, &http_client);
+    let clip_permits = Arc::new(tokio::sync::Semaphore::new(config.clip_concurrency));
+    let vlm_permits = Arc::new(tokio::sync::Semaphore::new(config.vlm_concurrency));
+    let embedding_permits = Arc::new(tokio::sync::Semaphore::new(config.embedding_concurrency));
+    let search_cache = Arc::new(search_cache::SearchCache::new(config.search_cache_ttl_secs));
 
     let state = state::AppState {
         db,
         config,
-        http_client: reqwest::Client::new(),
-        s3_signing_client: *s3_signing_client,
+        http_client,
+        text_embedder,
+        visual_embedder,
+        ocr,
+        summarizer,
+        s3_signing_client,
+        avatar_inflight: Default::default(),
+        queue_full_albums: Default::default(),
+        clip_permits,
+        vlm_permits,
+        embedding_permits,
+        search_cache,
     };
 
     // Spawn TG Bot
@@ -91,6 +87,12 @@ async fn main() {
         worker::run_worker(worker_state).await;
     });
 
+    // Spawn Retention Purge（未设置 RETENTION_DAYS 时直接返回）
+    let retention_state = state.clone();
+    tokio::spawn(async move {
+        purge::run_retention(retention_state).await;
+    });
+
     // Start API Server
     api::run_server(state).await;
 }
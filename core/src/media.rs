@@ -0,0 +1,97 @@
+/// 按文件头识别图片格式，返回 (mime, 扩展名)；不是可识别的图片时返回 None
+pub fn sniff_image(bytes: &[u8]) -> Option<(&'static str, &'static str)> {
+    let format = image::guess_format(bytes).ok()?;
+    let ext = format.extensions_str().first().copied().unwrap_or("bin");
+    Some((format.to_mime_type(), ext))
+}
+
+/// 下载响应声明的 Content-Type 是否可能是图片（缺省或 octet-stream 时交给文件头判断）
+pub fn content_type_may_be_image(content_type: Option<&str>) -> bool {
+    match content_type {
+        None => true,
+        Some(ct) => {
+            let ct = ct.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+            ct.is_empty() || ct.starts_with("image/") || ct == "application/octet-stream"
+        }
+    }
+}
+
+/// 64 位差值哈希（dHash）：缩成 9x8 灰度图，逐行比较相邻像素的明暗。
+/// 重新压缩、缩放后的同一张图哈希几乎不变，用汉明距离判断近似重复
+pub fn dhash(img: &image::DynamicImage) -> u64 {
+    let small = img
+        .resize_exact(9, 8, image::imageops::FilterType::Triangle)
+        .to_luma8();
+    let mut hash = 0_u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if small.get_pixel(x, y)[0] < small.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+    use std::io::Cursor;
+
+    /// 默认 PHASH_MAX_DISTANCE
+    const MAX_DISTANCE: u32 = 6;
+
+    fn distance(a: &DynamicImage, b: &DynamicImage) -> u32 {
+        (dhash(a) ^ dhash(b)).count_ones()
+    }
+
+    /// 带几块明暗区域的合成图，结构足够丰富，哈希各位不会全相同
+    fn scene(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            let (fx, fy) = (x as f32 / width as f32, y as f32 / height as f32);
+            let v = if (fx - 0.3).powi(2) + (fy - 0.4).powi(2) < 0.05 {
+                230.0
+            } else if fx > 0.6 && fy > 0.5 {
+                40.0
+            } else {
+                60.0 + 120.0 * fx * (1.0 - fy)
+            };
+            Rgb([v as u8, (v * 0.8) as u8, (255.0 - v) as u8])
+        }))
+    }
+
+    fn recompress_jpeg(img: &DynamicImage) -> DynamicImage {
+        let mut buf = Cursor::new(Vec::new());
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buf, 40)
+            .encode_image(&img.to_rgb8())
+            .unwrap();
+        image::load_from_memory_with_format(buf.get_ref(), ImageFormat::Jpeg).unwrap()
+    }
+
+    #[test]
+    fn identical_images_hash_equal() {
+        let img = scene(320, 240);
+        assert_eq!(dhash(&img), dhash(&img.clone()));
+    }
+
+    #[test]
+    fn resized_and_recompressed_copy_is_near_duplicate() {
+        let original = scene(640, 480);
+        let copy = recompress_jpeg(&original.resize_exact(300, 225, image::imageops::FilterType::Lanczos3));
+        assert!(distance(&original, &copy) <= MAX_DISTANCE, "distance {}", distance(&original, &copy));
+
+        let brighter = original.brighten(15);
+        assert!(distance(&original, &brighter) <= MAX_DISTANCE, "distance {}", distance(&original, &brighter));
+    }
+
+    #[test]
+    fn different_images_are_far_apart() {
+        let original = scene(640, 480);
+        let rotated = original.rotate90().resize_exact(640, 480, image::imageops::FilterType::Triangle);
+        for other in [original.fliph(), original.rotate180(), rotated] {
+            assert!(distance(&original, &other) > MAX_DISTANCE * 2, "distance {}", distance(&original, &other));
+        }
+    }
+}
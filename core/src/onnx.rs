@@ -0,0 +1,183 @@
+//! 本地 ONNX 推理（`onnx` feature）：离线部署时替代 embedding / CLIP HTTP 服务。
+//! ONNX Runtime 以动态库方式加载，通过 ORT_DYLIB_PATH 指定 libonnxruntime 路径。
+use crate::embedder::{EmbedFuture, TextEmbedder, VisualEmbedder};
+use anyhow::Context;
+use ort::session::Session;
+use ort::value::Tensor;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokenizers::Tokenizer;
+
+/// BGE-M3 的最大输入长度（token）
+const TEXT_MAX_TOKENS: usize = 8192;
+/// Chinese-CLIP 文本编码器的固定上下文长度
+const CLIP_CONTEXT_LENGTH: usize = 52;
+const CLIP_IMAGE_SIZE: u32 = 224;
+const CLIP_MEAN: [f32; 3] = [0.481_454_7, 0.457_827_5, 0.408_210_7];
+const CLIP_STD: [f32; 3] = [0.268_629_5, 0.261_302_6, 0.275_777_1];
+
+/// 单个 ONNX 模型；Session::run 需要可变借用，推理串行执行
+struct Model {
+    session: Mutex<Session>,
+}
+
+impl Model {
+    fn load(path: &Path) -> anyhow::Result<Self> {
+        let session = Session::builder()?
+            .commit_from_file(path)
+            .with_context(|| format!("failed to load ONNX model {}", path.display()))?;
+        Ok(Self { session: Mutex::new(session) })
+    }
+
+    /// 按模型声明的输入名填充 token 输入（input_ids/text、attention_mask、token_type_ids），
+    /// 取第一个输出；形状为 [1, seq, dim] 时取首个 token（CLS），[1, dim] 时直接使用
+    fn run_tokens(&self, ids: &[i64], mask: &[i64]) -> anyhow::Result<Vec<f32>> {
+        let mut session = self.session.lock().unwrap_or_else(|e| e.into_inner());
+        let shape = [1_usize, ids.len()];
+        let mut inputs = Vec::new();
+        for input in &session.inputs {
+            let data = match input.name.as_str() {
+                "attention_mask" => mask.to_vec(),
+                "token_type_ids" => vec![0; ids.len()],
+                _ => ids.to_vec(),
+            };
+            inputs.push((input.name.clone(), Tensor::from_array((shape, data))?.upcast()));
+        }
+        let outputs = session.run(inputs)?;
+        let (_, output) = outputs.iter().next().context("ONNX model produced no output")?;
+        let (out_shape, data) = output.try_extract_tensor::<f32>()?;
+        let dim = *out_shape.last().context("ONNX output has no dimensions")? as usize;
+        Ok(l2_normalize(data[..dim].to_vec()))
+    }
+
+    fn run_pixels(&self, pixels: Vec<f32>) -> anyhow::Result<Vec<f32>> {
+        let mut session = self.session.lock().unwrap_or_else(|e| e.into_inner());
+        let input_name = session.inputs.first().context("ONNX model has no inputs")?.name.clone();
+        let size = CLIP_IMAGE_SIZE as usize;
+        let tensor = Tensor::from_array(([1_usize, 3, size, size], pixels))?;
+        let outputs = session.run(vec![(input_name, tensor.upcast())])?;
+        let (_, output) = outputs.iter().next().context("ONNX model produced no output")?;
+        let (_, data) = output.try_extract_tensor::<f32>()?;
+        Ok(l2_normalize(data.to_vec()))
+    }
+}
+
+fn l2_normalize(mut v: Vec<f32>) -> Vec<f32> {
+    let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        v.iter_mut().for_each(|x| *x /= norm);
+    }
+    v
+}
+
+fn load_tokenizer(path: &Path) -> anyhow::Result<Tokenizer> {
+    Tokenizer::from_file(path).map_err(|e| anyhow::anyhow!("failed to load tokenizer {}: {}", path.display(), e))
+}
+
+fn encode(tokenizer: &Tokenizer, text: &str, max_len: usize) -> anyhow::Result<Vec<i64>> {
+    let encoding = tokenizer
+        .encode(text, true)
+        .map_err(|e| anyhow::anyhow!("tokenize failed: {}", e))?;
+    let mut ids: Vec<i64> = encoding.get_ids().iter().map(|&id| id as i64).collect();
+    ids.truncate(max_len);
+    Ok(ids)
+}
+
+/// BGE-M3 dense 向量。目录下需要 model.onnx 与 tokenizer.json
+pub struct OnnxTextEmbedder {
+    inner: Arc<(Model, Tokenizer)>,
+}
+
+impl OnnxTextEmbedder {
+    pub fn load(dir: &str) -> anyhow::Result<Self> {
+        let dir = Path::new(dir);
+        let model = Model::load(&dir.join("model.onnx"))?;
+        let tokenizer = load_tokenizer(&dir.join("tokenizer.json"))?;
+        tracing::info!("Loaded local ONNX text embedder from {}", dir.display());
+        Ok(Self { inner: Arc::new((model, tokenizer)) })
+    }
+}
+
+impl TextEmbedder for OnnxTextEmbedder {
+    fn embed<'a>(&'a self, text: &'a str) -> EmbedFuture<'a> {
+        let inner = self.inner.clone();
+        let text = text.to_string();
+        Box::pin(async move {
+            // 推理是 CPU 密集的同步调用，放到阻塞线程池避免卡住 tokio worker
+            tokio::task::spawn_blocking(move || {
+                let (model, tokenizer) = &*inner;
+                let ids = encode(tokenizer, &text, TEXT_MAX_TOKENS)?;
+                let mask = vec![1; ids.len()];
+                model.run_tokens(&ids, &mask).map(Some)
+            })
+            .await?
+        })
+    }
+}
+
+/// Chinese-CLIP（与 clip/main.py 相同的 ViT-L-14）。目录下需要 image.onnx、text.onnx 与 tokenizer.json
+pub struct OnnxClipEmbedder {
+    inner: Arc<(Model, Model, Tokenizer)>,
+}
+
+impl OnnxClipEmbedder {
+    pub fn load(dir: &str) -> anyhow::Result<Self> {
+        let dir = Path::new(dir);
+        let image_model = Model::load(&dir.join("image.onnx"))?;
+        let text_model = Model::load(&dir.join("text.onnx"))?;
+        let tokenizer = load_tokenizer(&dir.join("tokenizer.json"))?;
+        tracing::info!("Loaded local ONNX CLIP embedder from {}", dir.display());
+        Ok(Self { inner: Arc::new((image_model, text_model, tokenizer)) })
+    }
+}
+
+/// 与 cn_clip 的 preprocess 一致：bicubic 缩放到 224x224，按 CLIP 均值/方差归一化，CHW 排布
+fn clip_pixels(image_bytes: &[u8]) -> anyhow::Result<Vec<f32>> {
+    let img = image::load_from_memory(image_bytes)?
+        .resize_exact(CLIP_IMAGE_SIZE, CLIP_IMAGE_SIZE, image::imageops::FilterType::CatmullRom)
+        .to_rgb8();
+    let plane = (CLIP_IMAGE_SIZE * CLIP_IMAGE_SIZE) as usize;
+    let mut pixels = vec![0.0_f32; plane * 3];
+    for (i, p) in img.pixels().enumerate() {
+        for c in 0..3 {
+            pixels[c * plane + i] = (p[c] as f32 / 255.0 - CLIP_MEAN[c]) / CLIP_STD[c];
+        }
+    }
+    Ok(pixels)
+}
+
+impl VisualEmbedder for OnnxClipEmbedder {
+    fn embed_image(&self, image_bytes: Vec<u8>) -> EmbedFuture<'_> {
+        let inner = self.inner.clone();
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || {
+                let pixels = match clip_pixels(&image_bytes) {
+                    Ok(pixels) => pixels,
+                    Err(e) => {
+                        tracing::warn!("Skipping CLIP embedding: failed to decode image: {}", e);
+                        return Ok(None);
+                    }
+                };
+                inner.0.run_pixels(pixels).map(Some)
+            })
+            .await?
+        })
+    }
+
+    fn embed_text<'a>(&'a self, text: &'a str) -> EmbedFuture<'a> {
+        let inner = self.inner.clone();
+        let text = text.to_string();
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || {
+                let (_, text_model, tokenizer) = &*inner;
+                // cn_clip 的文本编码器以 0 作为 padding，输入固定为 52 个 token
+                let mut ids = encode(tokenizer, &text, CLIP_CONTEXT_LENGTH)?;
+                let mut mask = vec![1; ids.len()];
+                ids.resize(CLIP_CONTEXT_LENGTH, 0);
+                mask.resize(CLIP_CONTEXT_LENGTH, 0);
+                text_model.run_tokens(&ids, &mask).map(Some)
+            })
+            .await?
+        })
+    }
+}
@@ -0,0 +1,248 @@
+//! item 的彻底删除（数据库记录、关联任务、无剩余 item 且没有用户设置的实体、S3 对象），
+//! 供 DELETE /api/v1/items/:id 与 RETENTION_DAYS 保留期清理共用。
+use crate::reactions;
+use crate::state::AppState;
+use crate::storage;
+use sqlx::Row;
+
+/// 带用户设置的实体（静音、默认标签、手动上传的头像）即使没有剩余 item 也保留，
+/// 否则下次转发时会重新建出一个空白实体
+const ENTITY_HAS_SETTINGS: &str = r#"
+    e.muted
+    OR EXISTS (SELECT 1 FROM entity_default_tags d WHERE d.entity_id = e.id)
+    OR e.avatar_url LIKE 'PROXY:avatars/' || e.id || '-%'
+"#;
+
+/// delete_item 将要删除的内容（dry run 预览用）
+pub struct DeletePlan {
+    pub item_type: String,
+    /// 将被删除的 S3 对象：原文件、缩略图、预览拼图（缩略图复用原图时只算一次）
+    pub s3_keys: Vec<String>,
+    /// 该 item 是最后一条关联 item、将被一并删除的实体（带用户设置的实体除外）
+    pub entities_removed: Vec<i64>,
+    /// 随 item 删除的任务数
+    pub tasks_removed: i64,
+}
+
+/// 只读地计算删除单个 item 会移除哪些内容，不做任何修改；item 不存在时返回 Ok(None)
+pub async fn plan_delete_item(state: &AppState, id: i64) -> anyhow::Result<Option<DeletePlan>> {
+    let row = sqlx::query("SELECT item_type, s3_key, thumbnail_key, storyboard_key, tg_chat_id, tg_user_id FROM items WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&state.db)
+        .await?;
+
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let s3_key: Option<String> = row.try_get("s3_key").unwrap_or(None);
+    let thumbnail_key: Option<String> = row.try_get("thumbnail_key").unwrap_or(None);
+    let storyboard_key: Option<String> = row.try_get("storyboard_key").unwrap_or(None);
+    let tg_chat_id: Option<i64> = row.try_get("tg_chat_id").unwrap_or(None);
+    let tg_user_id: Option<i64> = row.try_get("tg_user_id").unwrap_or(None);
+
+    let mut s3_keys: Vec<String> = Vec::new();
+    for key in [s3_key, thumbnail_key, storyboard_key].into_iter().flatten() {
+        if !s3_keys.contains(&key) {
+            s3_keys.push(key);
+        }
+    }
+
+    // 与 delete_item 相同的判定：除该 item 外再没有关联 item、也没有用户设置的实体会被删除
+    let mut entities_removed = Vec::new();
+    for eid in [tg_chat_id, tg_user_id].into_iter().flatten() {
+        let removed: bool = sqlx::query_scalar(&format!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM entities e
+                WHERE e.id = $1
+                  AND NOT EXISTS (SELECT 1 FROM items WHERE (tg_chat_id = $1 OR tg_user_id = $1) AND id <> $2)
+                  AND NOT ({})
+            )
+            "#,
+            ENTITY_HAS_SETTINGS
+        ))
+        .bind(eid)
+        .bind(id)
+        .fetch_one(&state.db)
+        .await?;
+        if removed && !entities_removed.contains(&eid) {
+            entities_removed.push(eid);
+        }
+    }
+
+    let tasks_removed: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM tasks WHERE item_id = $1")
+        .bind(id)
+        .fetch_one(&state.db)
+        .await?;
+
+    Ok(Some(DeletePlan {
+        item_type: row.get("item_type"),
+        s3_keys,
+        entities_removed,
+        tasks_removed,
+    }))
+}
+
+/// 删除单个 item；item 不存在时返回 Ok(false)
+pub async fn delete_item(state: &AppState, id: i64) -> anyhow::Result<bool> {
+    // 1. Fetch info for S3 cleanup and Entity cleanup
+    let row = sqlx::query("SELECT s3_key, thumbnail_key, storyboard_key, tg_chat_id, tg_user_id FROM items WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&state.db)
+        .await?;
+
+    let Some(row) = row else {
+        return Ok(false);
+    };
+    let s3_key: Option<String> = row.try_get("s3_key").unwrap_or(None);
+    let thumbnail_key: Option<String> = row.try_get("thumbnail_key").unwrap_or(None);
+    let storyboard_key: Option<String> = row.try_get("storyboard_key").unwrap_or(None);
+    let tg_chat_id: Option<i64> = row.try_get("tg_chat_id").unwrap_or(None);
+    let tg_user_id: Option<i64> = row.try_get("tg_user_id").unwrap_or(None);
+
+    // 组图成员的任务随 item 一起删除，之后需要按剩余成员重新计算组图 reaction
+    let album: Option<(i64, String)> = sqlx::query_as(
+        r#"
+        SELECT bot_chat_id, payload->>'tg_group_id'
+        FROM tasks
+        WHERE item_id = $1 AND bot_chat_id <> 0 AND payload->>'tg_group_id' IS NOT NULL
+        LIMIT 1
+        "#,
+    )
+    .bind(id)
+    .fetch_optional(&state.db)
+    .await?;
+
+    // 2. Database Transaction
+    let mut tx = state.db.begin().await?;
+
+    // Delete tasks first (to satisfy FK)
+    sqlx::query("DELETE FROM tasks WHERE item_id = $1")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    // Delete item
+    let result = sqlx::query("DELETE FROM items WHERE id = $1")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    // 2.5 Entity Cleanup: If this was the last item for these entities, delete them
+    let mut entities_to_check = Vec::new();
+    if let Some(cid) = tg_chat_id { entities_to_check.push(cid); }
+    if let Some(uid) = tg_user_id { entities_to_check.push(uid); }
+
+    for eid in entities_to_check {
+        // Check if any other items remain for this entity
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM items WHERE tg_chat_id = $1 OR tg_user_id = $1")
+            .bind(eid)
+            .fetch_one(&mut *tx)
+            .await?;
+
+        if count == 0 {
+            let deleted = sqlx::query(&format!("DELETE FROM entities e WHERE e.id = $1 AND NOT ({})", ENTITY_HAS_SETTINGS))
+                .bind(eid)
+                .execute(&mut *tx)
+                .await?;
+            if deleted.rows_affected() > 0 {
+                tracing::info!("Entity {} has no more items. Deleted entity.", eid);
+            }
+        }
+    }
+
+    tx.commit().await?;
+
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    if let Some((bot_chat_id, group_id)) = album {
+        let bot = crate::bot::new_bot(&state.config);
+        if let Err(e) = reactions::update_album_reaction(state, &bot, bot_chat_id, &group_id).await {
+            tracing::warn!("Failed to update album reaction for group {}: {}", group_id, e);
+        }
+    }
+
+    // 3. S3 Cleanup（数据库已提交，对象删除失败只记录日志，残留对象由 /admin/gc 回收）
+    let bucket = match storage::internal_bucket(&state.config) {
+        Ok(bucket) => bucket,
+        Err(e) => {
+            tracing::warn!("Failed to create S3 bucket for cleanup of item {}: {}", id, e);
+            return Ok(true);
+        }
+    };
+    if let Some(key) = s3_key.as_ref() {
+        let _ = bucket.delete_object(key).await
+            .map_err(|e| tracing::warn!("Failed to delete S3 object {}: {}", key, e));
+    }
+    // 小图的缩略图可能就是原图本身，不重复删除
+    if let Some(key) = thumbnail_key.filter(|k| Some(k) != s3_key.as_ref()) {
+        let _ = bucket.delete_object(&key).await
+            .map_err(|e| tracing::warn!("Failed to delete S3 thumbnail {}: {}", key, e));
+    }
+    if let Some(key) = storyboard_key {
+        let _ = bucket.delete_object(&key).await
+            .map_err(|e| tracing::warn!("Failed to delete S3 storyboard {}: {}", key, e));
+    }
+
+    Ok(true)
+}
+
+const RETENTION_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
+/// 每批删除的 item 数；逐条删除，每条一个短事务，避免长时间持锁
+const RETENTION_BATCH_SIZE: i64 = 100;
+
+/// 保留期清理：定期删除早于 RETENTION_DAYS 的 item（收藏的 item 不受影响）
+pub async fn run_retention(state: AppState) {
+    let Some(days) = state.config.retention_days else {
+        return;
+    };
+    tracing::info!("Retention enabled: purging items older than {} days", days);
+
+    loop {
+        match purge_expired(&state, days).await {
+            Ok(0) => {}
+            Ok(purged) => tracing::info!("Retention purged {} items older than {} days", purged, days),
+            Err(e) => tracing::error!("Retention purge failed: {:?}", e),
+        }
+        tokio::time::sleep(RETENTION_CHECK_INTERVAL).await;
+    }
+}
+
+async fn purge_expired(state: &AppState, days: i64) -> anyhow::Result<u64> {
+    let mut purged = 0_u64;
+    let mut after_id = 0_i64;
+    loop {
+        let ids: Vec<i64> = sqlx::query_scalar(
+            r#"
+            SELECT id FROM items
+            WHERE created_at < NOW() - make_interval(days => $1::int)
+              AND NOT is_pinned
+              AND id > $2
+            ORDER BY id
+            LIMIT $3
+            "#,
+        )
+        .bind(days)
+        .bind(after_id)
+        .bind(RETENTION_BATCH_SIZE)
+        .fetch_all(&state.db)
+        .await?;
+
+        let Some(&last_id) = ids.last() else {
+            break;
+        };
+        after_id = last_id;
+
+        for id in ids {
+            match delete_item(state, id).await {
+                Ok(true) => purged += 1,
+                Ok(false) => {}
+                // 单条失败不影响其余 item，下一轮会再次尝试
+                Err(e) => tracing::warn!("Retention failed to delete item {}: {}", id, e),
+            }
+        }
+    }
+    Ok(purged)
+}
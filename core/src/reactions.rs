@@ -0,0 +1,97 @@
+//! Bot 状态反应（处理中 / 完成 / 失败）的统一出口。
+//! 默认值用 Unicode 转义书写，避免源码编码问题导致 emoji 乱码。
+use crate::state::AppState;
+use sqlx::Row;
+use teloxide::prelude::*;
+use teloxide::types::{ChatId, MessageId, ReactionType};
+
+/// 👀
+pub const DEFAULT_PROCESSING: &str = "\u{1F440}";
+/// ❤️
+pub const DEFAULT_DONE: &str = "\u{2764}\u{FE0F}";
+/// 👎
+pub const DEFAULT_FAILED: &str = "\u{1F44E}";
+
+fn is_emoji_modifier(c: char) -> bool {
+    matches!(c, '\u{FE0E}' | '\u{FE0F}' | '\u{20E3}' | '\u{1F3FB}'..='\u{1F3FF}')
+}
+
+/// 粗略判断字符串是否为单个 emoji：
+/// ZWJ 连接的组合 emoji 视为一个，每段只允许一个基础字符（可带变体选择符 / 肤色修饰）
+pub fn is_single_emoji(s: &str) -> bool {
+    if s.is_empty() || s.chars().any(|c| c.is_ascii() || c.is_whitespace()) {
+        return false;
+    }
+    s.split('\u{200D}')
+        .all(|seg| seg.chars().filter(|c| !is_emoji_modifier(*c)).count() == 1)
+}
+
+/// 给消息设置单个 emoji reaction（覆盖原有 reaction）
+pub async fn set_reaction(
+    bot: &Bot,
+    chat_id: ChatId,
+    message_id: MessageId,
+    emoji: &str,
+) -> Result<(), teloxide::RequestError> {
+    let reaction = ReactionType::Emoji { emoji: emoji.to_string() };
+    bot.set_message_reaction(chat_id, message_id)
+        .reaction(vec![reaction])
+        .send()
+        .await?;
+    Ok(())
+}
+
+/// 按组图所有成员任务的状态更新首条消息（bot_message_id 最小）的 reaction；
+/// worker 完成任务与删除组图成员后都会调用
+pub async fn update_album_reaction(
+    state: &AppState,
+    bot: &Bot,
+    bot_chat_id: i64,
+    group_id: &str,
+) -> anyhow::Result<()> {
+    let row = sqlx::query(
+        r#"
+        SELECT
+            MIN(bot_message_id) AS leader_message_id,
+            COUNT(*)::bigint AS cnt,
+            BOOL_OR(status = 'failed') AS any_failed,
+            BOOL_AND(status = 'completed') AS all_completed
+        FROM tasks
+        WHERE bot_chat_id = $1
+          AND payload->>'tg_group_id' = $2
+        "#,
+    )
+    .bind(bot_chat_id)
+    .bind(group_id)
+    .fetch_one(&state.db)
+    .await?;
+
+    let leader_message_id: Option<i64> = row.try_get("leader_message_id").ok();
+    let cnt: i64 = row.try_get::<i64, _>("cnt").unwrap_or(0);
+    let any_failed: bool = row.try_get::<Option<bool>, _>("any_failed").ok().flatten().unwrap_or(false);
+    let all_completed: bool = row.try_get::<Option<bool>, _>("all_completed").ok().flatten().unwrap_or(false);
+
+    let Some(leader_message_id) = leader_message_id else { return Ok(()); };
+    if cnt <= 0 {
+        return Ok(());
+    }
+
+    // Policy:
+    // - Any failed => failed reaction immediately
+    // - All completed => done reaction
+    // - Otherwise keep existing processing reaction (do nothing)
+    let emoji = if any_failed {
+        Some(state.config.reaction_failed.as_str())
+    } else if all_completed {
+        Some(state.config.reaction_done.as_str())
+    } else {
+        None
+    };
+
+    let Some(emoji) = emoji else { return Ok(()); };
+    let chat_id = ChatId(bot_chat_id);
+    let message_id = MessageId(leader_message_id as i32);
+    let _ = set_reaction(bot, chat_id, message_id, emoji).await;
+
+    Ok(())
+}
@@ -0,0 +1,62 @@
+//! 搜索结果的短时缓存：同一查询在 TTL 内重复出现时（翻页、前端重渲染）直接复用融合后的 id 列表，
+//! 省掉 embedding 调用与各路召回；item 详情与预签名 URL 每次仍重新获取
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// 缓存条目上限，超出时先清掉过期条目，仍然超出则整体清空
+const MAX_ENTRIES: usize = 512;
+
+pub struct SearchCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (Instant, Vec<i64>)>>,
+}
+
+impl SearchCache {
+    /// ttl 为 0 时不缓存
+    pub fn new(ttl_secs: u64) -> Self {
+        Self {
+            ttl: Duration::from_secs(ttl_secs),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.ttl.is_zero()
+    }
+
+    pub fn get(&self, key: &str) -> Option<Vec<i64>> {
+        if !self.enabled() {
+            return None;
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        match entries.get(key) {
+            Some((at, ids)) if at.elapsed() < self.ttl => Some(ids.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&self, key: String, ids: Vec<i64>) {
+        if !self.enabled() {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.len() >= MAX_ENTRIES {
+            let ttl = self.ttl;
+            entries.retain(|_, (at, _)| at.elapsed() < ttl);
+            if entries.len() >= MAX_ENTRIES {
+                entries.clear();
+            }
+        }
+        entries.insert(key, (Instant::now(), ids));
+    }
+}
+
+/// 查询词归一化：去掉首尾空白、合并连续空白并转小写
+pub fn normalize_query(q: &str) -> String {
+    q.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
@@ -1,12 +1,31 @@
 use crate::config::Config;
+use crate::embedder::{Ocr, Summarizer, TextEmbedder, VisualEmbedder};
+use crate::search_cache::SearchCache;
 use sqlx::PgPool;
-use std::sync::Arc;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
 use s3::bucket::Bucket;
+use tokio::sync::Semaphore;
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: PgPool,
     pub config: Arc<Config>,
     pub http_client: reqwest::Client,
+    pub text_embedder: Arc<dyn TextEmbedder>,
+    pub visual_embedder: Arc<dyn VisualEmbedder>,
+    pub ocr: Arc<dyn Ocr>,
+    /// 未开启摘要时为 None
+    pub summarizer: Option<Arc<dyn Summarizer>>,
     pub s3_signing_client: Bucket,
+    /// 正在抓取头像的实体 id，避免同一实体并发重复抓取
+    pub avatar_inflight: Arc<Mutex<HashSet<i64>>>,
+    /// 最近已回复过「队列已满」的相册（media_group_id），相册的其余成员不再重复回复
+    pub queue_full_albums: Arc<Mutex<HashSet<String>>>,
+    /// 外部推理服务的并发闸门：所有 worker 与搜索接口共享，调用前先取得许可
+    pub clip_permits: Arc<Semaphore>,
+    pub vlm_permits: Arc<Semaphore>,
+    pub embedding_permits: Arc<Semaphore>,
+    /// 融合后的搜索结果 id 列表的短时缓存
+    pub search_cache: Arc<SearchCache>,
 }
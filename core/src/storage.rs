@@ -0,0 +1,146 @@
+use crate::config::Config;
+use s3::bucket::Bucket;
+use s3::bucket_ops::BucketConfiguration;
+use s3::creds::Credentials;
+use s3::region::Region;
+use s3::serde_types::Part;
+use std::path::Path;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// 与 put_object 的默认值一致
+const MULTIPART_CONTENT_TYPE: &str = "application/octet-stream";
+
+fn credentials(config: &Config) -> anyhow::Result<Credentials> {
+    Ok(Credentials::new(
+        Some(&config.s3_access_key),
+        Some(&config.s3_secret_key),
+        None,
+        None,
+        None,
+    )?)
+}
+
+fn region(config: &Config, endpoint: &str) -> Region {
+    Region::Custom {
+        region: config.s3_region.clone(),
+        endpoint: endpoint.to_string(),
+    }
+}
+
+/// 按 S3_REGION / S3_PATH_STYLE 构建 bucket；path style 关闭时使用 virtual-hosted 寻址（`{bucket}.{endpoint}`）
+fn bucket(config: &Config, endpoint: &str) -> anyhow::Result<Bucket> {
+    let bucket = Bucket::new(&config.s3_bucket, region(config, endpoint), credentials(config)?)?;
+    Ok(if config.s3_path_style { *bucket.with_path_style() } else { *bucket })
+}
+
+/// 服务端内部读写用的 bucket（走 s3_endpoint）
+pub fn internal_bucket(config: &Config) -> anyhow::Result<Bucket> {
+    bucket(config, &config.s3_endpoint)
+}
+
+/// 生成预签名 URL 用的 bucket：签名包含 host，必须对浏览器可达的 s3_public_endpoint 签名
+pub fn signing_bucket(config: &Config) -> anyhow::Result<Bucket> {
+    bucket(config, &config.s3_public_endpoint)
+}
+
+/// bucket 不存在时创建（寻址方式与 internal_bucket 一致）
+pub async fn create_bucket(config: &Config) -> anyhow::Result<()> {
+    let region = region(config, &config.s3_endpoint);
+    let credentials = credentials(config)?;
+    let bucket_config = BucketConfiguration::default();
+    let response = if config.s3_path_style {
+        Bucket::create_with_path_style(&config.s3_bucket, region, credentials, bucket_config).await?
+    } else {
+        Bucket::create(&config.s3_bucket, region, credentials, bucket_config).await?
+    };
+    if !response.success() {
+        anyhow::bail!("HTTP {}: {}", response.response_code, response.response_text);
+    }
+    Ok(())
+}
+
+/// 上传 key 用的 bucket：按 key 前缀附带 x-amz-acl。只加在写入请求上，
+/// 共享的 bucket 本身不带 ACL 头（GET / DELETE / 预签名请求不应携带）
+pub fn with_object_acl(bucket: &Bucket, config: &Config, key: &str) -> Bucket {
+    let mut bucket = bucket.clone();
+    if let Some(acl) = config.object_acl(key) {
+        bucket.add_header("x-amz-acl", acl);
+    }
+    bucket
+}
+
+/// 上传内存中的数据：不超过 S3_MULTIPART_THRESHOLD_MB 时一次 put_object，否则分片上传
+pub async fn put_bytes(bucket: &Bucket, config: &Config, key: &str, data: &[u8]) -> anyhow::Result<()> {
+    let bucket = &with_object_acl(bucket, config, key);
+    if data.len() <= config.s3_multipart_threshold {
+        bucket.put_object(key, data).await?;
+        return Ok(());
+    }
+    let mut reader = data;
+    put_multipart(bucket, config, key, &mut reader).await
+}
+
+/// 上传本地文件（如大视频）：按文件大小选择一次 put_object 或分片上传，分片时不会把整个文件读入内存
+pub async fn put_file(bucket: &Bucket, config: &Config, key: &str, path: &Path) -> anyhow::Result<()> {
+    let bucket = &with_object_acl(bucket, config, key);
+    let size = tokio::fs::metadata(path).await?.len();
+    if size <= config.s3_multipart_threshold as u64 {
+        let data = tokio::fs::read(path).await?;
+        bucket.put_object(key, &data).await?;
+        return Ok(());
+    }
+    let mut file = tokio::fs::File::open(path).await?;
+    put_multipart(bucket, config, key, &mut file).await
+}
+
+/// 分片上传；任一步失败时中止该 upload，避免未完成的分片一直占用存储
+async fn put_multipart<R>(bucket: &Bucket, config: &Config, key: &str, reader: &mut R) -> anyhow::Result<()>
+where
+    R: AsyncRead + Unpin,
+{
+    let upload = bucket.initiate_multipart_upload(key, MULTIPART_CONTENT_TYPE).await?;
+    let result = match upload_parts(bucket, config.s3_multipart_part_size, key, &upload.upload_id, reader).await {
+        Ok(parts) => bucket
+            .complete_multipart_upload(key, &upload.upload_id, parts)
+            .await
+            .map(|_| ())
+            .map_err(anyhow::Error::from),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = &result {
+        tracing::warn!("Multipart upload of {} failed, aborting: {}", key, e);
+        if let Err(abort_err) = bucket.abort_upload(key, &upload.upload_id).await {
+            tracing::warn!("Failed to abort multipart upload {} of {}: {}", upload.upload_id, key, abort_err);
+        }
+    }
+    result
+}
+
+async fn upload_parts<R>(
+    bucket: &Bucket,
+    part_size: usize,
+    key: &str,
+    upload_id: &str,
+    reader: &mut R,
+) -> anyhow::Result<Vec<Part>>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut parts = Vec::new();
+    let mut part_number = 1_u32;
+    loop {
+        let mut chunk = Vec::with_capacity(part_size);
+        (&mut *reader).take(part_size as u64).read_to_end(&mut chunk).await?;
+        let last = chunk.len() < part_size;
+        // 数据恰好是分片大小的整数倍时最后读到空块，不再上传
+        if chunk.is_empty() && !parts.is_empty() {
+            break;
+        }
+        parts.push(bucket.put_multipart_chunk(chunk, key, part_number, upload_id, MULTIPART_CONTENT_TYPE).await?);
+        if last {
+            break;
+        }
+        part_number += 1;
+    }
+    Ok(parts)
+}
@@ -0,0 +1,139 @@
+/// worker 任务失败的分类。`code()` 作为 tasks.error_code 落库，
+/// 便于按类别制定重试策略（瞬时故障重试，文件过大等不重试）
+#[derive(Debug, thiserror::Error)]
+pub enum TaskError {
+    #[error("download failed: {0}")]
+    Download(String),
+    #[error("storage failed: {0}")]
+    Storage(String),
+    #[error("VLM failed: {0}")]
+    Vlm(String),
+    #[error("CLIP failed: {0}")]
+    Clip(String),
+    #[error("embedding failed: {0}")]
+    Embedding(String),
+    #[error("database error: {0}")]
+    Db(String),
+    #[error("file too large: {0}")]
+    TooLarge(String),
+    #[error("unsupported: {0}")]
+    Unsupported(String),
+    #[error("unreadable media: {0}")]
+    UnreadableMedia(String),
+    #[error("Telegram file expired; re-forward the message ({0})")]
+    FileExpired(String),
+}
+
+impl TaskError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            TaskError::Download(_) => "download",
+            TaskError::Storage(_) => "storage",
+            TaskError::Vlm(_) => "vlm",
+            TaskError::Clip(_) => "clip",
+            TaskError::Embedding(_) => "embedding",
+            TaskError::Db(_) => "db",
+            TaskError::TooLarge(_) => "too_large",
+            TaskError::Unsupported(_) => "unsupported",
+            TaskError::UnreadableMedia(_) => "unreadable_media",
+            TaskError::FileExpired(_) => "file_expired",
+        }
+    }
+
+    /// 重试有没有意义：外部服务与存储的故障多为瞬时，文件过大/格式不支持/文件损坏重试也不会成功
+    pub fn is_retryable(&self) -> bool {
+        !matches!(
+            self,
+            TaskError::TooLarge(_) | TaskError::Unsupported(_) | TaskError::UnreadableMedia(_) | TaskError::FileExpired(_)
+        )
+    }
+
+    /// Telegram getFile 对超过 20MB 的文件返回 "file is too big"，单独归为 TooLarge；
+    /// file_id 失效（积压任务处理时已过期或被吊销）归为 FileExpired
+    pub fn from_telegram(e: teloxide::RequestError) -> Self {
+        use teloxide::ApiError;
+        let expired = matches!(
+            &e,
+            teloxide::RequestError::Api(ApiError::WrongFileId | ApiError::FileIdInvalid | ApiError::WrongFileIdOrUrl)
+        );
+        let msg = e.to_string();
+        let lower = msg.to_ascii_lowercase();
+        if lower.contains("file is too big") {
+            TaskError::TooLarge(msg)
+        } else if expired || lower.contains("file not found") || lower.contains("file reference expired") {
+            TaskError::FileExpired(msg)
+        } else {
+            TaskError::Download(msg)
+        }
+    }
+
+    pub fn download(e: impl std::fmt::Display) -> Self {
+        TaskError::Download(e.to_string())
+    }
+
+    pub fn storage(e: impl std::fmt::Display) -> Self {
+        TaskError::Storage(e.to_string())
+    }
+
+    pub fn vlm(e: impl std::fmt::Display) -> Self {
+        TaskError::Vlm(e.to_string())
+    }
+
+    pub fn clip(e: impl std::fmt::Display) -> Self {
+        TaskError::Clip(e.to_string())
+    }
+
+    pub fn embedding(e: impl std::fmt::Display) -> Self {
+        TaskError::Embedding(e.to_string())
+    }
+
+    pub fn db(e: impl std::fmt::Display) -> Self {
+        TaskError::Db(e.to_string())
+    }
+}
+
+/// 任意任务错误是否值得重试：分类错误看 TaskError::is_retryable，数据库错误多为瞬时故障；
+/// 未分类的错误（含 panic）多半每次都会复现，不重试
+pub fn is_retryable(e: &anyhow::Error) -> bool {
+    if let Some(task_error) = e.downcast_ref::<TaskError>() {
+        return task_error.is_retryable();
+    }
+    e.downcast_ref::<sqlx::Error>().is_some()
+}
+
+/// 从任意任务错误中取出 error_code；未分类的错误归为 "internal"
+pub fn error_code(e: &anyhow::Error) -> &'static str {
+    if let Some(task_error) = e.downcast_ref::<TaskError>() {
+        return task_error.code();
+    }
+    if e.downcast_ref::<sqlx::Error>().is_some() {
+        return "db";
+    }
+    "internal"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permanent_errors_are_not_retried() {
+        for e in [
+            TaskError::TooLarge("x".into()),
+            TaskError::Unsupported("x".into()),
+            TaskError::UnreadableMedia("x".into()),
+            TaskError::FileExpired("x".into()),
+        ] {
+            assert!(!is_retryable(&e.into()));
+        }
+        assert!(!is_retryable(&anyhow::anyhow!("Internal Panic: boom")));
+    }
+
+    #[test]
+    fn transient_errors_are_retried() {
+        for e in [TaskError::Download("x".into()), TaskError::Vlm("x".into()), TaskError::Db("x".into())] {
+            assert!(is_retryable(&e.into()));
+        }
+        assert!(is_retryable(&sqlx::Error::PoolTimedOut.into()));
+    }
+}
@@ -0,0 +1,489 @@
+//! 网页链接预览：抓取页面，从 <title>、<meta> 与 OpenGraph 标签中提取标题/描述/配图，
+//! 正文去掉标签后截取一段作为检索文本。只做简单的字符串扫描，不构建完整的 DOM。
+//! 链接由用户提交，抓取前先解析主机并拒绝内网 / 回环 / 链路本地等非公网地址，跳转逐跳重新检查
+use crate::config::Config;
+use crate::http;
+use crate::media;
+use crate::task_error::TaskError;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+/// 页面最多读取的字节数，超出部分丢弃（标题与 meta 标签都在开头）
+const MAX_HTML_BYTES: usize = 2 * 1024 * 1024;
+/// 配图大小上限，超出时不收录配图
+const MAX_IMAGE_BYTES: usize = 10 * 1024 * 1024;
+const MAX_TITLE_CHARS: usize = 300;
+const MAX_DESCRIPTION_CHARS: usize = 1000;
+/// 正文摘录的长度，存入 meta.link.excerpt，重建索引时也从这里取
+const MAX_EXCERPT_CHARS: usize = 2000;
+/// 提取正文时跳过整段内容的元素
+const SKIPPED_ELEMENTS: [&str; 6] = ["script", "style", "noscript", "svg", "template", "iframe"];
+/// 最多跟随的跳转次数
+const MAX_REDIRECTS: usize = 5;
+/// 只在内网解析的主机名后缀
+const INTERNAL_HOST_SUFFIXES: [&str; 5] = [".localhost", ".local", ".internal", ".lan", ".home.arpa"];
+
+pub struct LinkPreview {
+    /// 跟随跳转后的最终地址
+    pub url: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub site_name: Option<String>,
+    /// 已解析为绝对地址的 og:image / twitter:image
+    pub image_url: Option<String>,
+    pub excerpt: String,
+}
+
+impl LinkPreview {
+    /// 写入 item meta 的 link 字段
+    pub fn to_meta(&self) -> serde_json::Value {
+        serde_json::json!({
+            "url": self.url,
+            "title": self.title,
+            "description": self.description,
+            "site_name": self.site_name,
+            "image_url": self.image_url,
+            "excerpt": self.excerpt,
+        })
+    }
+}
+
+/// 由 meta.link 拼出检索文本：标题、描述、地址与正文摘录
+pub fn searchable_text(link: &serde_json::Value) -> String {
+    ["title", "description", "url", "excerpt"]
+        .iter()
+        .filter_map(|field| link.get(field).and_then(|v| v.as_str()))
+        .filter(|s| !s.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// 抓取并解析页面；非 HTML 内容、非公网地址与 4xx 不重试
+pub async fn fetch_preview(config: &Config, url: &str) -> Result<LinkPreview, TaskError> {
+    let res = get_public(config, url, "text/html,application/xhtml+xml;q=0.9,*/*;q=0.8").await?;
+
+    let status = res.status();
+    if status.is_client_error() {
+        return Err(TaskError::Unsupported(format!("{} returned HTTP {}", url, status)));
+    }
+    if !status.is_success() {
+        return Err(TaskError::Download(format!("{} returned HTTP {}", url, status)));
+    }
+
+    let content_type = res
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.split(';').next().unwrap_or("").trim().to_ascii_lowercase());
+    if let Some(ct) = content_type.as_deref().filter(|ct| !ct.is_empty()) {
+        if ct != "text/html" && ct != "application/xhtml+xml" {
+            return Err(TaskError::Unsupported(format!("{} is not an HTML page ({})", url, ct)));
+        }
+    }
+
+    let final_url = res.url().clone();
+    let (body, _) = read_capped(res, MAX_HTML_BYTES).await.map_err(TaskError::download)?;
+    let html = String::from_utf8_lossy(&body);
+    Ok(parse_html(&html, &final_url))
+}
+
+/// 下载配图：Content-Type 与文件头都要像图片，超过大小上限时放弃
+pub async fn fetch_image(config: &Config, url: &str) -> Option<Vec<u8>> {
+    let res = match get_public(config, url, "image/*").await {
+        Ok(res) => res,
+        Err(e) => {
+            tracing::warn!("Failed to fetch link image {}: {}", url, e);
+            return None;
+        }
+    };
+    if !res.status().is_success() {
+        return None;
+    }
+    let content_type = res
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.to_string());
+    if !media::content_type_may_be_image(content_type.as_deref()) {
+        return None;
+    }
+    let (bytes, truncated) = read_capped(res, MAX_IMAGE_BYTES).await.ok()?;
+    if truncated || media::sniff_image(&bytes).is_none() {
+        return None;
+    }
+    Some(bytes)
+}
+
+/// GET 一个用户提供的地址，手动跟随跳转：每一跳都检查协议与解析结果，
+/// 并把连接固定到检查过的地址上，避免 DNS 在检查与连接之间被换成内网地址
+async fn get_public(config: &Config, url: &str, accept: &str) -> Result<reqwest::Response, TaskError> {
+    let mut url = reqwest::Url::parse(url).map_err(|e| TaskError::Unsupported(format!("invalid URL {}: {}", url, e)))?;
+    for _ in 0..=MAX_REDIRECTS {
+        let addr = resolve_public(&url).await?;
+        let mut builder = http::client_builder(config).redirect(reqwest::redirect::Policy::none());
+        if let Some(host) = url.domain() {
+            builder = builder.resolve(host, addr);
+        }
+        let client = builder.build().map_err(TaskError::download)?;
+        let res = http::send_with_retry(config.http_max_retries, || {
+            client.get(url.clone()).header(reqwest::header::ACCEPT, accept)
+        })
+        .await
+        .map_err(TaskError::download)?;
+
+        if !res.status().is_redirection() {
+            return Ok(res);
+        }
+        let location = res
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| TaskError::Unsupported(format!("{} returned HTTP {} without Location", url, res.status())))?;
+        url = url
+            .join(location)
+            .map_err(|e| TaskError::Unsupported(format!("{} redirected to invalid URL {}: {}", url, location, e)))?;
+    }
+    Err(TaskError::Unsupported(format!("too many redirects fetching {}", url)))
+}
+
+/// 只允许 http/https；主机解析出的地址必须全部是公网地址，返回用于连接的那一个
+async fn resolve_public(url: &reqwest::Url) -> Result<SocketAddr, TaskError> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(TaskError::Unsupported(format!("unsupported URL scheme: {}", url.scheme())));
+    }
+    let port = url.port_or_known_default().unwrap_or(80);
+    let host = url
+        .host_str()
+        .ok_or_else(|| TaskError::Unsupported(format!("URL has no host: {}", url)))?;
+    // IPv6 字面量在 host_str 中带方括号
+    let addrs: Vec<SocketAddr> = match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        Ok(ip) => vec![SocketAddr::new(ip, port)],
+        Err(_) => {
+            if is_internal_host(host) {
+                return Err(TaskError::Unsupported(format!("refusing to fetch internal host {}", host)));
+            }
+            tokio::net::lookup_host((host, port))
+                .await
+                .map_err(|e| TaskError::Download(format!("failed to resolve {}: {}", host, e)))?
+                .collect()
+        }
+    };
+    if let Some(blocked) = addrs.iter().find(|addr| !is_public_ip(addr.ip())) {
+        return Err(TaskError::Unsupported(format!("refusing to fetch {}: {} is not a public address", url, blocked.ip())));
+    }
+    addrs
+        .into_iter()
+        .next()
+        .ok_or_else(|| TaskError::Download(format!("{} resolved to no addresses", url)))
+}
+
+/// 单标签主机名（docker 服务名、内网短名）与常见内网后缀
+fn is_internal_host(host: &str) -> bool {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    host == "localhost" || !host.contains('.') || INTERNAL_HOST_SUFFIXES.iter().any(|suffix| host.ends_with(suffix))
+}
+
+/// 公网单播地址；回环、私有网段、链路本地（含 169.254.169.254 元数据服务）、CGNAT、保留与组播地址都不算
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_ipv4(ip),
+        IpAddr::V6(ip) => {
+            if let Some(v4) = ip.to_ipv4_mapped() {
+                return is_public_ipv4(v4);
+            }
+            let segments = ip.segments();
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || (segments[0] & 0xfe00) == 0xfc00 // fc00::/7 唯一本地
+                || (segments[0] & 0xffc0) == 0xfe80 // fe80::/10 链路本地
+                || (segments[0] == 0x2001 && segments[1] == 0x0db8) // 2001:db8::/32 文档
+                || (segments[0] == 0x0064 && segments[1] == 0xff9b) // 64:ff9b::/96 NAT64，可能映射到内网
+                || segments[..6] == [0, 0, 0, 0, 0, 0]) // ::/96 IPv4 兼容地址
+        }
+    }
+}
+
+fn is_public_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        || a == 0
+        || (a == 100 && (b & 0xc0) == 64) // 100.64.0.0/10 CGNAT
+        || (a == 192 && b == 0 && c == 0) // 192.0.0.0/24 协议分配
+        || (a == 198 && (b & 0xfe) == 18) // 198.18.0.0/15 基准测试
+        || a >= 240) // 240.0.0.0/4 保留
+}
+
+/// 逐块读取响应体，最多 cap 字节；第二个返回值表示是否被截断
+async fn read_capped(mut res: reqwest::Response, cap: usize) -> reqwest::Result<(Vec<u8>, bool)> {
+    let mut body = Vec::new();
+    while let Some(chunk) = res.chunk().await? {
+        if body.len() + chunk.len() > cap {
+            body.extend_from_slice(&chunk[..cap - body.len()]);
+            return Ok((body, true));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok((body, false))
+}
+
+fn parse_html(html: &str, base: &reqwest::Url) -> LinkPreview {
+    // 只改 ASCII 大小写，字节偏移与原文一致，可以在 lower 上查找、在原文上切片
+    let lower = html.to_ascii_lowercase();
+
+    let mut metas: Vec<(String, String)> = Vec::new();
+    let mut pos = 0;
+    while let Some(offset) = lower[pos..].find("<meta") {
+        let start = pos + offset + "<meta".len();
+        let end = lower[start..].find('>').map(|p| start + p).unwrap_or(lower.len());
+        let attrs = parse_attrs(&html[start..end]);
+        let key = attr(&attrs, "property").or_else(|| attr(&attrs, "name"));
+        if let (Some(key), Some(content)) = (key, attr(&attrs, "content")) {
+            metas.push((key.to_ascii_lowercase(), content.to_string()));
+        }
+        pos = end;
+    }
+    let meta = |keys: &[&str]| -> Option<String> {
+        keys.iter().find_map(|key| {
+            metas
+                .iter()
+                .find(|(k, v)| k == key && !v.trim().is_empty())
+                .map(|(_, v)| v.trim().to_string())
+        })
+    };
+
+    let title = meta(&["og:title", "twitter:title"]).or_else(|| element_text(html, &lower, "title"));
+    let description = meta(&["og:description", "twitter:description", "description"]);
+    let site_name = meta(&["og:site_name", "application-name"]);
+    let image_url = meta(&["og:image:secure_url", "og:image:url", "og:image", "twitter:image", "twitter:image:src"])
+        .and_then(|src| base.join(&src).ok())
+        .filter(|u| matches!(u.scheme(), "http" | "https"))
+        .map(|u| u.to_string());
+
+    LinkPreview {
+        url: base.to_string(),
+        title: title.map(|t| truncate_chars(&collapse_whitespace(&t), MAX_TITLE_CHARS)),
+        description: description.map(|d| truncate_chars(&collapse_whitespace(&d), MAX_DESCRIPTION_CHARS)),
+        site_name,
+        image_url,
+        excerpt: truncate_chars(&body_text(html, &lower), MAX_EXCERPT_CHARS),
+    }
+}
+
+/// 解析标签内的属性（不含标签名），值中的实体已解码；属性名转小写
+fn parse_attrs(s: &str) -> Vec<(String, String)> {
+    let bytes = s.as_bytes();
+    let len = bytes.len();
+    let mut attrs = Vec::new();
+    let mut i = 0;
+    loop {
+        while i < len && (bytes[i].is_ascii_whitespace() || bytes[i] == b'/') {
+            i += 1;
+        }
+        let name_start = i;
+        while i < len && !bytes[i].is_ascii_whitespace() && bytes[i] != b'=' && bytes[i] != b'/' {
+            i += 1;
+        }
+        if name_start == i {
+            break;
+        }
+        let name = s[name_start..i].to_ascii_lowercase();
+        while i < len && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        if i >= len || bytes[i] != b'=' {
+            attrs.push((name, String::new()));
+            continue;
+        }
+        i += 1;
+        while i < len && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        let value = if i < len && (bytes[i] == b'"' || bytes[i] == b'\'') {
+            let quote = bytes[i];
+            let value_start = i + 1;
+            i = value_start;
+            while i < len && bytes[i] != quote {
+                i += 1;
+            }
+            let value = &s[value_start..i];
+            i = (i + 1).min(len);
+            value
+        } else {
+            let value_start = i;
+            while i < len && !bytes[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            &s[value_start..i]
+        };
+        attrs.push((name, decode_entities(value)));
+    }
+    attrs
+}
+
+fn attr<'a>(attrs: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    attrs.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str())
+}
+
+/// 第一个 <name> 元素的文本内容
+fn element_text(html: &str, lower: &str, name: &str) -> Option<String> {
+    let open = lower.find(&format!("<{}", name))?;
+    let content_start = open + lower[open..].find('>')? + 1;
+    let content_end = content_start + lower[content_start..].find(&format!("</{}", name))?;
+    let text = collapse_whitespace(&decode_entities(&html[content_start..content_end]));
+    (!text.is_empty()).then_some(text)
+}
+
+/// <body> 中的可见文字：去掉注释、脚本样式等整段元素与所有标签
+fn body_text(html: &str, lower: &str) -> String {
+    let mut out = String::new();
+    let mut i = lower.find("<body").unwrap_or(0);
+    while i < html.len() && out.len() < MAX_EXCERPT_CHARS * 8 {
+        let Some(offset) = lower[i..].find('<') else {
+            out.push_str(&html[i..]);
+            break;
+        };
+        out.push_str(&html[i..i + offset]);
+        out.push(' ');
+        let tag_start = i + offset;
+        let rest = &lower[tag_start..];
+
+        let skip_until = if rest.starts_with("<!--") {
+            Some("-->".to_string())
+        } else {
+            SKIPPED_ELEMENTS
+                .iter()
+                .find(|name| {
+                    rest[1..].starts_with(*name)
+                        && !rest.as_bytes().get(1 + name.len()).is_some_and(|b| b.is_ascii_alphanumeric())
+                })
+                .map(|name| format!("</{}", name))
+        };
+        let after = match skip_until {
+            Some(close) => match rest.find(&close) {
+                Some(p) => tag_start + p + close.len(),
+                None => break,
+            },
+            None => tag_start,
+        };
+        i = match lower[after..].find('>') {
+            Some(p) => after + p + 1,
+            None => break,
+        };
+    }
+    collapse_whitespace(&decode_entities(&out))
+}
+
+/// 解码常见的命名实体与数字实体，其余原样保留
+fn decode_entities(s: &str) -> String {
+    if !s.contains('&') {
+        return s.to_string();
+    }
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest.find(';').filter(|&semi| semi <= 10).and_then(|semi| {
+            let entity = &rest[1..semi];
+            let ch = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                _ => entity
+                    .strip_prefix("#x")
+                    .or_else(|| entity.strip_prefix("#X"))
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                    .and_then(char::from_u32),
+            };
+            ch.map(|c| (c, semi))
+        });
+        match decoded {
+            Some((c, semi)) => {
+                out.push(c);
+                rest = &rest[semi + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn collapse_whitespace(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn truncate_chars(s: &str, max: usize) -> String {
+    match s.char_indices().nth(max) {
+        Some((idx, _)) => s[..idx].to_string(),
+        None => s.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn public(ip: &str) -> bool {
+        is_public_ip(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn rejects_non_global_addresses() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+            "64:ff9b::a00:1",
+        ] {
+            assert!(!public(ip), "{} should be rejected", ip);
+        }
+    }
+
+    #[test]
+    fn accepts_global_addresses() {
+        for ip in ["1.1.1.1", "93.184.216.34", "2606:4700:4700::1111", "::ffff:8.8.8.8"] {
+            assert!(public(ip), "{} should be allowed", ip);
+        }
+    }
+
+    #[test]
+    fn rejects_internal_host_names() {
+        for host in ["localhost", "LOCALHOST.", "api.localhost", "db", "printer.local", "metadata.google.internal"] {
+            assert!(is_internal_host(host), "{} should be rejected", host);
+        }
+        assert!(!is_internal_host("example.com"));
+    }
+
+    #[tokio::test]
+    async fn rejects_non_http_schemes_and_literal_private_hosts() {
+        for url in ["file:///etc/passwd", "ftp://example.com/", "http://127.0.0.1:8080/", "http://[::1]/", "http://169.254.169.254/latest/meta-data/"] {
+            let url = reqwest::Url::parse(url).unwrap();
+            assert!(matches!(resolve_public(&url).await, Err(TaskError::Unsupported(_))), "{} should be rejected", url);
+        }
+    }
+}
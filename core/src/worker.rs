@@ -1,15 +1,20 @@
 use crate::state::AppState;
 use sqlx::Row;
 use teloxide::prelude::*;
-use teloxide::net::Download;
-use teloxide::types::{ReactionType, FileId};
+use teloxide::types::{FileId, MessageId};
+use crate::reactions;
+use crate::{bot, db, media};
+use crate::task_error::{self, TaskError};
+use crate::storage;
+use crate::unfurl;
+use crate::embedder::normalized_centroid;
 use s3::Bucket;
-use s3::creds::Credentials;
-use s3::region::Region;
 use std::panic::AssertUnwindSafe;
 use std::process::Stdio;
 use futures::FutureExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::process::Command;
+use image::codecs::jpeg::JpegEncoder;
 
 fn payload_group_id_str(payload: &serde_json::Value) -> Option<String> {
     payload.get("tg_group_id").and_then(|v| match v {
@@ -19,62 +24,9 @@ fn payload_group_id_str(payload: &serde_json::Value) -> Option<String> {
     })
 }
 
-async fn update_album_reaction(
-    state: &AppState,
-    bot: &Bot,
-    bot_chat_id: i64,
-    group_id: &str,
-) -> anyhow::Result<()> {
-    let row = sqlx::query(
-        r#"
-        SELECT
-            MIN(bot_message_id) AS leader_message_id,
-            COUNT(*)::bigint AS cnt,
-            BOOL_OR(status = 'failed') AS any_failed,
-            BOOL_AND(status = 'completed') AS all_completed
-        FROM tasks
-        WHERE bot_chat_id = $1
-          AND payload->>'tg_group_id' = $2
-        "#,
-    )
-    .bind(bot_chat_id)
-    .bind(group_id)
-    .fetch_one(&state.db)
-    .await?;
-
-    let leader_message_id: Option<i64> = row.try_get("leader_message_id").ok();
-    let cnt: i64 = row.try_get::<i64, _>("cnt").unwrap_or(0);
-    let any_failed: bool = row.try_get::<Option<bool>, _>("any_failed").ok().flatten().unwrap_or(false);
-    let all_completed: bool = row.try_get::<Option<bool>, _>("all_completed").ok().flatten().unwrap_or(false);
-
-    let Some(leader_message_id) = leader_message_id else { return Ok(()); };
-    if cnt <= 0 {
-        return Ok(());
-    }
-
-    // Policy:
-    // - Any failed => 👎 immediately
-    // - All completed => ❤️
-    // - Otherwise keep existing 👀 (do nothing)
-    let emoji = if any_failed {
-        Some("👎")
-    } else if all_completed {
-        Some("❤️")
-    } else {
-        None
-    };
-
-    let Some(emoji) = emoji else { return Ok(()); };
-    let chat_id = teloxide::types::ChatId(bot_chat_id);
-    let message_id = teloxide::types::MessageId(leader_message_id as i32);
-    let reaction = ReactionType::Emoji { emoji: emoji.to_string() };
-    let _ = bot
-        .set_message_reaction(chat_id, message_id)
-        .reaction(vec![reaction])
-        .send()
-        .await;
-
-    Ok(())
+/// 系统任务（非 Telegram 消息触发，如重建索引、浏览器直传），不需要 reaction 与错误回复
+fn payload_is_system(payload: &serde_json::Value) -> bool {
+    payload.get("reprocess_item_id").is_some() || payload.get("uploaded_s3_key").is_some()
 }
 
 fn payload_tag_ids(payload: &serde_json::Value) -> Vec<i32> {
@@ -89,6 +41,14 @@ fn payload_tag_ids(payload: &serde_json::Value) -> Vec<i32> {
         .unwrap_or_default()
 }
 
+/// 任务处理期间通过 reaction 设置的可见性，item 创建时直接带上
+fn payload_visibility(payload: &serde_json::Value) -> &'static str {
+    match payload.get("visibility").and_then(|v| v.as_str()) {
+        Some("public") => "public",
+        _ => "private",
+    }
+}
+
 async fn apply_tag_ids_to_item(state: &AppState, item_id: i64, tag_ids: &[i32]) -> anyhow::Result<()> {
     if tag_ids.is_empty() {
         return Ok(());
@@ -115,26 +75,41 @@ async fn apply_tag_ids_to_item(state: &AppState, item_id: i64, tag_ids: &[i32])
     Ok(())
 }
 
+/// 分块计算文件的 md5，避免把大文件整个读入内存
+async fn md5_file(path: &std::path::Path) -> std::io::Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut ctx = md5::Context::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        ctx.consume(&buf[..n]);
+    }
+    Ok(format!("{:x}", ctx.compute()))
+}
+
 pub async fn run_worker(state: AppState) {
     tracing::info!("Worker pipeline started.");
 
-    let region = Region::Custom {
-        region: "us-east-1".to_owned(),
-        endpoint: state.config.s3_endpoint.clone(),
-    };
-    let credentials = Credentials::new(
-        Some(&state.config.s3_access_key),
-        Some(&state.config.s3_secret_key),
-        None, None, None
-    ).expect("Failed to create S3 credentials");
-    
-    let bucket = Bucket::new(
-        &state.config.s3_bucket,
-        region,
-        credentials
-    ).expect("Failed to create S3 bucket").with_path_style();
+    // ACL 按 key 在每次上传时设置（storage::with_object_acl），共享的 bucket 不带 ACL 头
+    let bucket = storage::internal_bucket(&state.config).expect("Failed to create S3 bucket");
+
+    // 启动时先回收上次异常退出遗留的任务，之后定期检查
+    let mut last_recovery: Option<tokio::time::Instant> = None;
 
     loop {
+        if last_recovery.is_none_or(|t| t.elapsed() >= STUCK_TASK_CHECK_INTERVAL) {
+            if let Err(e) = recover_stuck_tasks(&state).await {
+                tracing::error!("Failed to recover stuck tasks: {:?}", e);
+            }
+            if let Err(e) = settle_stale_albums(&state).await {
+                tracing::error!("Failed to settle stale albums: {:?}", e);
+            }
+            last_recovery = Some(tokio::time::Instant::now());
+        }
+
         let result = AssertUnwindSafe(process_next_task(&state, &bucket)).catch_unwind().await;
         
         match result {
@@ -159,42 +134,249 @@ pub async fn run_worker(state: AppState) {
     }
 }
 
+const STUCK_TASK_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// 把 processing 超时（worker 中途崩溃）的任务重置为 pending 以便重新处理；
+/// 重试次数已用完的任务（多半每次都会让 worker 崩溃）直接标记失败
+async fn recover_stuck_tasks(state: &AppState) -> anyhow::Result<()> {
+    let result = sqlx::query(
+        r#"
+        UPDATE tasks
+        SET status = 'pending',
+            retry_count = COALESCE(retry_count, 0) + 1,
+            updated_at = NOW()
+        WHERE status = 'processing'
+          AND updated_at < NOW() - make_interval(secs => $1::float8)
+          AND COALESCE(retry_count, 0) < $2
+        "#,
+    )
+    .bind(state.config.processing_timeout_secs as f64)
+    .bind(state.config.task_max_retries)
+    .execute(&state.db)
+    .await?;
+
+    if result.rows_affected() > 0 {
+        tracing::warn!("Recovered {} stuck processing tasks", result.rows_affected());
+    }
+
+    let exhausted = sqlx::query(
+        r#"
+        UPDATE tasks
+        SET status = 'failed',
+            error_message = 'processing timed out too many times',
+            error_code = 'timeout',
+            updated_at = NOW()
+        WHERE status = 'processing'
+          AND updated_at < NOW() - make_interval(secs => $1::float8)
+        RETURNING bot_chat_id, bot_message_id, payload
+        "#,
+    )
+    .bind(state.config.processing_timeout_secs as f64)
+    .fetch_all(&state.db)
+    .await?;
+    if exhausted.is_empty() {
+        return Ok(());
+    }
+
+    tracing::warn!("Marked {} repeatedly stuck tasks as failed", exhausted.len());
+    let bot = bot::new_bot(&state.config);
+    for row in exhausted {
+        let payload: serde_json::Value = row.get::<Option<serde_json::Value>, _>("payload").unwrap_or_default();
+        if payload_is_system(&payload) {
+            continue;
+        }
+        let bot_chat_id: i64 = row.get("bot_chat_id");
+        if let Some(gid) = payload_group_id_str(&payload) {
+            let _ = reactions::update_album_reaction(state, &bot, bot_chat_id, &gid).await;
+        } else {
+            let message_id = MessageId(row.get::<i64, _>("bot_message_id") as i32);
+            let _ = reactions::set_reaction(&bot, ChatId(bot_chat_id), message_id, &state.config.reaction_failed).await;
+        }
+    }
+    Ok(())
+}
+
+/// 可重试的失败重新排队：延后 created_at 实现指数退避（领取时只取 created_at <= NOW() 的任务），
+/// 期间保留 processing reaction，不回复失败。重试次数已用完时返回 false，由调用方按失败处理
+async fn requeue_for_retry(state: &AppState, task_id: i64, e: &anyhow::Error) -> anyhow::Result<bool> {
+    let result = sqlx::query(
+        r#"
+        UPDATE tasks
+        SET status = 'pending',
+            retry_count = COALESCE(retry_count, 0) + 1,
+            error_message = $2,
+            error_code = $3,
+            created_at = NOW() + make_interval(secs => $4::float8 * power(2, COALESCE(retry_count, 0))),
+            updated_at = NOW()
+        WHERE id = $1 AND COALESCE(retry_count, 0) < $5
+        "#,
+    )
+    .bind(task_id)
+    .bind(e.to_string())
+    .bind(task_error::error_code(e))
+    .bind(state.config.task_retry_delay_secs as f64)
+    .bind(state.config.task_max_retries)
+    .execute(&state.db)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// 组图中有成员卡在 pending / processing 时 update_album_reaction 永远不会标记完成：
+/// 首个成员完成超过 ALBUM_COMPLETE_TIMEOUT_SECS 后，逐条消息标记，已完成的设完成 reaction，卡住的设失败 reaction。
+/// 每个组图只处理一次（任务 payload 记 album_timeout_reacted）
+async fn settle_stale_albums(state: &AppState) -> anyhow::Result<()> {
+    if state.config.album_complete_timeout_secs <= 0 {
+        return Ok(());
+    }
+
+    // 只看仍有未完成成员的组图，避免扫描全部历史任务
+    let albums = sqlx::query(
+        r#"
+        SELECT t.bot_chat_id, t.payload->>'tg_group_id' AS group_id
+        FROM tasks t
+        WHERE (t.bot_chat_id, t.payload->>'tg_group_id') IN (
+            SELECT bot_chat_id, payload->>'tg_group_id'
+            FROM tasks
+            WHERE status IN ('pending', 'processing')
+              AND payload->>'tg_group_id' IS NOT NULL
+              AND bot_chat_id <> 0
+        )
+        GROUP BY t.bot_chat_id, t.payload->>'tg_group_id'
+        HAVING NOT BOOL_OR(t.status = 'failed')
+           AND MIN(t.updated_at) FILTER (WHERE t.status = 'completed') < NOW() - make_interval(secs => $1::float8)
+           AND NOT BOOL_OR(COALESCE((t.payload->>'album_timeout_reacted')::boolean, false))
+        "#,
+    )
+    .bind(state.config.album_complete_timeout_secs as f64)
+    .fetch_all(&state.db)
+    .await?;
+    if albums.is_empty() {
+        return Ok(());
+    }
+
+    let bot = bot::new_bot(&state.config);
+    for album in albums {
+        let bot_chat_id: i64 = album.get("bot_chat_id");
+        let group_id: String = album.get("group_id");
+        let members = sqlx::query(
+            r#"
+            UPDATE tasks
+            SET payload = COALESCE(payload, '{}'::jsonb) || '{"album_timeout_reacted": true}'::jsonb
+            WHERE bot_chat_id = $1 AND payload->>'tg_group_id' = $2
+            RETURNING bot_message_id, status
+            "#,
+        )
+        .bind(bot_chat_id)
+        .bind(&group_id)
+        .fetch_all(&state.db)
+        .await?;
+
+        let chat_id = ChatId(bot_chat_id);
+        let mut stuck = 0;
+        for member in &members {
+            let message_id = MessageId(member.get::<i64, _>("bot_message_id") as i32);
+            let emoji = if member.get::<Option<String>, _>("status").as_deref() == Some("completed") {
+                &state.config.reaction_done
+            } else {
+                stuck += 1;
+                &state.config.reaction_failed
+            };
+            let _ = reactions::set_reaction(&bot, chat_id, message_id, emoji).await;
+        }
+        tracing::warn!(
+            "Album {} in chat {} timed out with {} of {} members unfinished",
+            group_id, bot_chat_id, stuck, members.len()
+        );
+    }
+    Ok(())
+}
+
+/// 已领取（status 已置为 processing）的任务
+struct ClaimedTask {
+    id: i64,
+    bot_chat_id: i64,
+    bot_message_id: i64,
+    source_chat_id: Option<i64>,
+    source_message_id: Option<i64>,
+    source_user_id: Option<i64>,
+    payload: serde_json::Value,
+}
+
 async fn process_next_task(state: &AppState, bucket: &Bucket) -> anyhow::Result<bool> {
-    let mut tx = state.db.begin().await?;
+    let tasks = claim_tasks(&state.db, state.config.worker_batch_claim).await?;
+    if tasks.is_empty() {
+        return Ok(false);
+    }
+    let task_ids: Vec<i64> = tasks.iter().map(|t| t.id).collect();
+
+    // 并发处理本批任务；每个任务独立收尾，一个失败不会影响其它任务的状态落库
+    let results = futures::future::join_all(tasks.into_iter().map(|task| run_claimed_task(state, bucket, task))).await;
+    for (task_id, result) in task_ids.iter().zip(results) {
+        if let Err(e) = result {
+            tracing::error!("Failed to finalize task #{}: {:?}", task_id, e);
+        }
+    }
+
+    Ok(true)
+}
+
+/// 一次事务领取至多 batch_size 个 pending 任务并置为 processing，减少高吞吐时每个任务一次事务的开销
+async fn claim_tasks(pool: &sqlx::PgPool, batch_size: i64) -> sqlx::Result<Vec<ClaimedTask>> {
+    let mut tx = pool.begin().await?;
     
-    let row = sqlx::query(
+    let rows = sqlx::query(
         r#"
         SELECT id, bot_chat_id, bot_message_id, source_chat_id, source_message_id, source_user_id, payload 
         FROM tasks 
         WHERE status = 'pending' 
+          AND created_at <= NOW()
         ORDER BY created_at ASC 
-        LIMIT 1 
+        LIMIT $1 
         FOR UPDATE SKIP LOCKED
         "#
     )
-    .fetch_optional(&mut *tx)
+    .bind(batch_size.max(1))
+    .fetch_all(&mut *tx)
     .await?;
 
-    let (task_id, bot_chat_id, bot_message_id, source_chat_id, source_message_id, source_user_id, payload) = match row {
-        Some(r) => (
-             r.get::<i64, _>("id"),
-             r.get::<i64, _>("bot_chat_id"),
-             r.get::<i64, _>("bot_message_id"),
-             r.get::<Option<i64>, _>("source_chat_id"),
-             r.get::<Option<i64>, _>("source_message_id"),
-             r.get::<Option<i64>, _>("source_user_id"),
-             r.get::<Option<serde_json::Value>, _>("payload").unwrap_or(serde_json::json!({}))
-        ),
-        None => return Ok(false),
-    };
+    if rows.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let tasks: Vec<ClaimedTask> = rows
+        .iter()
+        .map(|r| ClaimedTask {
+            id: r.get::<i64, _>("id"),
+            bot_chat_id: r.get::<i64, _>("bot_chat_id"),
+            bot_message_id: r.get::<i64, _>("bot_message_id"),
+            source_chat_id: r.get::<Option<i64>, _>("source_chat_id"),
+            source_message_id: r.get::<Option<i64>, _>("source_message_id"),
+            source_user_id: r.get::<Option<i64>, _>("source_user_id"),
+            payload: r.get::<Option<serde_json::Value>, _>("payload").unwrap_or(serde_json::json!({})),
+        })
+        .collect();
+    let task_ids: Vec<i64> = tasks.iter().map(|t| t.id).collect();
 
-    sqlx::query("UPDATE tasks SET status = 'processing', updated_at = NOW() WHERE id = $1")
-        .bind(task_id)
+    sqlx::query("UPDATE tasks SET status = 'processing', updated_at = NOW() WHERE id = ANY($1)")
+        .bind(&task_ids)
         .execute(&mut *tx)
         .await?;
 
     tx.commit().await?;
-    
+    Ok(tasks)
+}
+
+async fn run_claimed_task(state: &AppState, bucket: &Bucket, task: ClaimedTask) -> anyhow::Result<()> {
+    let ClaimedTask {
+        id: task_id,
+        bot_chat_id,
+        bot_message_id,
+        source_chat_id,
+        source_message_id,
+        source_user_id,
+        payload,
+    } = task;
+
     tracing::info!("Processing task #{}", task_id);
     
     let result = match AssertUnwindSafe(perform_task(state, bucket, bot_chat_id, bot_message_id, source_chat_id, source_message_id, source_user_id, payload.clone())).catch_unwind().await {
@@ -211,7 +393,38 @@ async fn process_next_task(state: &AppState, bucket: &Bucket) -> anyhow::Result<
         }
     };
 
-    let bot = Bot::new(&state.config.tg_bot_token);
+    // 瞬时故障先重试，永久性错误（文件过大、格式不支持、文件损坏等）立即按失败处理
+    if let Err(e) = &result {
+        if task_error::is_retryable(e) && requeue_for_retry(state, task_id, e).await? {
+            tracing::warn!("Task #{} failed, requeued for retry: {}", task_id, e);
+            return Ok(());
+        }
+    }
+
+    // 系统任务（如重建索引）没有对应的 Telegram 消息，只落库状态
+    if payload_is_system(&payload) {
+        match result {
+            Ok(item_id) => {
+                sqlx::query("UPDATE tasks SET status = 'completed', item_id = $1, updated_at = NOW() WHERE id = $2")
+                    .bind(item_id)
+                    .bind(task_id)
+                    .execute(&state.db)
+                    .await?;
+            }
+            Err(e) => {
+                tracing::error!("System task #{} failed: {}", task_id, e);
+                sqlx::query("UPDATE tasks SET status = 'failed', error_message = $1, error_code = $2, updated_at = NOW() WHERE id = $3")
+                    .bind(e.to_string())
+                    .bind(task_error::error_code(&e))
+                    .bind(task_id)
+                    .execute(&state.db)
+                    .await?;
+            }
+        }
+        return Ok(());
+    }
+
+    let bot = bot::new_bot(&state.config);
     let chat_id = teloxide::types::ChatId(bot_chat_id);
     let message_id = teloxide::types::MessageId(bot_message_id as i32);
     
@@ -233,18 +446,21 @@ async fn process_next_task(state: &AppState, bucket: &Bucket) -> anyhow::Result<
                 .await?;
             
             // Reaction policy for albums:
-            // - ❤️ only when the whole album has completed
-            // - 👎 if any member failed
-            // - otherwise keep 👀 (do nothing)
+            // - done only when the whole album has completed
+            // - failed if any member failed
+            // - otherwise keep processing (do nothing)
             if let Some(gid) = payload_group_id_str(&payload) {
-                let _ = update_album_reaction(state, &bot, bot_chat_id, &gid).await;
+                let _ = reactions::update_album_reaction(state, &bot, bot_chat_id, &gid).await;
+                if state.config.reply_on_success {
+                    if let Err(e) = reply_album_success(state, &bot, bot_chat_id, &gid).await {
+                        tracing::warn!("Failed to send album success reply: {}", e);
+                    }
+                }
             } else {
-                let reaction = ReactionType::Emoji { emoji: "❤️".to_string() };
-                let _ = bot
-                    .set_message_reaction(chat_id, message_id)
-                    .reaction(vec![reaction])
-                    .send()
-                    .await;
+                let _ = reactions::set_reaction(&bot, chat_id, message_id, &state.config.reaction_done).await;
+                if state.config.reply_on_success {
+                    send_success_reply(state, &bot, chat_id, message_id, format!("✅ 已保存为 #{}", item_id)).await;
+                }
             }
             
             // 删除之前的错误回复消息（如果有）
@@ -256,14 +472,9 @@ async fn process_next_task(state: &AppState, bucket: &Bucket) -> anyhow::Result<
             tracing::error!("Task #{} failed: {}", task_id, e);
             
             if let Some(gid) = payload_group_id_str(&payload) {
-                let _ = update_album_reaction(state, &bot, bot_chat_id, &gid).await;
+                let _ = reactions::update_album_reaction(state, &bot, bot_chat_id, &gid).await;
             } else {
-                let reaction = ReactionType::Emoji { emoji: "👎".to_string() };
-                let _ = bot
-                    .set_message_reaction(chat_id, message_id)
-                    .reaction(vec![reaction])
-                    .send()
-                    .await;
+                let _ = reactions::set_reaction(&bot, chat_id, message_id, &state.config.reaction_failed).await;
             }
             
             // 查询是否已有错误回复消息
@@ -296,8 +507,9 @@ async fn process_next_task(state: &AppState, bucket: &Bucket) -> anyhow::Result<
             };
             
             // 更新任务状态和错误回复 ID
-            sqlx::query("UPDATE tasks SET status = 'failed', error_message = $1, error_reply_id = $2, updated_at = NOW() WHERE id = $3")
+            sqlx::query("UPDATE tasks SET status = 'failed', error_message = $1, error_code = $2, error_reply_id = $3, updated_at = NOW() WHERE id = $4")
                 .bind(e.to_string())
+                .bind(task_error::error_code(&e))
                 .bind(if error_reply_id > 0 { Some(error_reply_id) } else { None })
                 .bind(task_id)
                 .execute(&state.db)
@@ -305,20 +517,104 @@ async fn process_next_task(state: &AppState, bucket: &Bucket) -> anyhow::Result<
         }
     }
 
-    Ok(true)
+    Ok(())
+}
+
+/// 组图全部完成后回复首条消息一次。
+/// 多个成员可能同时完成，先在首条消息的任务上打 success_replied 标记，抢到标记的 worker 才发送
+async fn reply_album_success(state: &AppState, bot: &Bot, bot_chat_id: i64, group_id: &str) -> anyhow::Result<()> {
+    let leader_message_id: Option<i64> = sqlx::query_scalar(
+        r#"
+        UPDATE tasks SET payload = payload || '{"success_replied": true}'::jsonb
+        WHERE id = (
+            SELECT id FROM tasks
+            WHERE bot_chat_id = $1 AND payload->>'tg_group_id' = $2
+            ORDER BY bot_message_id
+            LIMIT 1
+        )
+          AND NOT (payload ? 'success_replied')
+          AND NOT EXISTS (
+            SELECT 1 FROM tasks
+            WHERE bot_chat_id = $1 AND payload->>'tg_group_id' = $2 AND status <> 'completed'
+          )
+        RETURNING bot_message_id
+        "#,
+    )
+    .bind(bot_chat_id)
+    .bind(group_id)
+    .fetch_optional(&state.db)
+    .await?;
+    let Some(leader_message_id) = leader_message_id else { return Ok(()); };
+
+    let item_ids: Vec<i64> = sqlx::query_scalar(
+        r#"
+        SELECT item_id FROM tasks
+        WHERE bot_chat_id = $1 AND payload->>'tg_group_id' = $2 AND item_id IS NOT NULL
+        ORDER BY bot_message_id
+        "#,
+    )
+    .bind(bot_chat_id)
+    .bind(group_id)
+    .fetch_all(&state.db)
+    .await?;
+    let ids = item_ids.iter().map(|id| format!("#{}", id)).collect::<Vec<_>>().join(" ");
+
+    send_success_reply(
+        state,
+        bot,
+        teloxide::types::ChatId(bot_chat_id),
+        teloxide::types::MessageId(leader_message_id as i32),
+        format!("✅ 已保存 {} 条：{}", item_ids.len(), ids),
+    )
+    .await;
+    Ok(())
+}
+
+/// 回复成功确认消息；REPLY_TTL_SECS > 0 时到期后删除
+async fn send_success_reply(state: &AppState, bot: &Bot, chat_id: ChatId, reply_to: MessageId, text: String) {
+    let reply_params = teloxide::types::ReplyParameters::new(reply_to);
+    let sent = match bot.send_message(chat_id, text).reply_parameters(reply_params).await {
+        Ok(sent) => sent,
+        Err(e) => {
+            tracing::warn!("Failed to send success reply: {}", e);
+            return;
+        }
+    };
+    let ttl = state.config.reply_ttl_secs;
+    if ttl == 0 {
+        return;
+    }
+    let bot = bot.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(tokio::time::Duration::from_secs(ttl)).await;
+        let _ = bot.delete_message(chat_id, sent.id).await;
+    });
+}
+
+/// 上传原文件用的 bucket：配置了 S3_ORIGINAL_STORAGE_CLASS 时附带存储类别头
+fn original_bucket(state: &AppState, bucket: &Bucket) -> Bucket {
+    let mut original = bucket.clone();
+    if let Some(class) = state.config.s3_original_storage_class.as_deref() {
+        original.add_header("x-amz-storage-class", class);
+    }
+    original
 }
 
 async fn perform_task(
     state: &AppState, 
     bucket: &Bucket, 
-    _bot_chat_id: i64, 
-    _bot_message_id: i64, 
+    bot_chat_id: i64, 
+    bot_message_id: i64, 
     source_chat_id: Option<i64>,
     source_message_id: Option<i64>,
     source_user_id: Option<i64>,
     payload: serde_json::Value
 ) -> anyhow::Result<i64> {
-    let bot = Bot::new(&state.config.tg_bot_token);
+    if let Some(item_id) = payload.get("reprocess_item_id").and_then(|v| v.as_i64()) {
+        return reprocess_item(state, bucket, item_id, ReprocessOptions::from_payload(&payload)).await;
+    }
+
+    let bot = bot::new_bot(&state.config);
     let file_id = payload["file_id"].as_str();
     let item_type = payload["item_type"].as_str().unwrap_or("text");
     let content_text = payload["content_text"].as_str().unwrap_or("").to_string();
@@ -331,46 +627,180 @@ async fn perform_task(
     
     let mut s3_key: Option<String> = None;
     let mut thumbnail_key: Option<String> = None;
+    let mut storyboard_key: Option<String> = None;
     let mut file_bytes: Vec<u8> = Vec::new();
+    // 原文件的 md5 与大小（视频不进内存，只能在这里单独记录）
+    let mut file_md5: Option<String> = None;
+    let mut file_size: usize = 0;
+    // 视频落地的临时文件，TempDir 需与路径同生命周期
+    let mut video_file: Option<(tempfile::TempDir, std::path::PathBuf)> = None;
     // 从 payload 中继承 meta 信息（如 forward_sender_name）
     let mut meta = payload.get("meta").cloned().unwrap_or_else(|| serde_json::json!({}));
+    // 图片 / 视频封面的感知哈希，用于近似重复检测
+    let mut phash: Option<i64> = None;
+    // 网页链接的标题、描述与正文摘录，并入 searchable_text
+    let mut link_text = String::new();
 
     if let Some(fid) = file_id {
         if !fid.is_empty() {
-             let file_info = bot.get_file(FileId(fid.to_string())).await?;
-             let mut dst = Vec::new();
-             bot.download_file(&file_info.path, &mut dst).await?;
-             file_bytes = dst;
-             
-             let ext = file_info.path.split('.').last().unwrap_or("bin");
+             let file_info = match bot.get_file(FileId(fid.to_string())).await.map_err(TaskError::from_telegram) {
+                 Ok(info) => info,
+                 Err(TaskError::FileExpired(msg)) => {
+                     // file_id 失效：从 bot 会话里的原消息重新取一个
+                     let file_unique_id = payload.get("file_unique_id").and_then(|v| v.as_str());
+                     let Some(fresh_id) = refresh_file_id(&bot, bot_chat_id, bot_message_id, file_unique_id).await else {
+                         return Err(TaskError::FileExpired(msg).into());
+                     };
+                     tracing::info!("Re-resolved expired file_id for message {}:{}", bot_chat_id, bot_message_id);
+                     bot.get_file(FileId(fresh_id)).await.map_err(TaskError::from_telegram)?
+                 }
+                 Err(e) => return Err(e.into()),
+             };
+             let ext = file_info.path.split('.').next_back().unwrap_or("bin");
              let key = format!("{}/{}.{}", chrono::Utc::now().format("%Y/%m/%d"), uuid::Uuid::new_v4(), ext);
-             
-             bucket.put_object(&key, &file_bytes).await?;
-             s3_key = Some(key);
+             let original_bucket = original_bucket(state, bucket);
+
+             if item_type == "video" {
+                 // 视频直接流式下载到临时文件再流式上传，避免整段读入内存
+                 let temp_dir = tempfile::tempdir().map_err(TaskError::download)?;
+                 let video_path = temp_dir.path().join(format!("video.{}", ext));
+                 let mut dst = tokio::fs::File::create(&video_path).await.map_err(TaskError::download)?;
+                 bot::download_tg_file(&bot, &file_info.path, &mut dst).await.map_err(TaskError::download)?;
+                 dst.flush().await.map_err(TaskError::download)?;
+                 drop(dst);
+
+                 if state.config.store_originals {
+                     storage::put_file(&original_bucket, &state.config, &key, &video_path).await.map_err(TaskError::storage)?;
+                 }
+
+                 file_md5 = Some(md5_file(&video_path).await.map_err(TaskError::storage)?);
+                 file_size = tokio::fs::metadata(&video_path).await.map_err(TaskError::storage)?.len() as usize;
+                 video_file = Some((temp_dir, video_path));
+             } else {
+                 let mut dst = Vec::new();
+                 bot::download_tg_file(&bot, &file_info.path, &mut dst).await.map_err(TaskError::download)?;
+                 file_bytes = dst;
+
+                 if state.config.store_originals {
+                     storage::put_bytes(&original_bucket, &state.config, &key, &file_bytes).await.map_err(TaskError::storage)?;
+                 }
+                 file_md5 = Some(format!("{:x}", md5::compute(&file_bytes)));
+                 file_size = file_bytes.len();
+             }
+             // 不保存原文件时只用内存 / 临时文件中的数据生成缩略图与向量
+             if state.config.store_originals {
+                 s3_key = Some(key);
+             }
+        }
+    } else if let Some(key) = payload.get("uploaded_s3_key").and_then(|v| v.as_str()) {
+        // 浏览器直传：原文件已在 S3 中，跳过 Telegram 下载，取回来做后续处理
+        if item_type == "video" {
+            // 与 Telegram 视频一样流式写入临时文件，避免整段读入内存
+            let ext = key.rsplit('.').next().filter(|e| !e.contains('/')).unwrap_or("mp4");
+            let temp_dir = tempfile::tempdir().map_err(TaskError::download)?;
+            let video_path = temp_dir.path().join(format!("video.{}", ext));
+            let mut dst = tokio::fs::File::create(&video_path).await.map_err(TaskError::download)?;
+            let status = bucket.get_object_to_writer(key, &mut dst).await.map_err(TaskError::storage)?;
+            if status != 200 {
+                return Err(TaskError::Storage(format!("failed to fetch {}: HTTP {}", key, status)).into());
+            }
+            dst.flush().await.map_err(TaskError::download)?;
+            drop(dst);
+
+            file_md5 = Some(md5_file(&video_path).await.map_err(TaskError::storage)?);
+            file_size = tokio::fs::metadata(&video_path).await.map_err(TaskError::storage)?.len() as usize;
+            video_file = Some((temp_dir, video_path));
+        } else {
+            let resp = bucket.get_object(key).await.map_err(TaskError::storage)?;
+            if resp.status_code() != 200 {
+                return Err(TaskError::Storage(format!("failed to fetch {}: HTTP {}", key, resp.status_code())).into());
+            }
+            file_bytes = resp.bytes().to_vec();
+            file_md5 = Some(format!("{:x}", md5::compute(&file_bytes)));
+            file_size = file_bytes.len();
+        }
+        // 不保存原文件时，直传的对象在入库后删除
+        if state.config.store_originals {
+            s3_key = Some(key.to_string());
+        }
+    } else if let Some(url) = payload.get("link_url").and_then(|v| v.as_str()) {
+        // 网页链接：抓取预览，OpenGraph 配图按图片处理（原图、缩略图、感知哈希、CLIP）
+        let preview = unfurl::fetch_preview(&state.config, url).await?;
+        meta["link"] = preview.to_meta();
+        link_text = unfurl::searchable_text(&meta["link"]);
+        // 同一链接只收录一次：URL 充当文件哈希参与 content_hash
+        file_md5 = Some(format!("{:x}", md5::compute(url.as_bytes())));
+
+        if let Some(image_url) = preview.image_url.as_deref() {
+            match unfurl::fetch_image(&state.config, image_url).await {
+                Some(bytes) => {
+                    if state.config.store_originals {
+                        let ext = media::sniff_image(&bytes).map(|(_, ext)| ext).unwrap_or("bin");
+                        let key = format!("{}/{}.{}", chrono::Utc::now().format("%Y/%m/%d"), uuid::Uuid::new_v4(), ext);
+                        storage::put_bytes(&original_bucket(state, bucket), &state.config, &key, &bytes).await.map_err(TaskError::storage)?;
+                        s3_key = Some(key);
+                    }
+                    file_size = bytes.len();
+                    file_bytes = bytes;
+                }
+                None => tracing::warn!("Failed to fetch link preview image {}", image_url),
+            }
         }
     }
     
-    // 图片处理：宽高提取及缩略图生成
-    if item_type == "image" && !file_bytes.is_empty() {
-        if let Ok(img) = image::load_from_memory(&file_bytes) {
+    // 图片处理：宽高提取及缩略图生成（链接的配图同样处理）
+    if matches!(item_type, "image" | "link") && !file_bytes.is_empty() {
+        let decoded = image::load_from_memory(&file_bytes);
+        if let Err(e) = &decoded {
+            // 损坏的配图不影响链接本身的收录，只有图片 item 按配置失败
+            let reason = format!("cannot decode image: {}", e);
+            tracing::warn!("Unreadable {} media: {}", item_type, reason);
+            if item_type == "image" && state.config.fail_on_unreadable_media {
+                return Err(TaskError::UnreadableMedia(reason).into());
+            }
+            meta["media_error"] = serde_json::json!(reason);
+        }
+        if let Ok(img) = decoded {
             meta["width"] = serde_json::json!(img.width());
             meta["height"] = serde_json::json!(img.height());
-            meta["file_size"] = serde_json::json!(file_bytes.len());
+            meta["file_size"] = serde_json::json!(file_size);
             tracing::info!("Image dimensions: {}x{}", img.width(), img.height());
+            phash = Some(media::dhash(&img) as i64);
+
+            // 小图（如表情大小）直接用原图当缩略图，省去一次放大编码与 S3 上传；
+            // 原文件设置了存储类别时可能无法即时读取，仍然单独生成缩略图
+            let min_dim = state.config.thumb_min_source_dim;
+            let reuse_original = img.width() < min_dim
+                && img.height() < min_dim
+                && s3_key.is_some()
+                && state.config.s3_original_storage_class.is_none();
+            if reuse_original {
+                thumbnail_key = s3_key.clone();
+                meta["thumbnail_is_original"] = serde_json::json!(true);
+                tracing::info!("Image below THUMB_MIN_SOURCE_DIM, reusing original as thumbnail");
+            }
 
             // 生成缩略图 (限制最大宽度或高度为 800px)
-            let thumbnail = img.thumbnail(800, 800);
-            let mut thumb_buf = std::io::Cursor::new(Vec::new());
-            if thumbnail.write_to(&mut thumb_buf, image::ImageFormat::Jpeg).is_ok() {
-                let thumb_data = thumb_buf.into_inner();
+            let source_format = image::guess_format(&file_bytes).ok();
+            let thumb = if reuse_original {
+                None
+            } else {
+                let mut thumb = img.thumbnail(800, 800);
+                if let Some(path) = state.config.thumb_watermark_path.as_deref() {
+                    apply_watermark(&mut thumb, path);
+                }
+                encode_thumbnail(&thumb, source_format, state.config.thumb_jpeg_quality)
+            };
+            if let Some((thumb_data, ext, mime)) = thumb {
                 let thumb_key = format!(
-                    "{}/{}_thumb.jpg",
+                    "{}/{}_thumb.{}",
                     chrono::Utc::now().format("%Y/%m/%d"),
-                    uuid::Uuid::new_v4()
+                    uuid::Uuid::new_v4(),
+                    ext
                 );
-                if bucket.put_object(&thumb_key, &thumb_data).await.is_ok() {
+                if storage::with_object_acl(bucket, &state.config, &thumb_key).put_object_with_content_type(&thumb_key, &thumb_data, mime).await.is_ok() {
                     thumbnail_key = Some(thumb_key);
-                    tracing::info!("Image thumbnail uploaded");
+                    tracing::info!("Image thumbnail uploaded ({})", mime);
                 }
             }
         }
@@ -378,13 +808,11 @@ async fn perform_task(
     
     // 视频处理：ffprobe 提取宽高/时长，ffmpeg 抽封面帧
     let mut cover_frame_bytes: Vec<u8> = Vec::new();
-    if item_type == "video" && !file_bytes.is_empty() {
-        // 写入临时文件供 ffprobe/ffmpeg 处理
-        let temp_dir = tempfile::tempdir()?;
-        let video_path = temp_dir.path().join("video.mp4");
-        tokio::fs::write(&video_path, &file_bytes).await?;
-        
+    // 除封面外额外抽取的帧，与封面一起参与视觉向量
+    let mut sampled_frames: Vec<Vec<u8>> = Vec::new();
+    if let Some((temp_dir, video_path)) = video_file.as_ref() {
         // ffprobe 提取元信息
+        let mut has_video_stream = false;
         let probe_output = Command::new("ffprobe")
             .args([
                 "-v", "quiet",
@@ -392,7 +820,7 @@ async fn perform_task(
                 "-show_format",
                 "-show_streams",
             ])
-            .arg(&video_path)
+            .arg(video_path)
             .output()
             .await;
         
@@ -403,6 +831,7 @@ async fn perform_task(
                     if let Some(streams) = probe_json.get("streams").and_then(|s| s.as_array()) {
                         for stream in streams {
                             if stream.get("codec_type").and_then(|t| t.as_str()) == Some("video") {
+                                has_video_stream = true;
                                 if let Some(w) = stream.get("width").and_then(|v| v.as_i64()) {
                                     meta["width"] = serde_json::json!(w);
                                 }
@@ -422,7 +851,7 @@ async fn perform_task(
                             meta["duration"] = serde_json::json!(duration);
                         }
                     }
-                    meta["file_size"] = serde_json::json!(file_bytes.len());
+                    meta["file_size"] = serde_json::json!(file_size);
                     tracing::info!("Video meta: {:?}", meta);
                 }
             }
@@ -435,7 +864,7 @@ async fn perform_task(
                 "-y",
                 "-i",
             ])
-            .arg(&video_path)
+            .arg(video_path)
             .args([
                 "-ss", "00:00:01",
                 "-vframes", "1",
@@ -447,11 +876,13 @@ async fn perform_task(
             .status()
             .await;
         
+        let mut thumb_ts = 1.0_f64;
         // 如果 1s 位置失败，尝试第一帧
         if ffmpeg_result.is_err() || !cover_path.exists() {
+            thumb_ts = 0.0;
             let _ = Command::new("ffmpeg")
                 .args(["-y", "-i"])
-                .arg(&video_path)
+                .arg(video_path)
                 .args(["-vframes", "1", "-q:v", "2"])
                 .arg(&cover_path)
                 .stderr(Stdio::null())
@@ -460,76 +891,135 @@ async fn perform_task(
                 .await;
         }
         
+        // 黑帧检测：封面接近全黑时改取中点帧，中点帧也是黑的则保留原封面
+        let duration = meta.get("duration").and_then(|d| d.as_f64()).unwrap_or(0.0);
+        if state.config.video_smart_thumb && duration > 0.0 && cover_path.exists() {
+            let cover_is_black = tokio::fs::read(&cover_path).await.is_ok_and(|data| frame_is_near_black(&data));
+            if cover_is_black {
+                let mid_ts = duration / 2.0;
+                let mid_path = temp_dir.path().join("cover_mid.jpg");
+                let mid_ok = extract_frame_at(video_path, mid_ts, &mid_path).await
+                    && tokio::fs::read(&mid_path).await.is_ok_and(|data| !frame_is_near_black(&data));
+                if mid_ok && tokio::fs::rename(&mid_path, &cover_path).await.is_ok() {
+                    tracing::info!("Video cover at {}s is near-black, using midpoint frame at {:.2}s", thumb_ts, mid_ts);
+                    thumb_ts = mid_ts;
+                }
+            }
+        }
+
         if cover_path.exists() {
             if let Ok(cover_data) = tokio::fs::read(&cover_path).await {
+                meta["thumb_ts"] = serde_json::json!(thumb_ts);
                 cover_frame_bytes = cover_data.clone();
+                if let Ok(cover) = image::load_from_memory(&cover_data) {
+                    phash = Some(media::dhash(&cover) as i64);
+                }
                 // 上传封面到 S3
                 let thumb_key = format!("{}/{}_thumb.jpg", chrono::Utc::now().format("%Y/%m/%d"), uuid::Uuid::new_v4());
-                if bucket.put_object(&thumb_key, &cover_data).await.is_ok() {
+                if storage::with_object_acl(bucket, &state.config, &thumb_key).put_object(&thumb_key, &cover_data).await.is_ok() {
                     thumbnail_key = Some(thumb_key);
                     tracing::info!("Video cover frame uploaded");
                 }
             }
         }
+
+        // 没有视频流或一帧都抽不出来：文件损坏或不是视频
+        let media_error = if !has_video_stream {
+            Some("no readable video stream")
+        } else if cover_frame_bytes.is_empty() {
+            Some("cannot extract a video frame")
+        } else {
+            None
+        };
+        if let Some(reason) = media_error {
+            tracing::warn!("Unreadable video media: {}", reason);
+            if state.config.fail_on_unreadable_media {
+                return Err(TaskError::UnreadableMedia(reason.to_string()).into());
+            }
+            meta["media_error"] = serde_json::json!(reason);
+        }
+
+        let extra_frames = state.config.video_embed_frames.saturating_sub(1);
+        if extra_frames > 0 && duration > 0.0 && !cover_frame_bytes.is_empty() {
+            sampled_frames = sample_video_frames(temp_dir.path(), video_path, duration, extra_frames).await;
+            tracing::info!("Sampled {} extra video frames for visual embedding", sampled_frames.len());
+        }
+
+        // 预览拼图：均匀抽取 N 帧平铺成一张图，供前端悬停拖动预览
+        let frames = state.config.storyboard_frames;
+        if frames > 0 && duration >= state.config.storyboard_min_duration {
+            match generate_storyboard(temp_dir.path(), video_path, duration, frames).await {
+                Some((data, storyboard_meta)) => {
+                    let key = format!("{}/{}_storyboard.jpg", chrono::Utc::now().format("%Y/%m/%d"), uuid::Uuid::new_v4());
+                    if storage::with_object_acl(bucket, &state.config, &key).put_object(&key, &data).await.is_ok() {
+                        storyboard_key = Some(key);
+                        meta["storyboard"] = storyboard_meta;
+                        tracing::info!("Video storyboard uploaded");
+                    }
+                }
+                None => tracing::warn!("Failed to generate video storyboard"),
+            }
+        }
     }
     
+    // 文字卡片：文本 item 没有图片，渲染一张卡片当缩略图
+    if item_type == "text" && state.config.text_cards && !content_text.trim().is_empty() {
+        let source_name = text_card_source_name(state, &meta, source_user_id.or(source_chat_id)).await;
+        match generate_text_card(&state.config.text_card_font, &content_text, source_name.as_deref()).await {
+            Some(card) => {
+                let key = format!("{}/{}_card.jpg", chrono::Utc::now().format("%Y/%m/%d"), uuid::Uuid::new_v4());
+                if storage::with_object_acl(bucket, &state.config, &key).put_object(&key, &card).await.is_ok() {
+                    thumbnail_key = Some(key);
+                    meta["thumbnail_is_card"] = serde_json::json!(true);
+                    tracing::info!("Text card uploaded");
+                }
+            }
+            None => tracing::warn!("Failed to render text card"),
+        }
+    }
+
     let mut visual_embedding_str: Option<String> = None;
     let mut text_embedding_str: Option<String> = None;
     let mut searchable_text = content_text.clone();
+    append_reply_context(&meta, &mut searchable_text);
+    if !link_text.is_empty() {
+        if searchable_text.is_empty() {
+            searchable_text = link_text;
+        } else {
+            searchable_text = format!("{}\n{}", searchable_text, link_text);
+        }
+    }
+
+    // 无法解码的图片不送 OCR / CLIP：模型拿到的是坏数据，结果没有意义
+    let media_readable = meta.get("media_error").is_none();
 
     // 1. OCR via VLM for images
+    // meta.ocr 区分「识别过但没有文字」(no_text) 与「图片无法解码，没有识别」(unreadable)
     if item_type == "image" && !file_bytes.is_empty() {
-        let base64_image = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, &file_bytes);
-        let vlm_url = format!("{}/chat/completions", state.config.vlm_api_base);
-        let body = serde_json::json!({
-            "model": state.config.vlm_model,
-            "messages": [{
-                "role": "user",
-                "content": [
-                    {"type": "text", "text": "请识别这张图片中的所有文字内容，只输出识别到的文字，不要任何解释。如果没有文字就输出空。"},
-                    {"type": "image_url", "image_url": {"url": format!("data:image/jpeg;base64,{}", base64_image)}}
-                ]
-            }],
-            "max_tokens": 2048
-        });
-        
-        let res = state.http_client
-            .post(&vlm_url)
-            .header("Authorization", format!("Bearer {}", state.config.vlm_api_key))
-            .header("Content-Type", "application/json")
-            .json(&body)
-            .send()
-            .await?;
-        
-        if res.status().is_success() {
-            let json: serde_json::Value = res.json().await?;
-            if let Some(ocr_text) = json.get("choices")
-                .and_then(|c| c.get(0))
-                .and_then(|c| c.get("message"))
-                .and_then(|m| m.get("content"))
-                .and_then(|c| c.as_str())
-            {
-                let ocr_text = ocr_text.trim();
-                if !ocr_text.is_empty() && ocr_text != "空" {
-                    let log_text: String = ocr_text.chars().take(50).collect();
-                    tracing::info!("OCR extracted: {}...", log_text);
-                    // Append OCR text to searchable_text
-                    if searchable_text.is_empty() {
-                        searchable_text = ocr_text.to_string();
-                    } else {
-                        searchable_text = format!("{}\n{}", searchable_text, ocr_text);
-                    }
-                }
+        if !media_readable {
+            meta["ocr"] = serde_json::json!("unreadable");
+        } else if let Some(ocr_text) = run_ocr(state, &file_bytes).await.map_err(TaskError::vlm)? {
+            meta["ocr"] = serde_json::json!("text");
+            meta["ocr_text"] = serde_json::json!(ocr_text);
+            // Append OCR text to searchable_text
+            if searchable_text.is_empty() {
+                searchable_text = ocr_text;
+            } else {
+                searchable_text = format!("{}\n{}", searchable_text, ocr_text);
             }
         } else {
-            let status = res.status();
-            let text = res.text().await.unwrap_or_default();
-            tracing::warn!("VLM OCR error: {} - {}", status, text);
+            meta["ocr"] = serde_json::json!("no_text");
         }
     }
 
-    // 2. Visual Embedding (CLIP) for images and video cover frames
-    let visual_bytes = if item_type == "image" && !file_bytes.is_empty() {
+    detect_language_into_meta(state, &searchable_text, &mut meta);
+    // 文档只有 caption，不提取文件正文，摘要没有意义
+    if item_type == "text" {
+        summarize_into_meta(state, &content_text, &mut meta).await;
+    }
+
+    // 2. Visual Embedding (CLIP) for images, link preview images and video cover frames
+    let visual_bytes = if matches!(item_type, "image" | "link") && !file_bytes.is_empty() && media_readable {
         Some(file_bytes.clone())
     } else if item_type == "video" && !cover_frame_bytes.is_empty() {
         Some(cover_frame_bytes.clone())
@@ -538,79 +1028,70 @@ async fn perform_task(
     };
     
     if let Some(img_bytes) = visual_bytes {
-        let clip_url = format!("{}/embed", state.config.clip_api_url);
-        let part = reqwest::multipart::Part::bytes(img_bytes)
-           .file_name("image.jpg")
-           .mime_str("image/jpeg")?;
-        let form = reqwest::multipart::Form::new().part("file", part);
-        let res = state.http_client.post(&clip_url).multipart(form).send().await?;
-        if res.status().is_success() {
-             let json: serde_json::Value = res.json().await?;
-             if let Some(arr) = json.get("embedding").and_then(|v| v.as_array()) {
-                 let vec: Vec<f32> = arr.iter().map(|v| v.as_f64().unwrap_or(0.0) as f32).collect();
-                 visual_embedding_str = Some(format!("[{}]", vec.iter().map(|f| f.to_string()).collect::<Vec<_>>().join(",")));
-                 tracing::info!("Generated visual embedding for {}", item_type);
-             }
+        // 视频多帧：封面与抽样帧分别向量化，取归一化均值
+        let vec = if sampled_frames.is_empty() {
+            embed_image(state, img_bytes).await.map_err(TaskError::clip)?
+        } else {
+            let mut vectors = Vec::with_capacity(sampled_frames.len() + 1);
+            for frame in std::iter::once(img_bytes).chain(sampled_frames.drain(..)) {
+                if let Some(vec) = embed_image(state, frame).await.map_err(TaskError::clip)? {
+                    vectors.push(vec);
+                }
+            }
+            meta["visual_frames"] = serde_json::json!(vectors.len());
+            normalized_centroid(&vectors)
+        };
+        if let Some(vec) = vec {
+            visual_embedding_str = Some(to_vector_literal(&vec));
+            tracing::info!("Generated visual embedding for {}", item_type);
         }
     }
 
     // 3. Text Embedding (BGE-M3 via OpenAI-compatible API) for searchable text
     if !searchable_text.is_empty() {
-        let embedding_url = format!("{}/embeddings", state.config.embedding_api_base);
-        let body = serde_json::json!({
-            "model": state.config.embedding_model,
-            "input": searchable_text
-        });
-        let res = state.http_client
-            .post(&embedding_url)
-            .header("Authorization", format!("Bearer {}", state.config.embedding_api_key))
-            .header("Content-Type", "application/json")
-            .json(&body)
-            .send()
-            .await?;
-        
-        if res.status().is_success() {
-            let json: serde_json::Value = res.json().await?;
-            // OpenAI format: {"data": [{"embedding": [...]}]}
-            if let Some(arr) = json.get("data")
-                .and_then(|d| d.get(0))
-                .and_then(|d| d.get("embedding"))
-                .and_then(|e| e.as_array()) 
-            {
-                let vec: Vec<f32> = arr.iter().map(|v| v.as_f64().unwrap_or(0.0) as f32).collect();
-                text_embedding_str = Some(format!("[{}]", vec.iter().map(|f| f.to_string()).collect::<Vec<_>>().join(",")));
-                tracing::info!("Generated text embedding with {} dimensions", vec.len());
-            }
-        } else {
-            let status = res.status();
-            let text = res.text().await.unwrap_or_default();
-            tracing::warn!("Embedding API error: {} - {}", status, text);
+        if let Some(vec) = embed_text(state, &searchable_text).await.map_err(TaskError::embedding)? {
+            text_embedding_str = Some(to_vector_literal(&vec));
+            tracing::info!("Generated text embedding with {} dimensions", vec.len());
         }
     }
     
     // 哈希计算：有文件和文本时是 md5(文件哈希 + 文本哈希)，否则单独计算
-    let content_hash = if !file_bytes.is_empty() && !content_text.is_empty() {
+    let content_hash = if let (Some(file_hash), false) = (file_md5.as_ref(), content_text.is_empty()) {
         // 图+文: md5(md5(file) + md5(text))
-        let file_hash = format!("{:x}", md5::compute(&file_bytes));
         let text_hash = format!("{:x}", md5::compute(content_text.as_bytes()));
         format!("{:x}", md5::compute(format!("{}{}", file_hash, text_hash)))
-    } else if !file_bytes.is_empty() {
+    } else if let Some(file_hash) = file_md5 {
         // 纯文件
-        format!("{:x}", md5::compute(&file_bytes))
+        file_hash
     } else {
         // 纯文本
         format!("{:x}", md5::compute(content_text.as_bytes()))
     };
 
+    // md5 抓不到重新压缩或缩放过的同一张图：按感知哈希标记最接近的已有 item，不阻止收录
+    if let Some(hash) = phash {
+        match db::find_near_duplicates(&state.db, hash, state.config.phash_max_distance, None, 1).await {
+            Ok(found) => {
+                if let Some((dup_id, distance)) = found.first() {
+                    // item 详情原样返回 meta，id 按字符串存，前端不会丢精度
+                    meta["near_duplicate_of"] = serde_json::json!(dup_id.to_string());
+                    tracing::info!("Near-duplicate of item {} (phash distance {})", dup_id, distance);
+                }
+            }
+            Err(e) => tracing::warn!("Failed to check near duplicates: {}", e),
+        }
+    }
+
     let rec = sqlx::query(
         r#"
         INSERT INTO items (
             item_type, content_hash, s3_key, thumbnail_key, 
             content_text, searchable_text, 
             text_embedding, visual_embedding, 
-            meta, tg_chat_id, tg_message_id, tg_user_id, tg_group_id
+            meta, tg_chat_id, tg_message_id, tg_user_id, tg_group_id, storyboard_key, visibility,
+            phash, lang, processed_at
         )
-        VALUES ($1, $2, $3, $4, $5, $6, $7::vector, $8::vector, $9, $10, $11, $12, $13)
+        VALUES ($1, $2, $3, $4, $5, $6, $7::vector, $8::vector, $9, $10, $11, $12, $13, $14, $15, $16, $17, NOW())
         RETURNING id
         "#
     )
@@ -627,10 +1108,23 @@ async fn perform_task(
     .bind(source_message_id)
     .bind(source_user_id)
     .bind(tg_group_id)
+    .bind(storyboard_key)
+    .bind(payload_visibility(&payload))
+    .bind(phash)
+    .bind(meta_lang(&meta))
     .fetch_one(&state.db)
-    .await?;
+    .await
+    .map_err(TaskError::db)?;
 
     let item_id: i64 = rec.get("id");
+    if !state.config.store_originals {
+        if let Some(key) = payload.get("uploaded_s3_key").and_then(|v| v.as_str()) {
+            // 删除失败时对象没有被引用，之后由 /api/v1/admin/gc 回收
+            if let Err(e) = bucket.delete_object(key).await {
+                tracing::warn!("Failed to delete uploaded original {}: {}", key, e);
+            }
+        }
+    }
     let tag_ids = payload_tag_ids(&payload);
     if let Err(e) = apply_tag_ids_to_item(state, item_id, &tag_ids).await {
         tracing::warn!("Failed to apply inherited tags to item {}: {}", item_id, e);
@@ -638,3 +1132,805 @@ async fn perform_task(
 
     Ok(item_id)
 }
+
+/// file_id 失效时重新获取：把 bot 会话中的原消息静默转发给同一会话得到新的 file_id，随即删掉转发出的副本。
+/// 只在私聊中这样做：群组与频道的成员会看到（并被通知）这条重复消息，此时直接返回 None，由调用方提示用户重新转发。
+/// 有 file_unique_id 时按它匹配（照片有多个尺寸）；系统任务或原消息已被删除时返回 None
+async fn refresh_file_id(
+    bot: &Bot,
+    bot_chat_id: i64,
+    bot_message_id: i64,
+    file_unique_id: Option<&str>,
+) -> Option<String> {
+    // 私聊的 chat id 为正数，群组与频道为负数，系统任务为 0
+    if bot_chat_id <= 0 {
+        return None;
+    }
+    let chat_id = ChatId(bot_chat_id);
+    let forwarded = match bot
+        .forward_message(chat_id, chat_id, MessageId(bot_message_id as i32))
+        .disable_notification(true)
+        .await
+    {
+        Ok(m) => m,
+        Err(e) => {
+            tracing::warn!("Failed to re-forward message {}:{} to refresh file_id: {}", bot_chat_id, bot_message_id, e);
+            return None;
+        }
+    };
+    if let Err(e) = bot.delete_message(chat_id, forwarded.id).await {
+        tracing::warn!("Failed to delete re-forwarded message {}: {}", forwarded.id, e);
+    }
+
+    let files: Vec<&teloxide::types::FileMeta> = if let Some(photos) = forwarded.photo() {
+        photos.iter().rev().map(|p| &p.file).collect()
+    } else if let Some(video) = forwarded.video() {
+        vec![&video.file]
+    } else if let Some(animation) = forwarded.animation() {
+        vec![&animation.file]
+    } else if let Some(audio) = forwarded.audio() {
+        vec![&audio.file]
+    } else if let Some(document) = forwarded.document() {
+        vec![&document.file]
+    } else {
+        Vec::new()
+    };
+
+    let file = match file_unique_id {
+        Some(uid) => files.into_iter().find(|f| f.unique_id.0 == uid),
+        None => files.into_iter().next(),
+    };
+    file.map(|f| f.id.0.clone())
+}
+
+/// 预览拼图中每一帧的宽度（像素）
+const STORYBOARD_TILE_WIDTH: u32 = 160;
+
+/// 平均亮度低于该值（0-255）的帧视为黑帧
+const BLACK_FRAME_MAX_LUMA: f32 = 16.0;
+
+/// 缩小成灰度图后按平均亮度判断是否接近全黑；解码失败时不算黑帧
+fn frame_is_near_black(image_bytes: &[u8]) -> bool {
+    let Ok(img) = image::load_from_memory(image_bytes) else {
+        return false;
+    };
+    let luma = img.thumbnail(64, 64).to_luma8();
+    let pixels = luma.pixels().len().max(1) as f32;
+    let mean = luma.pixels().map(|p| p[0] as f32).sum::<f32>() / pixels;
+    mean < BLACK_FRAME_MAX_LUMA
+}
+
+/// 用 ffmpeg 抽取 `ts` 秒处的一帧（输入端 seek，长视频也不用从头解码）
+async fn extract_frame_at(video_path: &std::path::Path, ts: f64, output: &std::path::Path) -> bool {
+    let status = Command::new("ffmpeg")
+        .args(["-y", "-ss", &format!("{:.3}", ts), "-i"])
+        .arg(video_path)
+        .args(["-vframes", "1", "-q:v", "2"])
+        .arg(output)
+        .stderr(Stdio::null())
+        .stdout(Stdio::null())
+        .status()
+        .await;
+    status.is_ok_and(|s| s.success()) && output.exists()
+}
+
+/// 在时长内均匀抽取 `count` 帧（取各段中点，避开片头片尾），返回成功抽出的帧
+async fn sample_video_frames(
+    work_dir: &std::path::Path,
+    video_path: &std::path::Path,
+    duration: f64,
+    count: u32,
+) -> Vec<Vec<u8>> {
+    let mut frames = Vec::with_capacity(count as usize);
+    for i in 0..count {
+        let ts = duration * (i as f64 + 0.5) / count as f64;
+        let path = work_dir.join(format!("sample_{}.jpg", i));
+        if extract_frame_at(video_path, ts, &path).await {
+            if let Ok(data) = tokio::fs::read(&path).await {
+                frames.push(data);
+            }
+        }
+    }
+    frames
+}
+
+/// 用 ffmpeg 均匀抽取 `frames` 帧并平铺成一张 JPEG，返回图片数据与写入 meta.storyboard 的描述
+async fn generate_storyboard(
+    work_dir: &std::path::Path,
+    video_path: &std::path::Path,
+    duration: f64,
+    frames: u32,
+) -> Option<(Vec<u8>, serde_json::Value)> {
+    let columns = (frames as f64).sqrt().ceil() as u32;
+    let rows = frames.div_ceil(columns);
+    let interval = duration / frames as f64;
+    let output_path = work_dir.join("storyboard.jpg");
+
+    let filter = format!(
+        "fps=1/{:.3},scale={}:-2,tile={}x{}",
+        interval, STORYBOARD_TILE_WIDTH, columns, rows
+    );
+    let status = Command::new("ffmpeg")
+        .args(["-y", "-i"])
+        .arg(video_path)
+        .args(["-vf", &filter, "-frames:v", "1", "-q:v", "5"])
+        .arg(&output_path)
+        .stderr(Stdio::null())
+        .stdout(Stdio::null())
+        .status()
+        .await
+        .ok()?;
+    if !status.success() {
+        return None;
+    }
+
+    let data = tokio::fs::read(&output_path).await.ok()?;
+    let storyboard_meta = serde_json::json!({
+        "frames": frames,
+        "interval": interval,
+        "columns": columns,
+        "rows": rows,
+        "tile_width": STORYBOARD_TILE_WIDTH,
+    });
+    Some((data, storyboard_meta))
+}
+
+/// 文字卡片的尺寸与排版（像素）；正文每行按「半角宽度」计，全角字符占 2
+const TEXT_CARD_SIZE: (u32, u32) = (600, 450);
+const TEXT_CARD_FONT_SIZE: u32 = 30;
+const TEXT_CARD_LINE_UNITS: usize = 32;
+const TEXT_CARD_MAX_LINES: usize = 8;
+
+/// 卡片底部显示的来源：隐藏账号的转发只有 forward_sender_name，否则取来源实体的名字
+async fn text_card_source_name(state: &AppState, meta: &serde_json::Value, entity_id: Option<i64>) -> Option<String> {
+    if let Some(name) = meta.get("forward_sender_name").and_then(|v| v.as_str()) {
+        return Some(name.to_string());
+    }
+    sqlx::query_scalar::<_, String>("SELECT name FROM entities WHERE id = $1")
+        .bind(entity_id?)
+        .fetch_optional(&state.db)
+        .await
+        .ok()
+        .flatten()
+}
+
+/// 按显示宽度折行并截断到 TEXT_CARD_MAX_LINES 行（draw_text 不会自动换行），被截断时以省略号结尾
+fn wrap_card_text(text: &str) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut truncated = false;
+    'outer: for paragraph in text.lines().map(str::trim_end) {
+        if paragraph.is_empty() {
+            // 连续空行只保留一行
+            if lines.last().is_some_and(|l| !l.is_empty()) {
+                lines.push(String::new());
+            }
+            continue;
+        }
+        let mut line = String::new();
+        let mut width = 0;
+        for c in paragraph.chars() {
+            let w = if c > '\u{2E7F}' { 2 } else { 1 };
+            if width + w > TEXT_CARD_LINE_UNITS {
+                lines.push(std::mem::take(&mut line));
+                width = 0;
+            }
+            if lines.len() >= TEXT_CARD_MAX_LINES {
+                truncated = true;
+                break 'outer;
+            }
+            line.push(c);
+            width += w;
+        }
+        lines.push(line);
+    }
+    while lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop();
+    }
+    if lines.len() > TEXT_CARD_MAX_LINES {
+        lines.truncate(TEXT_CARD_MAX_LINES);
+        truncated = true;
+    }
+    if truncated {
+        if let Some(last) = lines.last_mut() {
+            last.pop();
+            last.push('…');
+        }
+    }
+    lines.join("\n")
+}
+
+/// 卡片配色：背景、正文、来源名
+const TEXT_CARD_BACKGROUND: image::Rgb<u8> = image::Rgb([0xF5, 0xF1, 0xE8]);
+const TEXT_CARD_TEXT_COLOR: image::Rgb<u8> = image::Rgb([0x33, 0x33, 0x33]);
+const TEXT_CARD_SOURCE_COLOR: image::Rgb<u8> = image::Rgb([0x88, 0x88, 0x88]);
+const TEXT_CARD_MARGIN: i32 = 40;
+const TEXT_CARD_LINE_SPACING: i32 = 12;
+const TEXT_CARD_SOURCE_FONT_SIZE: f32 = 22.0;
+
+/// 字体文件（CJK 字体通常有十几 MB）只在第一次渲染时读取；读取失败后不再重试
+static TEXT_CARD_FONT_CACHE: std::sync::OnceLock<Option<ab_glyph::FontArc>> = std::sync::OnceLock::new();
+
+fn load_text_card_font(path: &str) -> Option<ab_glyph::FontArc> {
+    TEXT_CARD_FONT_CACHE
+        .get_or_init(|| {
+            let data = match std::fs::read(path) {
+                Ok(data) => data,
+                Err(e) => {
+                    tracing::warn!("Failed to read text card font {}: {}", path, e);
+                    return None;
+                }
+            };
+            // .ttc 字体集合取第一个字体
+            match ab_glyph::FontVec::try_from_vec_and_index(data, 0) {
+                Ok(font) => Some(ab_glyph::FontArc::new(font)),
+                Err(e) => {
+                    tracing::warn!("Failed to parse text card font {}: {}", path, e);
+                    None
+                }
+            }
+        })
+        .clone()
+}
+
+/// 在进程内渲染文字卡片（纯色背景 + 正文 + 底部来源名），返回 JPEG 数据
+async fn generate_text_card(font: &str, text: &str, source_name: Option<&str>) -> Option<Vec<u8>> {
+    let font_path = font.to_string();
+    let text = wrap_card_text(text);
+    let source_name = source_name
+        .map(str::trim)
+        .filter(|n| !n.is_empty())
+        .map(|n| format!("— {}", n.chars().take(TEXT_CARD_LINE_UNITS).collect::<String>()));
+    // 读字体与光栅化都是 CPU / 阻塞操作，不占用 async 线程
+    tokio::task::spawn_blocking(move || {
+        let font = load_text_card_font(&font_path)?;
+        render_text_card(&font, &text, source_name.as_deref())
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+fn render_text_card(font: &ab_glyph::FontArc, text: &str, source_line: Option<&str>) -> Option<Vec<u8>> {
+    let (width, height) = TEXT_CARD_SIZE;
+    let mut card = image::RgbImage::from_pixel(width, height, TEXT_CARD_BACKGROUND);
+
+    let line_height = TEXT_CARD_FONT_SIZE as i32 + TEXT_CARD_LINE_SPACING;
+    for (i, line) in text.lines().enumerate() {
+        let y = TEXT_CARD_MARGIN + i as i32 * line_height;
+        imageproc::drawing::draw_text_mut(&mut card, TEXT_CARD_TEXT_COLOR, TEXT_CARD_MARGIN, y, TEXT_CARD_FONT_SIZE as f32, font, line);
+    }
+    if let Some(source_line) = source_line {
+        let y = height as i32 - 60;
+        imageproc::drawing::draw_text_mut(&mut card, TEXT_CARD_SOURCE_COLOR, TEXT_CARD_MARGIN, y, TEXT_CARD_SOURCE_FONT_SIZE, font, source_line);
+    }
+
+    let mut out = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, 85)
+        .encode_image(&card)
+        .ok()?;
+    Some(out)
+}
+
+/// 把被回复消息的内容（meta.reply_context）拼进 searchable_text，让回复能按上下文被搜到
+fn append_reply_context(meta: &serde_json::Value, searchable_text: &mut String) {
+    let Some(ctx) = meta.get("reply_context").and_then(|v| v.as_str()) else { return; };
+    if ctx.trim().is_empty() {
+        return;
+    }
+    if searchable_text.is_empty() {
+        *searchable_text = ctx.to_string();
+    } else {
+        *searchable_text = format!("{}\n{}", searchable_text, ctx);
+    }
+}
+
+/// 语言检测（可选）：结果写入 meta.lang，入库时同步到 items.lang 供列表/搜索按语言过滤
+fn detect_language_into_meta(state: &AppState, text: &str, meta: &mut serde_json::Value) {
+    if !state.config.detect_language || text.is_empty() {
+        return;
+    }
+    if let Some(info) = whatlang::detect(text) {
+        meta["lang"] = serde_json::json!({
+            "code": info.lang().code(),
+            "confidence": info.confidence(),
+        });
+        tracing::info!("Detected language: {} ({:.2})", info.lang().code(), info.confidence());
+    }
+}
+
+/// meta.lang.code，同步写入 items.lang 列
+fn meta_lang(meta: &serde_json::Value) -> Option<&str> {
+    meta.get("lang").and_then(|l| l.get("code")).and_then(|c| c.as_str())
+}
+
+/// 水印距缩略图边缘的像素
+const WATERMARK_MARGIN: u32 = 8;
+
+/// 把水印叠加到缩略图右下角；水印读取失败或缩略图放不下水印时保持原样
+fn apply_watermark(thumb: &mut image::DynamicImage, path: &str) {
+    let watermark = match image::open(path) {
+        Ok(img) => img.to_rgba8(),
+        Err(e) => {
+            tracing::warn!("Skipping thumbnail watermark {}: {}", path, e);
+            return;
+        }
+    };
+    let (width, height) = (thumb.width(), thumb.height());
+    if watermark.width() + WATERMARK_MARGIN > width || watermark.height() + WATERMARK_MARGIN > height {
+        tracing::debug!("Thumbnail {}x{} is smaller than the watermark, skipping", width, height);
+        return;
+    }
+    let mut canvas = thumb.to_rgba8();
+    image::imageops::overlay(
+        &mut canvas,
+        &watermark,
+        (width - watermark.width() - WATERMARK_MARGIN) as i64,
+        (height - watermark.height() - WATERMARK_MARGIN) as i64,
+    );
+    *thumb = image::DynamicImage::ImageRgba8(canvas);
+}
+
+/// 缩略图编码，返回 (数据, 扩展名, MIME)。
+/// 含透明像素的图片保留透明度：WebP 来源编码为 WebP（image 只支持无损），其余编码为 PNG；
+/// 不透明的图片（包括 alpha 通道全为不透明的 PNG）编码为 JPEG 以减小体积
+fn encode_thumbnail(
+    thumb: &image::DynamicImage,
+    source_format: Option<image::ImageFormat>,
+    jpeg_quality: u8,
+) -> Option<(Vec<u8>, &'static str, &'static str)> {
+    let has_transparency = thumb.color().has_alpha() && thumb.to_rgba8().pixels().any(|p| p[3] < 255);
+    let mut buf = std::io::Cursor::new(Vec::new());
+    if has_transparency {
+        let rgba = image::DynamicImage::ImageRgba8(thumb.to_rgba8());
+        if source_format == Some(image::ImageFormat::WebP) {
+            let encoder = image::codecs::webp::WebPEncoder::new_lossless(&mut buf);
+            rgba.write_with_encoder(encoder).ok()?;
+            return Some((buf.into_inner(), "webp", "image/webp"));
+        }
+        rgba.write_to(&mut buf, image::ImageFormat::Png).ok()?;
+        return Some((buf.into_inner(), "png", "image/png"));
+    }
+    // JPEG 不支持 alpha 通道，先转成 RGB
+    let rgb = image::DynamicImage::ImageRgb8(thumb.to_rgb8());
+    let encoder = JpegEncoder::new_with_quality(&mut buf, jpeg_quality);
+    rgb.write_with_encoder(encoder).ok()?;
+    Some((buf.into_inner(), "jpg", "image/jpeg"))
+}
+
+/// 摘要输入的最大字符数，超出部分截断
+const SUMMARY_MAX_INPUT_CHARS: usize = 8000;
+
+/// 长文本生成 meta.summary；尽力而为，接口失败只记录日志，不影响入库
+async fn summarize_into_meta(state: &AppState, text: &str, meta: &mut serde_json::Value) {
+    let Some(summarizer) = state.summarizer.as_ref() else {
+        return;
+    };
+    let text = text.trim();
+    if text.chars().count() < state.config.summary_min_chars {
+        return;
+    }
+    let input: String = text.chars().take(SUMMARY_MAX_INPUT_CHARS).collect();
+    // 默认与 OCR 共用 VLM 服务，同样受 VLM_CONCURRENCY 限制
+    let result = match state.vlm_permits.acquire().await {
+        Ok(_permit) => summarizer.summarize(&input).await,
+        Err(e) => Err(e.into()),
+    };
+    match result {
+        Ok(Some(summary)) if !summary.trim().is_empty() => {
+            meta["summary"] = serde_json::json!(summary.trim());
+            tracing::info!("Summary generated ({} chars)", summary.trim().chars().count());
+        }
+        Ok(_) => {}
+        Err(e) => tracing::warn!("Summarization failed: {}", e),
+    }
+}
+
+/// 重建任务 payload 中的选项
+#[derive(Debug, Default, Clone, Copy)]
+struct ReprocessOptions {
+    /// 即使文本未变也重新生成文本向量，用于更换 embedding 模型或前缀之后
+    force: bool,
+    /// 只重建文本部分（如修改备注后）：沿用 meta.ocr_text，不重新 OCR，也不重新生成视觉向量与感知哈希
+    text_only: bool,
+}
+
+impl ReprocessOptions {
+    fn from_payload(payload: &serde_json::Value) -> Self {
+        let flag = |key: &str| payload.get(key).and_then(|v| v.as_bool()).unwrap_or(false);
+        Self { force: flag("force"), text_only: flag("text_only") }
+    }
+}
+
+/// 重建索引：基于 S3 中已存的原图 / 视频封面重新跑 OCR 与向量，
+/// 更新 searchable_text 与向量列（外部服务失败时保留旧向量）
+async fn reprocess_item(state: &AppState, bucket: &Bucket, item_id: i64, options: ReprocessOptions) -> anyhow::Result<i64> {
+    let row = sqlx::query(
+        "SELECT item_type, s3_key, thumbnail_key, content_text, searchable_text, meta, note, text_embedding IS NOT NULL AS has_text_embedding FROM items WHERE id = $1",
+    )
+        .bind(item_id)
+        .fetch_optional(&state.db)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Item {} no longer exists", item_id))?;
+
+    let item_type: String = row.get("item_type");
+    let s3_key: Option<String> = row.try_get("s3_key").ok().flatten();
+    let thumbnail_key: Option<String> = row.try_get("thumbnail_key").ok().flatten();
+    let content_text: Option<String> = row.try_get("content_text").ok().flatten();
+    let note: Option<String> = row.try_get("note").ok().flatten();
+    let old_searchable_text: Option<String> = row.try_get("searchable_text").ok().flatten();
+    let has_text_embedding: bool = row.try_get("has_text_embedding").unwrap_or(false);
+    let mut meta: serde_json::Value = row.try_get("meta").ok().flatten().unwrap_or_else(|| serde_json::json!({}));
+
+    // 早期收录的图片没有保存 OCR 文本，只能完整重建
+    let saved_ocr_text = meta.get("ocr_text").and_then(|v| v.as_str()).map(str::to_string);
+    let text_only = options.text_only
+        && !(item_type == "image" && meta.get("ocr").and_then(|v| v.as_str()) == Some("text") && saved_ocr_text.is_none());
+
+    // 视觉输入：图片与链接配图取原图（未保存原文件时退回缩略图），视频取封面帧。
+    // 多帧均值的视频向量无法只靠封面重建，保留原有向量
+    let multi_frame_video = meta.get("visual_frames").and_then(|v| v.as_u64()).is_some_and(|n| n > 1);
+    let visual_key = match item_type.as_str() {
+        _ if text_only => None,
+        "image" | "link" => s3_key.as_ref().or(thumbnail_key.as_ref()),
+        "video" if !multi_frame_video => thumbnail_key.as_ref(),
+        _ => None,
+    };
+    let visual_bytes = match visual_key {
+        Some(key) => {
+            let resp = bucket.get_object(key).await.map_err(TaskError::storage)?;
+            if resp.status_code() != 200 {
+                return Err(TaskError::Storage(format!("failed to fetch {}: HTTP {}", key, resp.status_code())).into());
+            }
+            Some(resp.bytes().to_vec())
+        }
+        None => None,
+    };
+    // 无法解码时不送 OCR / CLIP，记入 meta.media_error；能解码时清掉之前的记录
+    let decoded = visual_bytes.as_ref().map(|bytes| image::load_from_memory(bytes));
+    let visual_bytes = match &decoded {
+        Some(Err(e)) => {
+            tracing::warn!("Item {} media is unreadable: {}", item_id, e);
+            meta["media_error"] = serde_json::json!(format!("cannot decode image: {}", e));
+            None
+        }
+        Some(Ok(_)) => {
+            if let Some(obj) = meta.as_object_mut() {
+                obj.remove("media_error");
+            }
+            visual_bytes
+        }
+        None => None,
+    };
+
+    let mut searchable_text = content_text.clone().unwrap_or_default();
+    append_reply_context(&meta, &mut searchable_text);
+    if let Some(link) = meta.get("link") {
+        let link_text = unfurl::searchable_text(link);
+        if searchable_text.is_empty() {
+            searchable_text = link_text;
+        } else if !link_text.is_empty() {
+            searchable_text = format!("{}\n{}", searchable_text, link_text);
+        }
+    }
+    if item_type == "image" && text_only {
+        if let Some(ocr_text) = saved_ocr_text.filter(|t| !t.is_empty()) {
+            if searchable_text.is_empty() {
+                searchable_text = ocr_text;
+            } else {
+                searchable_text = format!("{}\n{}", searchable_text, ocr_text);
+            }
+        }
+    } else if item_type == "image" {
+        if let Some(bytes) = visual_bytes.as_ref() {
+            if let Some(ocr_text) = run_ocr(state, bytes).await.map_err(TaskError::vlm)? {
+                meta["ocr"] = serde_json::json!("text");
+                meta["ocr_text"] = serde_json::json!(ocr_text);
+                if searchable_text.is_empty() {
+                    searchable_text = ocr_text;
+                } else {
+                    searchable_text = format!("{}\n{}", searchable_text, ocr_text);
+                }
+            } else {
+                meta["ocr"] = serde_json::json!("no_text");
+                if let Some(obj) = meta.as_object_mut() {
+                    obj.remove("ocr_text");
+                }
+            }
+        } else if meta.get("media_error").is_some() {
+            meta["ocr"] = serde_json::json!("unreadable");
+            if let Some(obj) = meta.as_object_mut() {
+                obj.remove("ocr_text");
+            }
+        }
+    }
+
+    // 备注在收录之后才会有，只在重建索引时并入
+    if state.config.note_searchable {
+        if let Some(note) = note.as_deref().filter(|n| !n.is_empty()) {
+            if searchable_text.is_empty() {
+                searchable_text = note.to_string();
+            } else {
+                searchable_text = format!("{}\n{}", searchable_text, note);
+            }
+        }
+    }
+
+    detect_language_into_meta(state, &searchable_text, &mut meta);
+    // 已有摘要时不重复调用（文本本身不会变）；文档只有 caption，不做摘要
+    if item_type == "text" && meta.get("summary").is_none() {
+        summarize_into_meta(state, content_text.as_deref().unwrap_or_default(), &mut meta).await;
+    }
+
+    // 顺带为旧数据补上感知哈希
+    let phash = match &decoded {
+        Some(Ok(img)) => Some(media::dhash(img) as i64),
+        _ => None,
+    };
+
+    let visual_embedding_str = match visual_bytes {
+        Some(bytes) => embed_image(state, bytes).await.map_err(TaskError::clip)?.map(|v| to_vector_literal(&v)),
+        None => None,
+    };
+    // 文本没变且已有向量时不再调用 embedding 接口（COALESCE 会保留旧向量），除非要求强制重建
+    let text_unchanged = !options.force && has_text_embedding && old_searchable_text.as_deref() == Some(searchable_text.as_str());
+    let text_embedding_str = if searchable_text.is_empty() {
+        None
+    } else if text_unchanged {
+        tracing::info!("Item {} searchable_text unchanged, skipping text embedding", item_id);
+        None
+    } else {
+        embed_text(state, &searchable_text).await.map_err(TaskError::embedding)?.map(|v| to_vector_literal(&v))
+    };
+
+    // 处理期间备注又被修改时不写文本列：修改备注已入队的新任务会读到新备注再写
+    sqlx::query(
+        r#"
+        UPDATE items
+        SET searchable_text = CASE WHEN note IS NOT DISTINCT FROM $8 THEN $1 ELSE searchable_text END,
+            text_embedding = CASE WHEN note IS NOT DISTINCT FROM $8 THEN COALESCE($2::vector, text_embedding) ELSE text_embedding END,
+            visual_embedding = COALESCE($3::vector, visual_embedding),
+            meta = $4,
+            phash = COALESCE($6, phash),
+            lang = $7,
+            processed_at = NOW()
+        WHERE id = $5
+        "#,
+    )
+    .bind(&searchable_text)
+    .bind(text_embedding_str)
+    .bind(visual_embedding_str)
+    .bind(&meta)
+    .bind(item_id)
+    .bind(phash)
+    .bind(meta_lang(&meta))
+    .bind(note.as_deref())
+    .execute(&state.db)
+    .await?;
+
+    tracing::info!("Reprocessed item {}", item_id);
+    Ok(item_id)
+}
+
+/// 向量转成 pgvector 的文本字面量，如 "[0.1,0.2]"
+fn to_vector_literal(vec: &[f32]) -> String {
+    format!("[{}]", vec.iter().map(|f| f.to_string()).collect::<Vec<_>>().join(","))
+}
+
+/// 粗略估计图片含文字的可能性（0~1）：缩小成灰度图后统计强水平边缘的像素占比。
+/// 文字是密集的高对比笔画，风景、人像等照片的边缘通常更稀疏、更柔和
+fn text_likelihood(image_bytes: &[u8]) -> Option<f32> {
+    let img = image::load_from_memory(image_bytes).ok()?;
+    let gray = img.thumbnail(256, 256).to_luma8();
+    let (width, height) = gray.dimensions();
+    if width < 2 || height == 0 {
+        return None;
+    }
+
+    let mut edges = 0_u32;
+    for y in 0..height {
+        for x in 0..width - 1 {
+            let a = gray.get_pixel(x, y).0[0] as i16;
+            let b = gray.get_pixel(x + 1, y).0[0] as i16;
+            if (a - b).abs() > 64 {
+                edges += 1;
+            }
+        }
+    }
+    let density = edges as f32 / ((width - 1) * height) as f32;
+    // 边缘占比约 10% 以上基本就是文字截图，按此归一化
+    Some((density / 0.1).min(1.0))
+}
+
+/// VLM OCR：返回识别到的文字；没有文字或接口报错时返回 None
+async fn run_ocr(state: &AppState, image_bytes: &[u8]) -> anyhow::Result<Option<String>> {
+    let min_likelihood = state.config.ocr_min_text_likelihood;
+    if min_likelihood > 0.0 {
+        if let Some(likelihood) = text_likelihood(image_bytes) {
+            if likelihood < min_likelihood {
+                tracing::info!("OCR skipped: text likelihood {:.3} < {:.3}", likelihood, min_likelihood);
+                return Ok(None);
+            }
+        }
+    }
+
+    let ocr_result = {
+        let _permit = state.vlm_permits.acquire().await?;
+        state.ocr.recognize(image_bytes).await?
+    };
+    let Some(ocr_text) = ocr_result else {
+        return Ok(None);
+    };
+
+    let ocr_text = ocr_text.trim();
+    if ocr_text.is_empty() || ocr_text == "空" {
+        return Ok(None);
+    }
+    let log_text: String = ocr_text.chars().take(50).collect();
+    tracing::info!("OCR extracted: {}...", log_text);
+    Ok(Some(ocr_text.to_string()))
+}
+
+/// CLIP 图像向量
+async fn embed_image(state: &AppState, image_bytes: Vec<u8>) -> anyhow::Result<Option<Vec<f32>>> {
+    let _permit = state.clip_permits.acquire().await?;
+    state.visual_embedder.embed_image(image_bytes).await
+}
+
+/// 分块向量化时最多处理的块数，超出部分丢弃
+const MAX_EMBED_CHUNKS: usize = 16;
+
+/// 入库文本向量（BGE-M3）。超过 MAX_EMBED_CHARS 的文本默认截断，避免超出模型上下文导致整个任务失败；
+/// 开启 EMBED_CHUNKING 时按长度分块分别向量化，再取归一化后的均值
+async fn embed_text(state: &AppState, text: &str) -> anyhow::Result<Option<Vec<f32>>> {
+    let max_chars = state.config.max_embed_chars;
+    let char_count = text.chars().count();
+    if max_chars == 0 || char_count <= max_chars {
+        return embed_passage(state, text).await;
+    }
+
+    if !state.config.embed_chunking {
+        tracing::info!("Embedding input truncated: {} -> {} chars", char_count, max_chars);
+        let truncated: String = text.chars().take(max_chars).collect();
+        return embed_passage(state, &truncated).await;
+    }
+
+    let chars: Vec<char> = text.chars().collect();
+    let chunks: Vec<String> = chars
+        .chunks(max_chars)
+        .take(MAX_EMBED_CHUNKS)
+        .map(|chunk| chunk.iter().collect())
+        .collect();
+    if char_count > max_chars * MAX_EMBED_CHUNKS {
+        tracing::info!("Embedding input truncated to {} chunks of {} chars", MAX_EMBED_CHUNKS, max_chars);
+    }
+    let mut vectors = Vec::with_capacity(chunks.len());
+    for chunk in &chunks {
+        if let Some(vec) = embed_passage(state, chunk).await? {
+            vectors.push(vec);
+        }
+    }
+    Ok(normalized_centroid(&vectors))
+}
+
+/// 单次文本向量化请求，带 passage 前缀
+async fn embed_passage(state: &AppState, text: &str) -> anyhow::Result<Option<Vec<f32>>> {
+    let input = format!("{}{}", state.config.embedding_passage_prefix, text);
+    let _permit = state.embedding_permits.acquire().await?;
+    state.text_embedder.embed(&input).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 细节足够多的合成图，JPEG 质量对体积的影响才明显
+    fn textured() -> image::DynamicImage {
+        let rgb = image::RgbImage::from_fn(256, 256, |x, y| {
+            let v = ((x * 7) ^ (y * 13)) as u8;
+            image::Rgb([v, v.wrapping_mul(3), 255 - v])
+        });
+        image::DynamicImage::ImageRgb8(rgb)
+    }
+
+    #[test]
+    fn lower_jpeg_quality_gives_smaller_thumbnails() {
+        let img = textured();
+        let (high, ext, mime) = encode_thumbnail(&img, Some(image::ImageFormat::Jpeg), 95).unwrap();
+        let (low, _, _) = encode_thumbnail(&img, Some(image::ImageFormat::Jpeg), 30).unwrap();
+        assert_eq!((ext, mime), ("jpg", "image/jpeg"));
+        assert!(low.len() < high.len(), "q30 {} bytes, q95 {} bytes", low.len(), high.len());
+    }
+
+    #[test]
+    fn reprocess_options_default_to_a_full_reindex() {
+        let note_edit = ReprocessOptions::from_payload(&serde_json::json!({ "reprocess_item_id": 1, "text_only": true }));
+        assert!(note_edit.text_only && !note_edit.force);
+        let reindex = ReprocessOptions::from_payload(&serde_json::json!({ "reprocess_item_id": 1 }));
+        assert!(!reindex.text_only && !reindex.force);
+    }
+
+    /// claim_tasks 领取的是整张表里最早的 pending 任务，领取类测试需要串行执行
+    static TASK_QUEUE: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    /// 插入 n 个 pending 任务，用随机的 bot_chat_id 区分各测试的数据。
+    /// created_at 设在 1970 年，排在库中其它 pending 任务之前，按 n 的整数倍领取时只会领到这些任务
+    async fn insert_pending_tasks(pool: &sqlx::PgPool, n: i64) -> i64 {
+        let chat_id = (uuid::Uuid::new_v4().as_u128() >> 65) as i64;
+        sqlx::query(
+            r#"
+            INSERT INTO tasks (bot_chat_id, bot_message_id, created_at)
+            SELECT $1, g, 'epoch'::timestamptz + make_interval(secs => g)
+            FROM generate_series(1, $2) AS g
+            "#,
+        )
+        .bind(chat_id)
+        .bind(n)
+        .execute(pool)
+        .await
+        .unwrap();
+        chat_id
+    }
+
+    async fn task_statuses(pool: &sqlx::PgPool, chat_id: i64) -> Vec<(i64, String)> {
+        sqlx::query_as("SELECT id, status FROM tasks WHERE bot_chat_id = $1 ORDER BY id")
+            .bind(chat_id)
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn claim_marks_only_the_claimed_batch_processing() {
+        let pool = db::test_pool().await;
+        let _queue = TASK_QUEUE.lock().await;
+        let chat_id = insert_pending_tasks(&pool, 5).await;
+
+        let claimed = claim_tasks(&pool, 3).await.unwrap();
+        let statuses = task_statuses(&pool, chat_id).await;
+        sqlx::query("DELETE FROM tasks WHERE bot_chat_id = $1").bind(chat_id).execute(&pool).await.unwrap();
+
+        // 领到的是本测试插入的最早 3 个任务，其余 2 个保持 pending
+        let claimed_ids: Vec<i64> = claimed.iter().map(|t| t.id).collect();
+        let own_ids: Vec<i64> = statuses.iter().map(|(id, _)| *id).collect();
+        assert_eq!(claimed_ids, own_ids[..3]);
+        let status_of = |id: &i64| statuses.iter().find(|(sid, _)| sid == id).map(|(_, s)| s.as_str());
+        assert!(own_ids[..3].iter().all(|id| status_of(id) == Some("processing")), "{:?}", statuses);
+        assert!(own_ids[3..].iter().all(|id| status_of(id) == Some("pending")), "{:?}", statuses);
+    }
+
+    /// 领取吞吐的基准：逐个领取与按批领取同样数量的任务，批量应明显更快。
+    /// 计时结果受机器影响，单独运行：TEST_DATABASE_URL=... cargo test claim_throughput -- --ignored
+    #[tokio::test]
+    #[ignore = "benchmark, needs TEST_DATABASE_URL"]
+    async fn batch_claim_throughput() {
+        let pool = db::test_pool().await;
+        let _queue = TASK_QUEUE.lock().await;
+        // 32 的整数倍：每一批都只领到本测试插入的任务
+        const TASKS: i64 = 2048;
+
+        let mut elapsed = Vec::new();
+        for batch_size in [1, 32] {
+            let chat_id = insert_pending_tasks(&pool, TASKS).await;
+            let started = std::time::Instant::now();
+            let mut claimed = 0;
+            while claimed < TASKS {
+                let batch = claim_tasks(&pool, batch_size).await.unwrap();
+                assert!(!batch.is_empty() && batch.iter().all(|t| t.bot_chat_id == chat_id));
+                claimed += batch.len() as i64;
+            }
+            elapsed.push(started.elapsed());
+            assert_eq!(claimed, TASKS);
+            sqlx::query("DELETE FROM tasks WHERE bot_chat_id = $1").bind(chat_id).execute(&pool).await.unwrap();
+        }
+
+        let (single, batched) = (elapsed[0], elapsed[1]);
+        assert!(
+            batched * 2 < single,
+            "claiming {} tasks: one at a time {:?}, 32 per transaction {:?}",
+            TASKS,
+            single,
+            batched
+        );
+    }
+}
@@ -1,5 +1,5 @@
 export interface Item {
-  id: number;
+  id: string; // BIGINT as string for JS safety
   // Client-only stable key for UI lists (e.g. Random page may contain duplicate ids).
   // Not persisted; may be undefined for most items.
   clientKey?: string;
@@ -7,14 +7,56 @@ export interface Item {
   content: string | null;
   s3_url: string | null;
   thumbnail_url?: string | null;
+  storyboard_url?: string | null;
+  storyboard?: Storyboard | null;
   created_at: string | null;
+  processed_at?: string | null;
   width?: number;
   height?: number;
   source_url?: string | null;
+  source?: ItemSource | null;
+  // Display name of a hidden-account user the item was forwarded from.
+  source_name?: string | null;
+  // Short LLM summary of long text/document items, when summarization is enabled.
+  summary?: string | null;
+  // OpenGraph preview of web link items (type 'link').
+  link?: LinkPreview | null;
+  // Whether the item was forwarded (and from what) or sent directly; absent on older items.
+  source_kind?: 'forward_channel' | 'forward_user' | 'forward_hidden' | 'self' | null;
+  // Detected language (ISO 639-3, e.g. 'eng', 'jpn') when language detection is enabled.
+  lang?: string | null;
+  // Telegram coordinates of the archived message, as strings to keep full precision.
+  tg_chat_id?: string | null;
+  tg_message_id?: string | null;
   tg_group_id?: string | null;
   group_items?: Item[];
   tags?: number[];
   tag_objects?: Tag[];
+  is_pinned?: boolean;
+  visibility?: 'private' | 'public';
+  note?: string | null;
+}
+
+export interface Storyboard {
+  frames: number;
+  interval: number;
+  columns: number;
+  rows: number;
+  tile_width: number;
+}
+
+export interface ItemSource {
+  id: string; // BIGINT as string for JS safety
+  name: string;
+  username: string | null;
+  avatar_url: string | null;
+}
+
+export interface LinkPreview {
+  url: string;
+  title?: string | null;
+  description?: string | null;
+  site_name?: string | null;
 }
 
 export interface Tag {
@@ -24,6 +66,8 @@ export interface Tag {
   label?: string | null;
   asset_url?: string | null;
   asset_mime?: string | null;
+  // Number of items carrying this tag (omitted when fetched with with_counts=false).
+  count?: number;
 }
 
 export interface ItemDetail extends Item {
@@ -32,6 +76,23 @@ export interface ItemDetail extends Item {
   processed_at: string | null;
   meta: Record<string, any>;
   tags: number[];
+  processing_state?: 'pending' | 'processing' | 'completed';
+}
+
+export interface PendingTask {
+  id: string;
+  status: 'pending' | 'processing' | 'failed';
+  bot_chat_id: string;
+  bot_message_id: string;
+  source_chat_id: string | null;
+  source_message_id: string | null;
+  item_id: string | null;
+  item_type: string | null;
+  tg_group_id: string | null;
+  retry_count: number;
+  error_code: string | null;
+  created_at: string | null;
+  updated_at: string | null;
 }
 
 export interface Entity {
@@ -41,6 +102,7 @@ export interface Entity {
   type: string;
   avatar_url: string | null;
   updated_at?: string | null;
+  muted?: boolean;
 }
 
 export interface EntitiesPageResponse {
@@ -51,7 +113,7 @@ export interface EntitiesPageResponse {
 
 export interface ListResponse {
   items: Item[];
-  next_cursor: number | null;
+  next_cursor: string | null;
 }
 
 type InflightJsonEntry<T> = {
@@ -131,16 +193,19 @@ function sharedGetJson<T>(url: string, consumerSignal?: AbortSignal): Promise<T>
 }
 
 export async function fetchItems(
-  cursor?: number | null,
+  cursor?: string | null,
   mode: 'timeline' | 'random' = 'timeline',
-  entity_id?: string | null,
+  entity_id?: string | string[] | null,
   tag_id?: number | null,
   signal?: AbortSignal
 ): Promise<ListResponse> {
   const params = new URLSearchParams();
-  if (cursor) params.append('cursor', cursor.toString());
+  if (cursor) params.append('cursor', cursor);
   if (mode) params.append('mode', mode);
-  if (entity_id) params.append('entity_id', entity_id);
+  // Several entity ids combine their items into one timeline.
+  for (const id of Array.isArray(entity_id) ? entity_id : entity_id ? [entity_id] : []) {
+    params.append('entity_id', id);
+  }
   if (tag_id) params.append('tag_id', tag_id.toString());
   
   const url = `/api/v1/items?${params.toString()}`;
@@ -152,7 +217,47 @@ export async function fetchItems(
   }
 }
 
-export async function fetchItemDetail(id: number): Promise<ItemDetail> {
+export async function fetchItemsBatch(ids: string[], signal?: AbortSignal): Promise<Item[]> {
+  const res = await fetch('/api/v1/items/batch', {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ ids }),
+    signal,
+  });
+  if (!res.ok) throw new Error('Failed to fetch items');
+  const data = (await res.json()) as { items?: Item[] };
+  return data.items || [];
+}
+
+// A random item for "surprise me"; null when nothing matches the filters.
+export async function fetchRandomItem(
+  type?: string | null,
+  tag_id?: number | null,
+  signal?: AbortSignal
+): Promise<Item | null> {
+  const params = new URLSearchParams();
+  if (type) params.append('type', type);
+  if (tag_id) params.append('tag_id', tag_id.toString());
+  const res = await fetch(`/api/v1/items/random?${params.toString()}`, { signal });
+  if (res.status === 404) return null;
+  if (!res.ok) throw new Error('Failed to fetch random item');
+  const data = (await res.json()) as { item?: Item | null };
+  return data.item ?? null;
+}
+
+export interface DuplicateItem extends Item {
+  phash_distance: number;
+}
+
+// Near-duplicate images (re-compressed or resized copies) of an item, closest first.
+export async function fetchItemDuplicates(id: string, signal?: AbortSignal): Promise<DuplicateItem[]> {
+  const res = await fetch(`/api/v1/items/${id}/duplicates`, { signal });
+  if (!res.ok) throw new Error('Failed to fetch duplicates');
+  const data = (await res.json()) as { items?: DuplicateItem[] };
+  return data.items || [];
+}
+
+export async function fetchItemDetail(id: string): Promise<ItemDetail> {
   const url = `/api/v1/items/${id}`;
   try {
     return await sharedGetJson<ItemDetail>(url);
@@ -161,11 +266,38 @@ export async function fetchItemDetail(id: number): Promise<ItemDetail> {
   }
 }
 
-export async function deleteItem(id: number): Promise<void> {
+export async function deleteItem(id: string): Promise<void> {
   const res = await fetch(`/api/v1/items/${id}`, { method: 'DELETE' });
   if (!res.ok) throw new Error('Failed to delete item');
 }
 
+export interface DeletePreview {
+  dry_run: true;
+  item: { id: string; type: string };
+  s3_keys: string[];
+  // Entities whose last item this is; they are removed together with it.
+  entities_removed: string[];
+  tasks_removed: number;
+}
+
+// What deleteItem would remove, without changing anything.
+export async function previewDeleteItem(id: string, signal?: AbortSignal): Promise<DeletePreview> {
+  const res = await fetch(`/api/v1/items/${id}?dry_run=true`, { method: 'DELETE', signal });
+  if (!res.ok) throw new Error('Failed to preview deletion');
+  return (await res.json()) as DeletePreview;
+}
+
+export async function fetchPendingTasks(includeFailed = false, signal?: AbortSignal): Promise<PendingTask[]> {
+  const url = includeFailed ? '/api/v1/pending?include_failed=true' : '/api/v1/pending';
+  try {
+    const data = await sharedGetJson<{ tasks?: PendingTask[] }>(url, signal);
+    return data.tasks || [];
+  } catch (e) {
+    if (isAbortError(e)) throw e;
+    throw new Error('Failed to fetch pending tasks');
+  }
+}
+
 export async function fetchEntitiesPage(
   cursor?: string | null,
   limit: number = 10,
@@ -184,6 +316,33 @@ export async function fetchEntitiesPage(
   }
 }
 
+export async function setEntityMuted(id: string, muted: boolean, token: string, signal?: AbortSignal): Promise<void> {
+  const res = await fetch(`/api/v1/entities/${id}`, {
+    method: 'PATCH',
+    headers: { 'Content-Type': 'application/json', Authorization: `Bearer ${token}` },
+    body: JSON.stringify({ muted }),
+    signal,
+  });
+  if (!res.ok) throw new Error('Failed to update entity');
+}
+
+// Manually set an entity's avatar (max 2 MB); the image bytes are sent as the raw request body.
+export async function uploadEntityAvatar(
+  id: string,
+  image: Blob,
+  token: string,
+  signal?: AbortSignal
+): Promise<{ avatar_url: string | null }> {
+  const res = await fetch(`/api/v1/entities/${id}/avatar`, {
+    method: 'POST',
+    headers: { 'Content-Type': image.type || 'application/octet-stream', Authorization: `Bearer ${token}` },
+    body: image,
+    signal,
+  });
+  if (!res.ok) throw new Error('Failed to upload avatar');
+  return res.json();
+}
+
 // Back-compat helper: returns a flat list. Prefer fetchEntitiesPage() for pagination.
 export async function fetchEntities(signal?: AbortSignal): Promise<Entity[]> {
   const page = await fetchEntitiesPage(null, 1000, signal);
@@ -193,12 +352,24 @@ export async function fetchEntities(signal?: AbortSignal): Promise<Entity[]> {
 export interface SearchResponse {
   items: Item[];
   total: number;
+  // Recall channels that were unavailable (e.g. CLIP down); results may be incomplete.
+  warnings?: string[];
 }
 
-export async function searchItems(query: string, type?: string, signal?: AbortSignal): Promise<SearchResponse> {
+// How the full-text channel interprets the query; 'web' accepts quotes, "or" and -exclusions,
+// 'raw' accepts tsquery syntax such as foo:* prefixes and & | ! operators.
+export type SearchMatch = 'web' | 'phrase' | 'any' | 'all' | 'raw';
+
+export async function searchItems(
+  query: string,
+  type?: string,
+  signal?: AbortSignal,
+  match?: SearchMatch
+): Promise<SearchResponse> {
   const params = new URLSearchParams();
   params.append('q', query);
   if (type) params.append('type', type);
+  if (match) params.append('match', match);
   
   const url = `/api/v1/search?${params.toString()}`;
   try {
@@ -209,6 +380,36 @@ export async function searchItems(query: string, type?: string, signal?: AbortSi
   }
 }
 
+// Search by the combined visual signature of up to 8 reference images.
+export async function searchByImages(imageUrls: string[], type?: string, signal?: AbortSignal): Promise<SearchResponse> {
+  const params = new URLSearchParams();
+  for (const url of imageUrls) params.append('image_url', url);
+  if (type) params.append('type', type);
+
+  const url = `/api/v1/search?${params.toString()}`;
+  try {
+    return await sharedGetJson<SearchResponse>(url, signal);
+  } catch (e) {
+    if (isAbortError(e)) throw e;
+    throw new Error('Failed to search by images');
+  }
+}
+
+export interface ItemTypeCount {
+  item_type: string;
+  count: number;
+}
+
+export async function fetchItemTypes(signal?: AbortSignal): Promise<ItemTypeCount[]> {
+  try {
+    const data = await sharedGetJson<{ types?: ItemTypeCount[] }>('/api/v1/item-types', signal);
+    return data.types || [];
+  } catch (e) {
+    if (isAbortError(e)) throw e;
+    throw new Error('Failed to fetch item types');
+  }
+}
+
 export async function fetchTags(signal?: AbortSignal): Promise<Tag[]> {
   try {
     const data = await sharedGetJson<{ tags?: Tag[] }>('/api/v1/tags', signal);
@@ -243,7 +444,303 @@ export async function updateTagLabel(id: number, label: string | null, signal?:
   if (!res.ok) throw new Error('Failed to update tag');
 }
 
+export async function setItemPinned(id: string, pinned: boolean, signal?: AbortSignal): Promise<void> {
+  const res = await fetch(`/api/v1/items/${id}/${pinned ? 'pin' : 'unpin'}`, { method: 'POST', signal });
+  if (!res.ok) throw new Error('Failed to update pin state');
+}
+
+export async function setItemVisibility(
+  id: string,
+  visibility: 'private' | 'public',
+  signal?: AbortSignal
+): Promise<void> {
+  const res = await fetch(`/api/v1/items/${id}/visibility`, {
+    method: 'PATCH',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ visibility }),
+    signal,
+  });
+  if (!res.ok) throw new Error('Failed to update visibility');
+}
+
+// Pass null (or an empty string) to clear the note.
+export async function setItemNote(id: string, note: string | null, signal?: AbortSignal): Promise<void> {
+  const res = await fetch(`/api/v1/items/${id}/note`, {
+    method: 'PATCH',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ note }),
+    signal,
+  });
+  if (!res.ok) throw new Error('Failed to update note');
+}
+
 export async function deleteTag(id: number, signal?: AbortSignal): Promise<void> {
   const res = await fetch(`/api/v1/tags/${id}`, { method: 'DELETE', signal });
   if (!res.ok) throw new Error('Failed to delete tag');
 }
+
+async function bulkTagItems(
+  tagId: number,
+  action: 'apply' | 'remove',
+  itemIds: string[],
+  signal?: AbortSignal
+): Promise<number> {
+  const res = await fetch(`/api/v1/tags/${tagId}/${action}`, {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ item_ids: itemIds }),
+    signal,
+  });
+  if (!res.ok) throw new Error(`Failed to ${action} tag`);
+  const data = await res.json();
+  return data.affected ?? 0;
+}
+
+// Resolves to the number of items whose tags actually changed (album members included).
+export async function applyTagToItems(tagId: number, itemIds: string[], signal?: AbortSignal): Promise<number> {
+  return bulkTagItems(tagId, 'apply', itemIds, signal);
+}
+
+export async function removeTagFromItems(tagId: number, itemIds: string[], signal?: AbortSignal): Promise<number> {
+  return bulkTagItems(tagId, 'remove', itemIds, signal);
+}
+
+export interface Collection {
+  id: string;
+  name: string;
+  description?: string | null;
+  cover_item_id?: string | null;
+  cover_url?: string | null;
+  item_count: number;
+  created_at?: string;
+  updated_at?: string;
+}
+
+export async function fetchCollections(signal?: AbortSignal): Promise<Collection[]> {
+  const res = await fetch('/api/v1/collections', { signal });
+  if (!res.ok) throw new Error('Failed to fetch collections');
+  const data = (await res.json()) as { collections?: Collection[] };
+  return data.collections || [];
+}
+
+export async function createCollection(
+  payload: { name: string; description?: string | null },
+  token: string,
+  signal?: AbortSignal
+): Promise<{ id: string }> {
+  const res = await fetch('/api/v1/collections', {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json', Authorization: `Bearer ${token}` },
+    body: JSON.stringify(payload),
+    signal,
+  });
+  if (!res.ok) throw new Error('Failed to create collection');
+  return res.json();
+}
+
+// cover_item_id must be a member of the collection; null falls back to the first member.
+export async function updateCollection(
+  id: string,
+  payload: { name?: string; description?: string | null; cover_item_id?: string | null },
+  token: string,
+  signal?: AbortSignal
+): Promise<void> {
+  const res = await fetch(`/api/v1/collections/${id}`, {
+    method: 'PATCH',
+    headers: { 'Content-Type': 'application/json', Authorization: `Bearer ${token}` },
+    body: JSON.stringify(payload),
+    signal,
+  });
+  if (!res.ok) throw new Error('Failed to update collection');
+}
+
+export async function deleteCollection(id: string, token: string, signal?: AbortSignal): Promise<void> {
+  const res = await fetch(`/api/v1/collections/${id}`, {
+    method: 'DELETE',
+    headers: { Authorization: `Bearer ${token}` },
+    signal,
+  });
+  if (!res.ok) throw new Error('Failed to delete collection');
+}
+
+export interface CollectionItem extends Item {
+  position: number;
+}
+
+export interface CollectionItemsResponse {
+  items: CollectionItem[];
+  next_cursor?: number | null;
+}
+
+// Members in collection order; pass the previous next_cursor to fetch the following page.
+export async function fetchCollectionItems(
+  id: string,
+  cursor?: number | null,
+  signal?: AbortSignal
+): Promise<CollectionItemsResponse> {
+  const params = new URLSearchParams();
+  if (cursor) params.append('cursor', cursor.toString());
+  const res = await fetch(`/api/v1/collections/${id}/items?${params.toString()}`, { signal });
+  if (!res.ok) throw new Error('Failed to fetch collection items');
+  return res.json();
+}
+
+export async function addCollectionItems(
+  id: string,
+  itemIds: string[],
+  token: string,
+  signal?: AbortSignal
+): Promise<number> {
+  const res = await fetch(`/api/v1/collections/${id}/items`, {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json', Authorization: `Bearer ${token}` },
+    body: JSON.stringify({ item_ids: itemIds }),
+    signal,
+  });
+  if (!res.ok) throw new Error('Failed to add items to collection');
+  const data = (await res.json()) as { added?: number };
+  return data.added ?? 0;
+}
+
+// Moves the given members to the front in this order; pass a single id to pin it to the top.
+export async function reorderCollectionItems(
+  id: string,
+  itemIds: string[],
+  token: string,
+  signal?: AbortSignal
+): Promise<void> {
+  const res = await fetch(`/api/v1/collections/${id}/items/order`, {
+    method: 'PUT',
+    headers: { 'Content-Type': 'application/json', Authorization: `Bearer ${token}` },
+    body: JSON.stringify({ item_ids: itemIds }),
+    signal,
+  });
+  if (!res.ok) throw new Error('Failed to reorder collection');
+}
+
+export async function removeCollectionItem(
+  id: string,
+  itemId: string,
+  token: string,
+  signal?: AbortSignal
+): Promise<void> {
+  const res = await fetch(`/api/v1/collections/${id}/items/${itemId}`, {
+    method: 'DELETE',
+    headers: { Authorization: `Bearer ${token}` },
+    signal,
+  });
+  if (!res.ok) throw new Error('Failed to remove item from collection');
+}
+
+export interface PresignedUpload {
+  key: string;
+  url: string;
+  method: 'PUT';
+  expires_in: number;
+}
+
+export async function presignUpload(filename: string, token: string, signal?: AbortSignal): Promise<PresignedUpload> {
+  const res = await fetch('/api/v1/uploads/presign', {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json', Authorization: `Bearer ${token}` },
+    body: JSON.stringify({ filename }),
+    signal,
+  });
+  if (!res.ok) throw new Error('Failed to presign upload');
+  return res.json();
+}
+
+export async function createItemFromUpload(
+  payload: { s3_key: string; type: string; content_text?: string | null },
+  token: string,
+  signal?: AbortSignal
+): Promise<{ task_id: string }> {
+  const res = await fetch('/api/v1/items', {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json', Authorization: `Bearer ${token}` },
+    body: JSON.stringify(payload),
+    signal,
+  });
+  if (!res.ok) throw new Error('Failed to create item');
+  return res.json();
+}
+
+// Saves a web page as a 'link' item; the worker fetches the page preview asynchronously.
+export async function createLinkItem(
+  payload: { url: string; content_text?: string | null },
+  token: string,
+  signal?: AbortSignal
+): Promise<{ task_id: string }> {
+  const res = await fetch('/api/v1/items', {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json', Authorization: `Bearer ${token}` },
+    body: JSON.stringify(payload),
+    signal,
+  });
+  if (!res.ok) throw new Error('Failed to save link');
+  return res.json();
+}
+
+export interface RelatedEntity {
+  entity: ItemSource;
+  count: number;
+}
+
+export async function fetchRelatedEntities(
+  entityId: string,
+  limit: number = 10,
+  signal?: AbortSignal
+): Promise<RelatedEntity[]> {
+  const params = new URLSearchParams();
+  params.append('limit', String(limit));
+  const url = `/api/v1/entities/${entityId}/related?${params.toString()}`;
+  try {
+    const data = await sharedGetJson<{ related?: RelatedEntity[] }>(url, signal);
+    return data.related || [];
+  } catch (e) {
+    if (isAbortError(e)) throw e;
+    throw new Error('Failed to fetch related entities');
+  }
+}
+
+export interface EntityTagStat {
+  tag: Tag;
+  count: number;
+}
+
+// Tags applied to an entity's items, most used first.
+export async function fetchEntityTagStats(entityId: string, signal?: AbortSignal): Promise<EntityTagStat[]> {
+  const url = `/api/v1/entities/${entityId}/tags`;
+  try {
+    const data = await sharedGetJson<{ tags?: EntityTagStat[] }>(url, signal);
+    return data.tags || [];
+  } catch (e) {
+    if (isAbortError(e)) throw e;
+    throw new Error('Failed to fetch entity tag stats');
+  }
+}
+
+export interface TimelineDay {
+  date: string; // YYYY-MM-DD (UTC)
+  count: number;
+}
+
+// Daily item counts for an entity; days without items are included with count 0.
+export async function fetchEntityTimeline(
+  entityId: string,
+  from?: string,
+  to?: string,
+  signal?: AbortSignal
+): Promise<TimelineDay[]> {
+  const params = new URLSearchParams();
+  if (from) params.append('from', from);
+  if (to) params.append('to', to);
+  const url = `/api/v1/entities/${entityId}/timeline?${params.toString()}`;
+  try {
+    const data = await sharedGetJson<{ days?: TimelineDay[] }>(url, signal);
+    return data.days || [];
+  } catch (e) {
+    if (isAbortError(e)) throw e;
+    throw new Error('Failed to fetch entity timeline');
+  }
+}
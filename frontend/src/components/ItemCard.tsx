@@ -8,16 +8,17 @@ import { TagIcon } from './TagIcon';
 interface Props {
   item: Item;
   onClick: (item: Item, opts?: { startIndex?: number }) => void;
-  onDeleted?: (id: number) => void;
+  onDeleted?: (id: string) => void;
 }
 
 const ANIMATION_DURATION = 300;
 
 const globalLoadedImageUrls = new Set<string>();
-const albumIndexByItemId = new Map<number, number>();
+const albumIndexByItemId = new Map<string, number>();
 
 function getCardPreviewUrl(it: Item): string | null {
   if (it.type === 'image') return it.thumbnail_url || it.s3_url;
+  if (it.type === 'link') return it.thumbnail_url || null;
   if (it.type === 'video') return it.thumbnail_url || null;
   return null;
 }
@@ -155,7 +156,7 @@ export const ItemCard: React.FC<Props> = ({ item, onClick, onDeleted }) => {
           className="item-media"
           style={aspectRatio ? { aspectRatio: `${aspectRatio}` } : undefined}
         >
-          {(displayItem.type === 'image' || displayItem.type === 'video') ? (
+          {(displayItem.type === 'image' || displayItem.type === 'video' || displayItem.type === 'link') ? (
             <div className="album-slider">
               <div
                 className="album-track"
@@ -172,7 +173,7 @@ export const ItemCard: React.FC<Props> = ({ item, onClick, onDeleted }) => {
 
                   return (
                     <div key={it.id} className="album-slide">
-                      {it.type === 'image' && previewUrl ? (
+                      {(it.type === 'image' || it.type === 'link') && previewUrl ? (
                         <>
                           <img
                             src={previewUrl}
@@ -287,6 +288,9 @@ export const ItemCard: React.FC<Props> = ({ item, onClick, onDeleted }) => {
       )}
 
       <div className="item-content">
+        {displayItem.type === 'link' && displayItem.link?.title && (
+          <p className="text-title">{displayItem.link.title}</p>
+        )}
         {displayItem.type !== 'text' && displayItem.content && <p className="text-title">{displayItem.content}</p>}
         {!!item.tag_objects?.length && (
           <div className="item-tags" onClick={(e) => e.stopPropagation()}>
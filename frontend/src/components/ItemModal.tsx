@@ -6,11 +6,11 @@ import { ConfirmModal } from './ConfirmModal';
 import { TagIcon } from './TagIcon';
 
 interface Props {
-  itemId: number;
+  itemId: string;
   groupItems?: Item[];
   startIndex?: number;
   onClose: () => void;
-  onDeleted: (id: number) => void;
+  onDeleted: (id: string) => void;
 }
 
 const ANIMATION_DURATION = 300;
@@ -32,7 +32,7 @@ function markImageLoaded(url: string | null | undefined, setLoadedImages: React.
 
 export const ItemModal: React.FC<Props> = ({ itemId, groupItems, startIndex, onClose, onDeleted }) => {
   // 所有组图项的详情缓存
-  const [detailsCache, setDetailsCache] = useState<Map<number, ItemDetail>>(new Map());
+  const [detailsCache, setDetailsCache] = useState<Map<string, ItemDetail>>(new Map());
   const [loading, setLoading] = useState(true);
   const [showConfirm, setShowConfirm] = useState(false);
 
@@ -79,7 +79,7 @@ export const ItemModal: React.FC<Props> = ({ itemId, groupItems, startIndex, onC
           })
       )
     ).then((results) => {
-      const newCache = new Map<number, ItemDetail>();
+      const newCache = new Map<string, ItemDetail>();
       results.forEach((r) => {
         if (r && r.detail) {
           newCache.set(r.id, r.detail);
@@ -216,7 +216,11 @@ export const ItemModal: React.FC<Props> = ({ itemId, groupItems, startIndex, onC
         >
           {(album ? album : [detail]).map((it: any) => {
             const type = (it.type ?? detail.type) as string;
-            const url = (it.s3_url ?? detail.s3_url) as string | null | undefined;
+            // Without stored originals (STORE_ORIGINALS=false) images fall back to their thumbnail.
+            const url = (it.s3_url ?? detail.s3_url ?? (type === 'image' ? it.thumbnail_url : null)) as
+              | string
+              | null
+              | undefined;
 
             const isLoaded = url
               ? (loadedImages.has(url) || globalLoadedImageUrls.has(url))
@@ -46,7 +46,7 @@ function isValidItem(value: unknown): value is Item {
   return (
     typeof value === 'object' &&
     value !== null &&
-    typeof (value as any).id === 'number'
+    typeof (value as any).id === 'string'
   );
 }
 
@@ -55,7 +55,7 @@ interface MasonryGridProps {
   columnCount?: number;
   layoutKey?: string | number;
   onItemClick: (item: Item, opts?: { startIndex?: number }) => void;
-  onItemDelete?: (id: number) => void;
+  onItemDelete?: (id: string) => void;
   loading: boolean;
   hasMore: boolean;
   onLoadMore: () => void;
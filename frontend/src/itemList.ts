@@ -1,7 +1,7 @@
 import { Item } from './api';
 
 export function uniqueItemsById(items: Item[]): Item[] {
-  const seen = new Set<number>();
+  const seen = new Set<string>();
   const out: Item[] = [];
 
   for (const item of items) {
@@ -17,7 +17,7 @@ export function mergeUniqueItemsById(prev: Item[], next: Item[]): Item[] {
   if (prev.length === 0) return uniqueItemsById(next);
   if (next.length === 0) return prev;
 
-  const seen = new Set<number>();
+  const seen = new Set<string>();
   const out: Item[] = [];
 
   for (const item of prev) {
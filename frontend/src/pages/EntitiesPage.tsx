@@ -95,10 +95,10 @@ export const EntitiesPage: React.FC = () => {
     const [entitiesLoading, setEntitiesLoading] = useState(false);
     const [selectedEntityId, setSelectedEntityId] = useState<string | null>(null);
     const [items, setItems] = useState<Item[]>([]);
-    const [cursor, setCursor] = useState<number | null>(null);
+    const [cursor, setCursor] = useState<string | null>(null);
     const [loading, setLoading] = useState(false);
     const [selected, setSelected] = useState<{
-        itemId: number;
+        itemId: string;
         groupItems?: Item[];
         startIndex?: number;
     } | null>(null);
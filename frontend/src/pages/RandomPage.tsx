@@ -9,7 +9,7 @@ export const RandomPage: React.FC = () => {
     const [loading, setLoading] = useState(false);
     const [hasMore, setHasMore] = useState(true);
     const [selected, setSelected] = useState<{
-        itemId: number;
+        itemId: string;
         groupItems?: Item[];
         startIndex?: number;
     } | null>(null);
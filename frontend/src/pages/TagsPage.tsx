@@ -154,10 +154,10 @@ export const TagsPage: React.FC = () => {
   const [selectedTagId, setSelectedTagId] = useState<number | null>(null);
 
   const [items, setItems] = useState<Item[]>([]);
-  const [cursor, setCursor] = useState<number | null>(null);
+  const [cursor, setCursor] = useState<string | null>(null);
   const [itemsLoading, setItemsLoading] = useState(false);
   const [selected, setSelected] = useState<{
-    itemId: number;
+    itemId: string;
     groupItems?: Item[];
     startIndex?: number;
   } | null>(null);
@@ -11,10 +11,10 @@ export const TimelinePage: React.FC = () => {
     const query = searchParams.get('search');
     
     const [items, setItems] = useState<Item[]>([]);
-    const [cursor, setCursor] = useState<number | null>(null);
+    const [cursor, setCursor] = useState<string | null>(null);
     const [loading, setLoading] = useState(false);
     const [selected, setSelected] = useState<{
-        itemId: number;
+        itemId: string;
         groupItems?: Item[];
         startIndex?: number;
     } | null>(null);